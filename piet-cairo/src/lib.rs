@@ -6,12 +6,12 @@ mod text;
 
 use std::borrow::Cow;
 
-use cairo::{Context, Filter, Format, ImageSurface, Matrix, SurfacePattern};
+use cairo::{Context, Filter, Format, Glyph, ImageSurface, Matrix, SurfacePattern};
 
 use piet::kurbo::{Affine, PathEl, Point, QuadBez, Rect, Shape, Size};
 use piet::{
     Color, Error, FixedGradient, Image, ImageFormat, InterpolationMode, IntoBrush, LineCap,
-    LineJoin, RenderContext, StrokeStyle, TextLayout,
+    LineJoin, RenderContext, StrokeStyle, TextLayout, UnderlineStyle,
 };
 
 pub use crate::text::{CairoText, CairoTextLayout, CairoTextLayoutBuilder};
@@ -151,16 +151,20 @@ impl<'a> RenderContext for CairoRenderContext<'a> {
     }
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        self.draw_layout(layout, pos.into(), None);
+    }
+
+    fn draw_text_with_brush(
+        &mut self,
+        layout: &Self::TextLayout,
+        pos: impl Into<Point>,
+        brush: &impl IntoBrush<Self>,
+    ) -> Result<(), Error> {
         let pos = pos.into();
         let rect = layout.image_bounds() + pos.to_vec2();
-        let brush = layout.fg_color.make_brush(self, || rect);
-        self.ctx.set_scaled_font(&layout.font);
-        self.set_brush(&*brush);
-
-        for lm in &layout.line_metrics {
-            self.ctx.move_to(pos.x, pos.y + lm.y_offset + lm.baseline);
-            self.ctx.show_text(&layout.text[lm.range()]);
-        }
+        let brush = brush.make_brush(self, || rect).into_owned();
+        self.draw_layout(layout, pos, Some(&brush));
+        Ok(())
     }
 
     fn save(&mut self) -> Result<(), Error> {
@@ -328,13 +332,21 @@ impl Image for CairoImage {
 impl<'a> CairoRenderContext<'a> {
     /// Create a new Cairo back-end.
     ///
-    /// At the moment, it uses the "toy text API" for text layout, but when
-    /// we change to a more sophisticated text layout approach, we'll probably
-    /// need a factory for that as an additional argument.
+    /// Fonts are found with fontconfig and text is shaped with HarfBuzz.
     pub fn new(ctx: &Context) -> CairoRenderContext {
+        CairoRenderContext::with_text(ctx, CairoText::new())
+    }
+
+    /// Create a render context that shares an existing text factory.
+    ///
+    /// Fonts loaded through a [`CairoText`] are only available to layouts
+    /// created by that factory (or its clones), and it caches the system
+    /// fonts it has used, so applications should keep a factory around and
+    /// pass it in here.
+    pub fn with_text(ctx: &Context, text: CairoText) -> CairoRenderContext {
         CairoRenderContext {
             ctx,
-            text: CairoText::new(),
+            text,
             transform_stack: Vec::new(),
         }
     }
@@ -410,6 +422,146 @@ impl<'a> CairoRenderContext<'a> {
         }
     }
 
+    /// Draw a layout, with `brush` in place of its text colors if given.
+    fn draw_layout(&mut self, layout: &CairoTextLayout, pos: Point, brush: Option<&Brush>) {
+        let rect = layout.image_bounds() + pos.to_vec2();
+
+        // backgrounds go under all of the text, so that they don't cover the
+        // overhang of glyphs on the line above.
+        for (range, run) in layout.runs.slices(0..layout.text.len()) {
+            if let Some(color) = &run.background {
+                let brush = color.make_brush(self, || rect);
+                self.set_brush(&*brush);
+                for bg_rect in layout.rects_for_range(range) {
+                    let bg_rect = bg_rect + pos.to_vec2();
+                    self.ctx
+                        .rectangle(bg_rect.x0, bg_rect.y0, bg_rect.width(), bg_rect.height());
+                }
+                self.ctx.fill();
+            }
+        }
+
+        for (line_num, lm) in layout.line_metrics.iter().enumerate() {
+            let line = layout.line(line_num);
+            let y = pos.y + lm.y_offset + lm.baseline;
+            for slice in line.slices() {
+                let run = slice.run;
+                let y = y - run.baseline_offset;
+                match brush {
+                    Some(brush) => self.set_brush(brush),
+                    None => {
+                        let brush = run.fg_color.make_brush(self, || rect);
+                        self.set_brush(&*brush);
+                    }
+                }
+                self.ctx.set_scaled_font(&run.font);
+
+                let glyphs: Vec<Glyph> = line
+                    .glyphs(slice)
+                    .map(|(x, glyph)| Glyph {
+                        index: glyph.id.into(),
+                        x: pos.x + x + glyph.x_offset,
+                        y: y - glyph.y_offset,
+                    })
+                    .collect();
+                self.ctx.show_glyphs(&glyphs);
+
+                let decorations = &run.decorations;
+                if !decorations.is_empty() {
+                    if let Some(color) = &decorations.color {
+                        let brush = color.make_brush(self, || rect);
+                        self.set_brush(&*brush);
+                    }
+                    let x0 = pos.x + slice.x;
+                    let metrics = run.decoration_metrics();
+                    let mut add_line = |style: UnderlineStyle, offset: f64| {
+                        self.draw_decoration(style, x0, y + offset, slice.width, metrics.thickness)
+                    };
+                    if let Some(style) = decorations.underline {
+                        add_line(style, metrics.underline_offset);
+                    }
+                    if decorations.strikethrough {
+                        add_line(UnderlineStyle::Single, metrics.strikethrough_offset);
+                    }
+                    if decorations.overline {
+                        add_line(UnderlineStyle::Single, metrics.overline_offset);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw a decoration line in the current source, centered vertically
+    /// on `y`.
+    fn draw_decoration(
+        &mut self,
+        style: UnderlineStyle,
+        x0: f64,
+        y: f64,
+        width: f64,
+        thickness: f64,
+    ) {
+        let ctx = self.ctx;
+        // the line styles and the wavy line's clip mustn't leak into later
+        // drawing
+        ctx.save();
+        match style {
+            UnderlineStyle::Single => {
+                ctx.rectangle(x0, y - thickness / 2.0, width, thickness);
+                ctx.fill();
+            }
+            UnderlineStyle::Double => {
+                // two lines, a line's width apart
+                for &center in &[y - thickness, y + thickness] {
+                    ctx.rectangle(x0, center - thickness / 2.0, width, thickness);
+                }
+                ctx.fill();
+            }
+            UnderlineStyle::Dotted | UnderlineStyle::Dashed => {
+                if style == UnderlineStyle::Dotted {
+                    ctx.set_line_cap(cairo::LineCap::Round);
+                    ctx.set_dash(&[0.0, 2.0 * thickness], 0.0);
+                } else {
+                    ctx.set_line_cap(cairo::LineCap::Butt);
+                    ctx.set_dash(&[3.0 * thickness, 2.0 * thickness], 0.0);
+                }
+                ctx.set_line_width(thickness);
+                ctx.move_to(x0, y);
+                ctx.line_to(x0 + width, y);
+                ctx.stroke();
+            }
+            UnderlineStyle::Wavy => {
+                let amplitude = thickness.max(1.0);
+                let half_wave = 3.0 * amplitude;
+                // a cubic with both control points at 4/3 of the amplitude
+                // peaks at the amplitude.
+                let control = amplitude * 4.0 / 3.0;
+                ctx.rectangle(x0, y - 2.0 * amplitude, width, 4.0 * amplitude);
+                ctx.clip();
+                ctx.set_line_width(thickness);
+                ctx.set_line_cap(cairo::LineCap::Butt);
+                ctx.move_to(x0, y);
+                let mut x = x0;
+                let mut sign = -1.0;
+                while x < x0 + width {
+                    let dy = sign * control;
+                    ctx.curve_to(
+                        x + half_wave / 3.0,
+                        y + dy,
+                        x + half_wave * 2.0 / 3.0,
+                        y + dy,
+                        x + half_wave,
+                        y,
+                    );
+                    x += half_wave;
+                    sign = -sign;
+                }
+                ctx.stroke();
+            }
+        }
+        ctx.restore();
+    }
+
     fn draw_image_inner(
         &mut self,
         image: &ImageSurface,
@@ -494,3 +646,272 @@ fn compute_blurred_rect(rect: Rect, radius: f64) -> (ImageSurface, Point) {
     let origin = rect_exp.origin();
     (image, origin)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::{
+        FontFamily, LinearGradient, Text, TextAlignment, TextAttribute, TextLayoutBuilder,
+        UnitPoint,
+    };
+
+    /// The pixels of an image drawn by `f`, as unpremultiplied `[r, g, b, a]`.
+    struct Pixels {
+        width: usize,
+        data: Vec<[u8; 4]>,
+    }
+
+    impl Pixels {
+        fn draw(width: i32, height: i32, f: impl FnOnce(&mut CairoRenderContext)) -> Pixels {
+            let mut surface = ImageSurface::create(Format::ARgb32, width, height).unwrap();
+            {
+                let ctx = Context::new(&surface);
+                let mut rc = CairoRenderContext::new(&ctx);
+                f(&mut rc);
+                rc.finish().unwrap();
+            }
+            let stride = surface.get_stride() as usize;
+            let data = surface.get_data().unwrap();
+            let data = data
+                .chunks(stride)
+                .flat_map(|row| row[..width as usize * 4].chunks(4))
+                .map(|px| {
+                    let argb = u32::from_ne_bytes([px[0], px[1], px[2], px[3]]);
+                    let alpha = argb >> 24;
+                    let channel = |shift: u32| match alpha {
+                        0 => 0,
+                        a => (((argb >> shift) & 255) * 255 / a) as u8,
+                    };
+                    [channel(16), channel(8), channel(0), alpha as u8]
+                })
+                .collect();
+            Pixels {
+                width: width as usize,
+                data,
+            }
+        }
+
+        fn get(&self, x: f64, y: f64) -> [u8; 4] {
+            self.data[y as usize * self.width + x as usize]
+        }
+    }
+
+    #[test]
+    fn draw_text_with_gradient() {
+        let pos = Point::new(10.0, 10.0);
+        let mut bounds = Rect::ZERO;
+        let pixels = Pixels::draw(400, 80, |rc| {
+            let layout = rc
+                .text()
+                .new_text_layout("IIIIIIII")
+                .font(FontFamily::SANS_SERIF, 40.0)
+                .build()
+                .unwrap();
+            bounds = layout.outline().bounding_box() + pos.to_vec2();
+            let gradient = LinearGradient::new(
+                UnitPoint::LEFT,
+                UnitPoint::RIGHT,
+                (Color::rgb8(255, 0, 0), Color::rgb8(0, 0, 255)),
+            );
+            rc.draw_text_with_brush(&layout, pos, &gradient).unwrap();
+        });
+
+        let y = bounds.center().y;
+        let inked: Vec<[u8; 4]> = (bounds.x0 as usize..bounds.x1 as usize)
+            .map(|x| pixels.get(x as f64, y))
+            .filter(|px| px[3] == 255)
+            .collect();
+        assert!(inked.len() > 8);
+        let (first, last) = (inked[0], inked[inked.len() - 1]);
+        assert!(first[0] > first[2], "{:?}", first);
+        assert!(last[2] > last[0], "{:?}", last);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn decorations_restore_line_style() {
+        let surface = ImageSurface::create(Format::ARgb32, 100, 40).unwrap();
+        let ctx = Context::new(&surface);
+        let line_width = ctx.get_line_width();
+        {
+            let mut rc = CairoRenderContext::new(&ctx);
+            for &style in &[UnderlineStyle::Dotted, UnderlineStyle::Dashed] {
+                let layout = rc
+                    .text()
+                    .new_text_layout("piet")
+                    .default_attribute(style)
+                    .build()
+                    .unwrap();
+                rc.draw_text(&layout, Point::new(10.0, 10.0));
+            }
+            rc.finish().unwrap();
+        }
+        assert_eq!(ctx.get_line_cap(), cairo::LineCap::Butt);
+        assert_eq!(ctx.get_line_width(), line_width);
+        assert_eq!(ctx.get_dash_count(), 0);
+    }
+
+    #[test]
+    fn stroke_text() {
+        let pos = Point::new(10.0, 10.0);
+        let mut bounds = Rect::ZERO;
+        let pixels = Pixels::draw(100, 100, |rc| {
+            let layout = rc
+                .text()
+                .new_text_layout("I")
+                .font(FontFamily::SANS_SERIF, 64.0)
+                .build()
+                .unwrap();
+            bounds = layout.outline().bounding_box() + pos.to_vec2();
+            let style = StrokeStyle::new();
+            rc.stroke_text(&layout, pos, &Color::BLACK, 1.0, &style)
+                .unwrap();
+
+            // there is nothing to stroke, but that isn't an error
+            let blank = rc.text().new_text_layout("   ").build().unwrap();
+            rc.stroke_text(&blank, pos, &Color::BLACK, 1.0, &style)
+                .unwrap();
+        });
+
+        // the stem is outlined, but not filled
+        let center = bounds.center();
+        assert_eq!(pixels.get(center.x, center.y)[3], 0);
+        let edge = (0..3)
+            .map(|dx| pixels.get(bounds.x0 - 1.0 + dx as f64, center.y)[3])
+            .max()
+            .unwrap();
+        assert!(edge > 100);
+    }
+
+    #[test]
+    fn clip_text_is_restored() {
+        let pos = Point::new(10.0, 10.0);
+        let mut bounds = Rect::ZERO;
+        let pixels = Pixels::draw(100, 100, |rc| {
+            let layout = rc
+                .text()
+                .new_text_layout("I")
+                .font(FontFamily::SANS_SERIF, 64.0)
+                .build()
+                .unwrap();
+            bounds = layout.outline().bounding_box() + pos.to_vec2();
+            rc.with_save(|rc| {
+                rc.clip_text(&layout, pos)?;
+                rc.fill(Rect::new(0.0, 0.0, 100.0, 50.0), &Color::rgb8(255, 0, 0));
+                Ok(())
+            })
+            .unwrap();
+            rc.fill(Rect::new(0.0, 50.0, 100.0, 100.0), &Color::rgb8(0, 0, 255));
+        });
+
+        // inside the save, only the glyph is painted
+        let y = bounds.y0 + 5.0;
+        assert!(y < 50.0);
+        assert_eq!(pixels.get(bounds.center().x, y), [255, 0, 0, 255]);
+        assert_eq!(pixels.get(bounds.x1 + 5.0, y)[3], 0);
+        // after the restore, nothing is clipped
+        assert_eq!(pixels.get(bounds.x1 + 5.0, 75.0), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn draw_text_range_across_lines() {
+        let mut layout = None;
+        let pixels = Pixels::draw(200, 100, |rc| {
+            let full = rc.text().new_text_layout("aaaa bbbb").build().unwrap();
+            let wrapped = rc
+                .text()
+                .new_text_layout("aaaa bbbb")
+                .max_width(full.size().width - 1.0)
+                .default_attribute(TextAttribute::Underline(true))
+                .build()
+                .unwrap();
+            rc.draw_text_range(&wrapped, 2..7, Point::ZERO, Some(Color::rgb8(255, 0, 0)))
+                .unwrap();
+            layout = Some(wrapped);
+        });
+        let layout = layout.unwrap();
+        assert_eq!(layout.line_count(), 2);
+
+        let is_red = |px: [u8; 4]| px[3] > 0 && px[0] > 200 && px[1] < 50 && px[2] < 50;
+        let inked_in = |x0: f64, x1: f64, line: usize| {
+            let lm = layout.line_metric(line).unwrap();
+            let xs = (x0.max(0.0) as usize)..(x1.min(200.0) as usize);
+            let ys = (lm.y_offset as usize)..((lm.y_offset + lm.height) as usize);
+            ys.flat_map(|y| xs.clone().map(move |x| (x, y)))
+                .map(|(x, y)| pixels.get(x as f64, y as f64))
+                .filter(|px| px[3] > 0)
+                .collect::<Vec<_>>()
+        };
+
+        // the range is drawn in the override color on both lines
+        let x2 = layout.hit_test_text_position(2).point.x;
+        let x7 = layout.hit_test_text_position(7).point.x;
+        let first = inked_in(x2 + 1.0, 200.0, 0);
+        let second = inked_in(0.0, x7 - 1.0, 1);
+        assert!(!first.is_empty() && first.iter().all(|px| is_red(*px)));
+        assert!(!second.is_empty() && second.iter().all(|px| is_red(*px)));
+        // and nothing outside it is drawn
+        assert!(inked_in(0.0, x2 - 1.0, 0).is_empty());
+        assert!(inked_in(x7 + 1.0, 200.0, 1).is_empty());
+
+        // "bb" has no descenders, so anything below its baseline is the underline
+        let lm = layout.line_metric(1).unwrap();
+        let x = x7 / 2.0;
+        let underline = ((lm.y_offset + lm.baseline) as usize + 1
+            ..(lm.y_offset + lm.height) as usize)
+            .map(|y| pixels.get(x, y as f64))
+            .any(is_red);
+        assert!(underline);
+    }
+
+    #[test]
+    fn background_rects() {
+        let red = Color::rgb8(255, 0, 0);
+        for &alignment in &[TextAlignment::End, TextAlignment::Justified] {
+            let mut layout = None;
+            let mut unaligned = None;
+            let pixels = Pixels::draw(200, 100, |rc| {
+                let short = rc.text().new_text_layout("aaaa bbbb").build().unwrap();
+                let max_width = short.size().width + 10.0;
+                // only the background is drawn
+                let aligned = rc
+                    .text()
+                    .new_text_layout("aaaa bbbb cccc")
+                    .max_width(max_width)
+                    .alignment(alignment)
+                    .default_attribute(TextAttribute::TextColor(Color::rgba8(0, 0, 0, 0)))
+                    .range_attribute(5..9, TextAttribute::BackgroundColor(red.clone()))
+                    .build()
+                    .unwrap();
+                rc.draw_text(&aligned, Point::ZERO);
+                layout = Some(aligned);
+                unaligned = Some(short);
+            });
+            let (layout, unaligned) = (layout.unwrap(), unaligned.unwrap());
+            assert_eq!(layout.line_count(), 2);
+
+            let rects = layout.rects_for_range(5..9);
+            assert_eq!(rects.len(), 1);
+            let rect = rects[0];
+            // the background moves with the aligned text
+            let x5 = unaligned.hit_test_text_position(5).point.x;
+            assert!(rect.x0 > x5 + 1.0, "{:?} {:?}", alignment, rect);
+            if alignment == TextAlignment::Justified {
+                let max_width = unaligned.size().width + 10.0;
+                assert!((rect.x1 - max_width).abs() < 1.0, "{:?}", rect);
+            }
+
+            // the whole rect is filled, and nothing else is
+            let inside = rect.inset(-1.0);
+            for y in inside.y0.ceil() as usize..inside.y1 as usize {
+                for x in inside.x0.ceil() as usize..inside.x1 as usize {
+                    assert_eq!(pixels.get(x as f64, y as f64), [255, 0, 0, 255]);
+                }
+            }
+            let y = rect.center().y;
+            assert_eq!(pixels.get(rect.x0 - 2.0, y)[3], 0);
+            assert_eq!(pixels.get(rect.x1 + 2.0, y)[3], 0);
+            assert_eq!(pixels.get(rect.center().x, rect.y1 + 2.0)[3], 0);
+        }
+    }
+}
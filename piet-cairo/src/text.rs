@@ -1,30 +1,51 @@
 //! Text functionality for Piet cairo backend
 
+mod bidi;
+mod fontconfig;
+mod fonts;
 mod grapheme;
 mod lines;
+mod runs;
+mod shaping;
 
+use std::cell::RefCell;
 use std::fmt;
-use std::ops::RangeBounds;
+use std::ops::{Range, RangeBounds};
 use std::rc::Rc;
 
-use cairo::{FontFace, FontOptions, FontSlant, FontWeight, Matrix, ScaledFont};
+use cairo::{Context, FontOptions, Format, ImageSurface, Matrix, PathSegment, ScaledFont};
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{BezPath, Point, Rect, Size};
 use piet::{
-    util, Color, Error, FontFamily, FontStyle, HitTestPoint, HitTestPosition, LineMetric, Text,
-    TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
+    util, Affinity, Error, FontFamily, Glyph, GlyphRun, HitTestPoint, HitTestPosition, LineHeight,
+    LineMetric, Text, TextAlignment, TextAttribute, TextDirection, TextLayout, TextLayoutBuilder,
+    TextOverflow, TextStorage,
 };
 
 use unicode_segmentation::UnicodeSegmentation;
 
-use self::grapheme::{get_grapheme_boundaries, point_x_in_grapheme};
-
-/// Right now, we don't need any state, as the "toy text API" treats the
-/// access to system font information as a global. This will change.
-// we use a phantom lifetime here to match the API of the d2d backend,
-// and the likely API of something with access to system font information.
+use self::bidi::BidiLevels;
+use self::fontconfig::FontQuery;
+use self::fonts::{self, LoadedFonts, ResolvedFace};
+use self::grapheme::{point_x_in_grapheme, GraphemeBoundaries};
+use self::lines::ParagraphStyle;
+use self::runs::{Elision, Line, LineAlignment, Run, Runs};
+
+/// The cairo text factory.
+///
+/// System fonts are found with fontconfig and shaped with HarfBuzz.
+///
+/// The fonts loaded with [`Text::load_font`], along with the system fonts we
+/// have used, are kept by the factory, and shared with its clones. A font
+/// loaded through one factory is not visible to any other, so applications
+/// that load custom fonts should keep a factory around and give it to each
+/// render context with [`CairoRenderContext::with_text`].
+///
+/// [`CairoRenderContext::with_text`]: struct.CairoRenderContext.html#method.with_text
 #[derive(Clone)]
-pub struct CairoText;
+pub struct CairoText {
+    loaded_fonts: Rc<RefCell<LoadedFonts>>,
+}
 
 #[derive(Clone)]
 struct CairoFont {
@@ -33,32 +54,44 @@ struct CairoFont {
 
 #[derive(Clone)]
 pub struct CairoTextLayout {
-    // we currently don't handle range attributes, so we stash the default
-    // color here and then just grab it when we draw ourselves.
-    pub(crate) fg_color: Color,
     size: Size,
+    image_bounds: Rect,
     trailing_ws_width: f64,
-    pub(crate) font: ScaledFont,
+    alignment: TextAlignment,
+    paragraph: ParagraphStyle,
+    max_lines: usize,
+    overflow: TextOverflow,
+    /// The text hidden behind an ellipsis on the last line, if any.
+    elision: Option<Elision>,
+    bidi: BidiLevels,
+    pub(crate) runs: Runs,
     pub(crate) text: Rc<dyn TextStorage>,
 
     // currently calculated on build
     pub(crate) line_metrics: Vec<LineMetric>,
+    line_alignments: Vec<LineAlignment>,
 }
 
 pub struct CairoTextLayoutBuilder {
     text: Rc<dyn TextStorage>,
+    loaded_fonts: Rc<RefCell<LoadedFonts>>,
     defaults: util::LayoutDefaults,
+    attributes: util::RangeAttributes,
+    alignment: TextAlignment,
+    direction: TextDirection,
+    paragraph: ParagraphStyle,
+    max_lines: usize,
+    overflow: TextOverflow,
     width_constraint: f64,
 }
 
 impl CairoText {
     /// Create a new factory that satisfies the piet `Text` trait.
-    ///
-    /// No state is needed for now because the current implementation is just
-    /// toy text, but that will change when proper text is implemented.
     #[allow(clippy::new_without_default)]
     pub fn new() -> CairoText {
-        CairoText
+        CairoText {
+            loaded_fonts: Default::default(),
+        }
     }
 }
 
@@ -70,14 +103,21 @@ impl Text for CairoText {
         Some(FontFamily::new_unchecked(family_name))
     }
 
-    fn load_font(&mut self, _data: &[u8]) -> Result<FontFamily, Error> {
-        Err(Error::NotSupported)
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        self.loaded_fonts.borrow_mut().add(data)
     }
 
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         CairoTextLayoutBuilder {
             defaults: util::LayoutDefaults::default(),
+            attributes: util::RangeAttributes::default(),
             text: Rc::new(text),
+            loaded_fonts: self.loaded_fonts.clone(),
+            alignment: TextAlignment::Start,
+            direction: TextDirection::Auto,
+            paragraph: ParagraphStyle::default(),
+            max_lines: usize::MAX,
+            overflow: TextOverflow::default(),
             width_constraint: f64::INFINITY,
         }
     }
@@ -96,19 +136,92 @@ impl CairoFont {
 
     #[cfg(test)]
     pub(crate) fn resolve_simple(&self, size: f64) -> ScaledFont {
-        self.resolve(size, FontSlant::Normal, FontWeight::Normal)
+        let mut loaded_fonts = LoadedFonts::default();
+        let face = self.face(&mut loaded_fonts, &util::LayoutDefaults::default());
+        scaled_font(&face, size)
     }
 
-    /// Create a ScaledFont for this family.
-    pub(crate) fn resolve(&self, size: f64, slant: FontSlant, weight: FontWeight) -> ScaledFont {
-        let font_face = FontFace::toy_create(self.family.name(), slant, weight);
-        let font_matrix = scale_matrix(size);
-        let ctm = scale_matrix(1.0);
-        let options = FontOptions::default();
-        ScaledFont::new(&font_face, &font_matrix, &ctm, &options)
+    /// The face in this family that is closest to the weight, width and style
+    /// in `attrs`; styles the family doesn't have are synthesized.
+    ///
+    /// Fonts that were loaded at runtime take precedence over system fonts
+    /// with the same family name.
+    fn face(&self, loaded_fonts: &mut LoadedFonts, attrs: &util::LayoutDefaults) -> ResolvedFace {
+        loaded_fonts.face(&self.family, FontQuery::new(attrs), &attrs.font_variations)
     }
 }
 
+fn scaled_font(face: &ResolvedFace, size: f64) -> ScaledFont {
+    let font_matrix = scale_matrix(size);
+    let ctm = scale_matrix(1.0);
+    let options = FontOptions::default();
+    options.set_variations(face.variations.as_deref());
+    ScaledFont::new(&face.face, &font_matrix, &ctm, &options)
+}
+
+fn to_bez_path(path: &cairo::Path) -> BezPath {
+    let mut bez_path = BezPath::new();
+    for segment in path.iter() {
+        match segment {
+            PathSegment::MoveTo((x, y)) => bez_path.move_to((x, y)),
+            PathSegment::LineTo((x, y)) => bez_path.line_to((x, y)),
+            PathSegment::CurveTo(p1, p2, p3) => bez_path.curve_to(p1, p2, p3),
+            PathSegment::ClosePath => bez_path.close_path(),
+        }
+    }
+    bez_path
+}
+
+/// Split an attribute run into pieces that can each be drawn with a single
+/// font, using fallback fonts for text that the requested font can't display.
+fn font_runs(
+    text: &str,
+    range: Range<usize>,
+    loaded_fonts: &mut LoadedFonts,
+    attrs: &util::LayoutDefaults,
+) -> Vec<(Range<usize>, ScaledFont)> {
+    let primary = CairoFont::new(attrs.font.clone()).face(loaded_fonts, attrs);
+    let primary_data = fonts::face_data(&primary.face);
+
+    let mut pieces: Vec<(Range<usize>, ResolvedFace)> = Vec::new();
+    for (idx, grapheme) in text[range.clone()].grapheme_indices(true) {
+        let start = range.start + idx;
+        let end = start + grapheme.len();
+        let ch = grapheme.chars().next().unwrap();
+        let is_space = ch.is_whitespace() || ch.is_control();
+        let face = match (pieces.last(), &primary_data) {
+            // whitespace sticks with the text before it, so a space between
+            // two words in a fallback font doesn't split the run.
+            (Some((_, prev)), _) if is_space => prev.clone(),
+            (_, Some(data)) if !is_space && !data.has_glyph(ch) => loaded_fonts
+                .fallback_face(
+                    &attrs.font,
+                    FontQuery::new(attrs),
+                    &attrs.font_variations,
+                    ch,
+                )
+                .unwrap_or_else(|| primary.clone()),
+            // we can't look inside toy faces, so we trust them.
+            _ => primary.clone(),
+        };
+        match pieces.last_mut() {
+            Some((prev_range, prev)) if *prev == face => prev_range.end = end,
+            _ => pieces.push((start..end, face)),
+        }
+    }
+    if pieces.is_empty() {
+        pieces.push((range, primary));
+    }
+
+    pieces
+        .into_iter()
+        .map(|(range, face)| {
+            let size = attrs.font_size * attrs.baseline_shift.scale();
+            (range, scaled_font(&face, size))
+        })
+        .collect()
+}
+
 impl fmt::Debug for CairoFont {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("CairoFont").finish()
@@ -123,8 +236,43 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
         self
     }
 
-    fn alignment(self, _alignment: piet::TextAlignment) -> Self {
-        // TextAlignment is not supported by cairo toy text.
+    fn alignment(mut self, alignment: TextAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    fn direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    fn line_height(mut self, height: LineHeight) -> Self {
+        self.paragraph.line_height = height;
+        self
+    }
+
+    fn paragraph_spacing(mut self, spacing: f64) -> Self {
+        self.paragraph.paragraph_spacing = spacing;
+        self
+    }
+
+    fn first_line_indent(mut self, indent: f64) -> Self {
+        self.paragraph.first_line_indent = indent;
+        self
+    }
+
+    fn hanging_indent(mut self, indent: f64) -> Self {
+        self.paragraph.hanging_indent = indent;
+        self
+    }
+
+    fn max_lines(mut self, lines: usize) -> Self {
+        self.max_lines = lines;
+        self
+    }
+
+    fn overflow(mut self, overflow: TextOverflow) -> Self {
+        self.overflow = overflow;
         self
     }
 
@@ -134,36 +282,60 @@ impl TextLayoutBuilder for CairoTextLayoutBuilder {
     }
 
     fn range_attribute(
-        self,
-        _range: impl RangeBounds<usize>,
-        _attribute: impl Into<TextAttribute>,
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
     ) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        self.attributes.add(range, attribute.into());
         self
     }
 
     fn build(self) -> Result<Self::Out, Error> {
-        // set our default font
-        let font = CairoFont::new(self.defaults.font.clone());
-        let size = self.defaults.font_size;
-        let weight = if self.defaults.weight.to_raw() <= piet::FontWeight::MEDIUM.to_raw() {
-            FontWeight::Normal
-        } else {
-            FontWeight::Bold
-        };
-        let slant = match self.defaults.style {
-            FontStyle::Italic => FontSlant::Italic,
-            FontStyle::Regular => FontSlant::Normal,
+        let bidi = BidiLevels::new(&self.text, self.direction);
+        let runs = {
+            let mut loaded_fonts = self.loaded_fonts.borrow_mut();
+            let mut runs = Vec::new();
+            for run in self.attributes.runs(&self.defaults, self.text.len()) {
+                if run.attrs.placeholder.is_some() {
+                    // a placeholder is a single box, so it isn't split by
+                    // direction or font
+                    let level = bidi
+                        .runs(run.range.clone())
+                        .first()
+                        .map(|(_, level)| *level)
+                        .unwrap_or_else(|| bidi.paragraph_level(run.range.start));
+                    let range = run.range.clone();
+                    let (_, font) =
+                        font_runs(&self.text, range.clone(), &mut loaded_fonts, &run.attrs)
+                            .remove(0);
+                    runs.push(Run::new(&self.text, range, font, &run.attrs, level));
+                    continue;
+                }
+                for (range, level) in bidi.runs(run.range) {
+                    for (range, font) in font_runs(&self.text, range, &mut loaded_fonts, &run.attrs)
+                    {
+                        runs.push(Run::new(&self.text, range, font, &run.attrs, level));
+                    }
+                }
+            }
+            runs
         };
 
-        let scaled_font = font.resolve(size, slant, weight);
-
         // invalid until update_width() is called
         let mut layout = CairoTextLayout {
-            fg_color: self.defaults.fg_color,
-            font: scaled_font,
+            runs: Runs::new(runs),
             size: Size::ZERO,
+            image_bounds: Rect::ZERO,
             trailing_ws_width: 0.0,
+            alignment: self.alignment,
+            paragraph: self.paragraph,
+            max_lines: self.max_lines,
+            overflow: self.overflow,
+            elision: None,
+            bidi,
             line_metrics: Vec::new(),
+            line_alignments: Vec::new(),
             text: self.text,
         };
 
@@ -188,7 +360,7 @@ impl TextLayout for CairoTextLayout {
     }
 
     fn image_bounds(&self) -> Rect {
-        self.size.to_rect()
+        self.image_bounds
     }
 
     fn text(&self) -> &str {
@@ -227,26 +399,46 @@ impl TextLayout for CairoTextLayout {
         // determine whether this click is within the y bounds of the layout,
         // and what line it coorresponds to. (For points above and below the layout,
         // we hittest the first and last lines respectively.)
-        let (y_inside, lm) = if point.y < 0. {
-            (false, self.line_metrics.first().unwrap())
+        //
+        // Points in the space between paragraphs hit the line below.
+        let (y_inside, line_num) = if point.y < 0. {
+            (false, 0)
         } else if point.y >= height {
-            (false, self.line_metrics.last().unwrap())
+            (false, self.line_metrics.len() - 1)
         } else {
-            let line = self
+            let line_num = self
                 .line_metrics
                 .iter()
-                .find(|l| point.y >= l.y_offset && point.y < l.y_offset + l.height)
+                .position(|l| point.y < l.y_offset + l.height)
                 .unwrap();
-            (true, line)
+            (true, line_num)
         };
 
-        // Trailing whitespace is remove for the line
-        let line = &self.text[lm.range()];
+        let lm = &self.line_metrics[line_num];
+        let line = self.line(line_num);
 
-        let mut htp = hit_test_line_point(&self.font, line, point);
+        let mut htp = hit_test_line_point(&line, point);
         htp.idx += lm.start_offset;
-        if htp.idx == lm.end_offset {
-            htp.idx -= util::trailing_nlf(line).unwrap_or(0);
+        // hidden text is hit at whichever end of the ellipsis is nearer
+        if let Some(elision) = &self.elision {
+            let range = &elision.range;
+            if range.start < htp.idx && htp.idx < range.end {
+                htp.idx = if htp.idx - range.start < range.end - htp.idx {
+                    range.start
+                } else {
+                    range.end
+                };
+            }
+        }
+        if htp.idx == lm.start_offset + line.text.len() {
+            match util::trailing_nlf(line.text) {
+                Some(len) => htp.idx -= len,
+                // past the end of a wrapped line, the caret stays on the line
+                None if line_num + 1 < self.line_metrics.len() => {
+                    htp = HitTestPoint::with_affinity(htp.idx, htp.is_inside, Affinity::Upstream)
+                }
+                None => (),
+            }
         }
         htp.is_inside &= y_inside;
         htp
@@ -257,7 +449,9 @@ impl TextLayout for CairoTextLayout {
         assert!(self.text.is_char_boundary(idx));
 
         if idx == 0 && self.text.is_empty() {
-            return HitTestPosition::new(Point::new(0., self.font.extents().ascent), 0);
+            let x_pos = self.line_alignments[0].x_offset;
+            let baseline = self.line_metrics[0].baseline;
+            return HitTestPosition::new(Point::new(x_pos, baseline), 0);
         }
 
         // first need to find line it's on, and get line start offset
@@ -267,24 +461,193 @@ impl TextLayout for CairoTextLayout {
         let y_pos = lm.y_offset + lm.baseline;
 
         // Then for the line, do text position
-        // Trailing whitespace is removed for the line
-        let line = &self.text[lm.range()];
         let line_position = idx - lm.start_offset;
-
-        let x_pos = hit_test_line_position(&self.font, line, line_position);
+        let x_pos = hit_test_line_position(&self.line(line_num), line_position);
         HitTestPosition::new(Point::new(x_pos, y_pos), line_num)
     }
+
+    fn cursor_rect(&self, idx: usize, affinity: Affinity) -> Rect {
+        let idx = idx.min(self.text.len());
+        let mut line_num = util::line_number_for_position(&self.line_metrics, idx);
+        if affinity == Affinity::Upstream && line_num > 0 {
+            let prev = &self.line_metrics[line_num - 1];
+            let prev_text = &self.text[prev.range()];
+            if idx == prev.end_offset && util::trailing_nlf(prev_text).is_none() {
+                line_num -= 1;
+            }
+        }
+        let lm = &self.line_metrics[line_num];
+        let line = self.line(line_num);
+        let line_idx = idx - lm.start_offset;
+
+        let mut x = hit_test_line_position(&line, line_idx);
+        if affinity == Affinity::Upstream && line_idx > 0 {
+            // the caret goes against the trailing edge of the character before
+            // it, which is away from `x` at a boundary between directions.
+            let prev_char = line.text[..line_idx].chars().next_back().unwrap();
+            let prev_idx = line_idx - prev_char.len_utf8();
+            let leading = line.x_of(prev_idx);
+            if let Some(&(x0, x1)) = line.x_ranges(prev_idx..line_idx).first() {
+                x = if (x0 - leading).abs() < (x1 - leading).abs() {
+                    x1
+                } else {
+                    x0
+                };
+            }
+        }
+        Rect::new(x, lm.y_offset, x, lm.y_offset + lm.height)
+    }
+
+    fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
+        let text_len = self.text.len();
+        let mut range = util::resolve_range(range, text_len);
+        range.start = range.start.min(text_len);
+        range.end = range.end.min(text_len);
+
+        if range.start >= range.end {
+            return Vec::new();
+        }
+
+        let first_line = util::line_number_for_position(&self.line_metrics, range.start);
+        let last_line = util::line_number_for_position(&self.line_metrics, range.end);
+
+        // a selection in mixed-direction text can cover several pieces of a line
+        let mut result = Vec::new();
+        for line_num in first_line..=last_line {
+            let lm = &self.line_metrics[line_num];
+            let y0 = lm.y_offset;
+            let y1 = y0 + lm.height;
+            let start = range.start.max(lm.start_offset);
+            let end = if line_num == last_line {
+                range.end
+            } else {
+                lm.end_offset - lm.trailing_whitespace
+            };
+
+            let line = self.line(line_num);
+            let start = start - lm.start_offset;
+            let end = end.max(lm.start_offset) - lm.start_offset;
+            let spans = line.x_ranges(start..end);
+            if spans.is_empty() {
+                let x = line.x_of(start);
+                result.push(Rect::new(x, y0, x, y1));
+            }
+            result.extend(spans.into_iter().map(|(x0, x1)| Rect::new(x0, y0, x1, y1)));
+        }
+        result
+    }
+
+    fn placeholder_rects(&self) -> Vec<(Range<usize>, Rect)> {
+        let last_line = self.line_metrics.len() - 1;
+        let visible_end =
+            self.line_metrics[last_line].start_offset + self.line(last_line).text.len();
+        let is_hidden = |range: &Range<usize>| {
+            range.start >= visible_end
+                || self.elision.as_ref().map_or(false, |elision| {
+                    elision.range.start < range.end && range.start < elision.range.end
+                })
+        };
+        self.runs
+            .slices(0..self.text.len())
+            .filter_map(|(range, run)| {
+                let placeholder = run.placeholder?;
+                if is_hidden(&range) {
+                    return None;
+                }
+                let line_num = util::line_number_for_position(&self.line_metrics, range.start);
+                let lm = &self.line_metrics[line_num];
+                let line = self.line(line_num);
+                let (x0, _) = line
+                    .x_ranges(range.start - lm.start_offset..range.end - lm.start_offset)
+                    .first()
+                    .copied()?;
+                let y0 = lm.y_offset + lm.baseline - run.baseline_offset - placeholder.baseline;
+                let rect = Rect::new(x0, y0, x0 + placeholder.width, y0 + placeholder.height);
+                Some((range, rect))
+            })
+            .collect()
+    }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        self.placed_glyphs()
+            .into_iter()
+            .map(|(run, range, glyphs)| GlyphRun {
+                range,
+                font: run.family.clone(),
+                font_size: run.font.get_font_matrix().yy,
+                weight: run.weight,
+                style: run.style,
+                color: run.fg_color.clone(),
+                glyphs,
+            })
+            .collect()
+    }
+
+    fn glyph_outlines(&self) -> Vec<BezPath> {
+        // cairo can only build paths on a context; any surface will do.
+        let surface = match ImageSurface::create(Format::A8, 1, 1) {
+            Ok(surface) => surface,
+            Err(_) => return Vec::new(),
+        };
+        let ctx = Context::new(&surface);
+        let mut outlines = Vec::new();
+        for (run, _, glyphs) in self.placed_glyphs() {
+            ctx.set_scaled_font(&run.font);
+            for glyph in glyphs {
+                ctx.new_path();
+                ctx.glyph_path(&[cairo::Glyph {
+                    index: glyph.id.into(),
+                    x: glyph.position.x,
+                    y: glyph.position.y,
+                }]);
+                outlines.push(to_bez_path(&ctx.copy_path()));
+            }
+        }
+        outlines
+    }
 }
 
 impl CairoTextLayout {
+    /// The glyphs of each slice of each line that has any, in visual order
+    /// within a line, positioned relative to the layout's origin as
+    /// `draw_text` places them.
+    fn placed_glyphs(&self) -> Vec<(&Run, Range<usize>, Vec<Glyph>)> {
+        let mut placed = Vec::new();
+        for (line_num, lm) in self.line_metrics.iter().enumerate() {
+            let line = self.line(line_num);
+            let y = lm.y_offset + lm.baseline;
+            let levels: Vec<_> = line.slices().iter().map(|slice| slice.level).collect();
+            for idx in bidi::visual_order(&levels) {
+                let slice = &line.slices()[idx];
+                let run = slice.run;
+                let y = y - run.baseline_offset;
+                let glyphs: Vec<Glyph> = line
+                    .glyphs(slice)
+                    .map(|(x, glyph)| Glyph {
+                        id: glyph.id,
+                        position: Point::new(x + glyph.x_offset, y - glyph.y_offset),
+                        advance: glyph.x_advance,
+                        cluster: glyph.cluster,
+                    })
+                    .collect();
+                if !glyphs.is_empty() {
+                    placed.push((run, slice.range.clone(), glyphs));
+                }
+            }
+        }
+        placed
+    }
+
     fn update_width(&mut self, new_width: impl Into<Option<f64>>) -> Result<(), Error> {
         let new_width = new_width.into().unwrap_or(std::f64::INFINITY);
 
-        self.line_metrics = lines::calculate_line_metrics(&self.text, &self.font, new_width);
+        self.line_metrics =
+            lines::calculate_line_metrics(&self.text, &self.runs, new_width, &self.paragraph);
         if self.text.is_empty() {
+            let (baseline, height) = self.paragraph.line_extents(&self.runs, 0..0);
             self.line_metrics.push(LineMetric {
-                baseline: self.font.extents().ascent,
-                height: self.font.extents().height,
+                baseline,
+                height,
                 ..Default::default()
             })
         } else if util::trailing_nlf(&self.text).is_some() {
@@ -296,38 +659,248 @@ impl CairoTextLayout {
                     end_offset: self.text.len(),
                     height: lm.height,
                     baseline: lm.baseline,
-                    y_offset: lm.y_offset + lm.height,
+                    y_offset: lm.y_offset + lm.height + self.paragraph.paragraph_spacing,
                     trailing_whitespace: 0,
                 })
                 .unwrap();
             self.line_metrics.push(newline_eof);
         }
+        self.truncate(new_width);
 
-        let (width, ws_width) = self
+        let indents: Vec<f64> = self
             .line_metrics
             .iter()
-            .map(|lm| {
-                let full_width = self.font.text_extents(&self.text[lm.range()]).x_advance;
-                let non_ws_width = if lm.trailing_whitespace > 0 {
-                    let non_ws_range = lm.start_offset..lm.end_offset - lm.trailing_whitespace;
-                    self.font.text_extents(&self.text[non_ws_range]).x_advance
-                } else {
-                    full_width
-                };
-                (non_ws_width, full_width)
+            .map(|lm| self.paragraph.indent(&self.text, lm.start_offset))
+            .collect();
+        let line_widths: Vec<(f64, f64)> = (0..self.line_metrics.len())
+            .map(|i| {
+                self.line_with_alignment(i, LineAlignment::default())
+                    .widths()
             })
-            .fold((0.0, 0.0), |a: (f64, f64), b| (a.0.max(b.0), a.1.max(b.1)));
+            .collect();
+        let (width, ws_width) = line_widths
+            .iter()
+            .zip(&indents)
+            .fold((0.0, 0.0), |a: (f64, f64), (b, indent)| {
+                (a.0.max(indent + b.0), a.1.max(indent + b.1))
+            });
+
+        // lines are aligned within the width constraint, if there is one, and
+        // otherwise within the widest line.
+        let align_width = if new_width.is_finite() {
+            new_width
+        } else {
+            width
+        };
+        let line_count = self.line_metrics.len();
+        self.line_alignments = self
+            .line_metrics
+            .iter()
+            .zip(&line_widths)
+            .zip(&indents)
+            .enumerate()
+            .map(|(i, ((lm, (non_ws_width, _)), indent))| {
+                let is_last = i == line_count - 1;
+                let rtl = self.bidi.paragraph_level(lm.start_offset).is_rtl();
+                // indents are from the start edge, which is on the right in
+                // right-to-left paragraphs
+                let mut alignment =
+                    self.align_line(lm, *non_ws_width, align_width - indent, is_last, rtl);
+                if !rtl {
+                    alignment.x_offset += indent;
+                }
+                alignment
+            })
+            .collect();
 
         let height = self
             .line_metrics
             .last()
             .map(|l| l.y_offset + l.height)
-            .unwrap_or_else(|| self.font.extents().height);
+            .unwrap_or_else(|| self.runs.line_extents(0..0).1);
         self.size = Size::new(width, height);
         self.trailing_ws_width = ws_width;
 
+        // justified lines are stretched to fill the alignment width
+        let x1 = self
+            .line_alignments
+            .iter()
+            .zip(&line_widths)
+            .map(|(align, (non_ws_width, _))| {
+                if align.word_spacing != 0.0 {
+                    align_width
+                } else {
+                    align.x_offset + non_ws_width
+                }
+            })
+            .fold(0.0, f64::max);
+        self.image_bounds = Rect::new(0.0, 0.0, x1, height);
+
         Ok(())
     }
+
+    fn align_line(
+        &self,
+        lm: &LineMetric,
+        non_ws_width: f64,
+        align_width: f64,
+        is_last: bool,
+        rtl: bool,
+    ) -> LineAlignment {
+        let free_space = (align_width - non_ws_width).max(0.0);
+        // the start of a right-to-left paragraph is on the right
+        let start = LineAlignment {
+            x_offset: if rtl { free_space } else { 0.0 },
+            word_spacing: 0.0,
+        };
+        let end = LineAlignment {
+            x_offset: if rtl { 0.0 } else { free_space },
+            word_spacing: 0.0,
+        };
+        match self.alignment {
+            TextAlignment::Start => start,
+            TextAlignment::End => end,
+            TextAlignment::Center => LineAlignment {
+                x_offset: free_space / 2.0,
+                word_spacing: 0.0,
+            },
+            TextAlignment::Justified => {
+                // the last line of each paragraph is not stretched
+                let line = &self.text[lm.range()];
+                if is_last || util::trailing_nlf(line).is_some() {
+                    return start;
+                }
+                let content = self.text[lm.range()].trim_end();
+                match content.matches(' ').count() {
+                    0 => start,
+                    spaces => LineAlignment {
+                        x_offset: 0.0,
+                        word_spacing: free_space / spaces as f64,
+                    },
+                }
+            }
+        }
+    }
+
+    /// Drop the lines after `max_lines`, and hide the text that doesn't fit
+    /// on the last line behind an ellipsis, as determined by `overflow`.
+    fn truncate(&mut self, width: f64) {
+        self.elision = None;
+        let max_lines = self.max_lines.max(1);
+        let is_truncated = self.line_metrics.len() > max_lines;
+        let text_last_line = self.line_metrics.last().unwrap().clone();
+        self.line_metrics.truncate(max_lines);
+        if self.overflow == TextOverflow::Clip {
+            return;
+        }
+
+        let lm = self.line_metrics.last().unwrap();
+        let start = lm.start_offset;
+        let content_end = start + self.text[lm.range()].trim_end().len();
+        let room = width - self.paragraph.indent(&self.text, start);
+        if !is_truncated && self.runs.advance(start..content_end) <= room {
+            return;
+        }
+
+        let level = self.bidi.paragraph_level(start);
+        let style_run = self.runs.run_at(content_end.max(start + 1) - 1);
+        let text_len = self.text.len();
+        let sample = Run::ellipsis(style_run, text_len..text_len, level);
+        let room = room - sample.width();
+
+        // the line keeps text from `start..head_end` and `tail_start..`
+        let graphemes = |range: Range<usize>| {
+            let offset = range.start;
+            self.text[range]
+                .grapheme_indices(true)
+                .map(move |(idx, g)| offset + idx..offset + idx + g.len())
+        };
+        let mut head_end = start;
+        let mut head_width = 0.0;
+        for grapheme in graphemes(start..content_end) {
+            let width = self.runs.advance(grapheme.clone());
+            if head_width + width > room {
+                break;
+            }
+            head_width += width;
+            head_end = grapheme.end;
+        }
+        let mut tail_start = text_len;
+        if self.overflow == TextOverflow::MiddleEllipsis {
+            // the end of the text shares the room with the start of the line,
+            // taking its graphemes alternately from each end.
+            let tail_line = text_last_line.start_offset.max(start);
+            let tail_end = tail_line + self.text[tail_line..].trim_end().len();
+            let mut head = graphemes(start..content_end);
+            let mut tail = graphemes(tail_line..tail_end).rev();
+            let mut tail_width = 0.0;
+            head_end = start;
+            head_width = 0.0;
+            tail_start = tail_end;
+            let mut head_done = false;
+            let mut tail_done = false;
+            while !(head_done && tail_done) {
+                let from_head = !head_done && (tail_done || head_width <= tail_width);
+                let grapheme = if from_head { head.next() } else { tail.next() };
+                let grapheme = match grapheme {
+                    Some(g) if g.start >= head_end && g.end <= tail_start => g,
+                    _ => {
+                        head_done |= from_head;
+                        tail_done |= !from_head;
+                        continue;
+                    }
+                };
+                let width = self.runs.advance(grapheme.clone());
+                if head_width + tail_width + width > room {
+                    head_done |= from_head;
+                    tail_done |= !from_head;
+                } else if from_head {
+                    head_width += width;
+                    head_end = grapheme.end;
+                } else {
+                    tail_width += width;
+                    tail_start = grapheme.start;
+                }
+            }
+        }
+        if head_end >= tail_start {
+            return;
+        }
+
+        let range = head_end..tail_start;
+        let style_run = self.runs.run_at(head_end.max(start + 1) - 1);
+        self.elision = Some(Elision {
+            range: range.clone(),
+            ellipsis: Run::ellipsis(style_run, range, level),
+        });
+    }
+
+    /// The line at `line_number`, for measurement.
+    pub(crate) fn line(&self, line_number: usize) -> Line<'_> {
+        self.line_with_alignment(line_number, self.line_alignments[line_number])
+    }
+
+    fn line_with_alignment(&self, line_number: usize, alignment: LineAlignment) -> Line<'_> {
+        let lm = &self.line_metrics[line_number];
+        // only the last line can hide text; it then draws up to the end of
+        // the text, past the end of its metric.
+        let elision = self
+            .elision
+            .as_ref()
+            .filter(|_| line_number + 1 == self.line_metrics.len());
+        let range = match elision {
+            Some(_) => lm.start_offset..self.text.len(),
+            None => lm.range(),
+        };
+        Line::new(
+            &self.text[range],
+            lm.start_offset,
+            &self.runs,
+            alignment,
+            self.bidi.paragraph_level(lm.start_offset),
+            elision,
+        )
+    }
 }
 
 impl fmt::Debug for CairoTextLayout {
@@ -336,86 +909,102 @@ impl fmt::Debug for CairoTextLayout {
     }
 }
 
-// NOTE this is the same as the old, non-line-aware version of hit_test_point
-// Future: instead of passing Font, should there be some other line-level text layout?
-fn hit_test_line_point(font: &ScaledFont, text: &str, point: Point) -> HitTestPoint {
+// Positions are not monotonic across a line of mixed-direction text, but they
+// are within each of its visual runs; we find the run under the point and
+// binary search the graphemes inside it.
+fn hit_test_line_point(line: &Line, point: Point) -> HitTestPoint {
+    let text = line.text;
+
     // null case
     if text.is_empty() {
         return HitTestPoint::default();
     }
 
-    // get bounds
-    // TODO handle if string is not null yet count is 0?
-    let end = UnicodeSegmentation::graphemes(text, true).count() - 1;
-    let end_bounds = match get_grapheme_boundaries(font, text, end) {
-        Some(bounds) => bounds,
-        None => return HitTestPoint::default(),
+    // each end of a run, with its position
+    let runs = line.visual_runs();
+    let ends = || {
+        runs.iter().flat_map(|(range, start_x, end_x)| {
+            std::iter::once((range.start, *start_x)).chain(Some((range.end, *end_x)))
+        })
     };
+    let by_x = |a: &(usize, f64), b: &(usize, f64)| a.1.partial_cmp(&b.1).unwrap();
 
-    let start = 0;
-    let start_bounds = match get_grapheme_boundaries(font, text, start) {
-        Some(bounds) => bounds,
+    // first test beyond ends
+    let (left_idx, left_x) = match ends().min_by(by_x) {
+        Some(end) => end,
         None => return HitTestPoint::default(),
     };
-
-    // first test beyond ends
-    if point.x > end_bounds.trailing {
-        return HitTestPoint::new(text.len(), false);
-    }
-    if point.x <= start_bounds.leading {
-        return HitTestPoint::default();
-    }
-
-    // then test the beginning and end (common cases)
-    if let Some(hit) = point_x_in_grapheme(point.x, &start_bounds) {
-        return hit;
+    let (right_idx, right_x) = ends().max_by(by_x).unwrap();
+    if point.x > right_x {
+        return HitTestPoint::new(right_idx, false);
     }
-    if let Some(hit) = point_x_in_grapheme(point.x, &end_bounds) {
-        return hit;
+    if point.x <= left_x {
+        return HitTestPoint::new(left_idx, false);
     }
 
-    // Now that we know it's not beginning or end, begin binary search.
-    // Iterative style
-    let mut left = start;
-    let mut right = end;
-    loop {
-        // pick halfway point
-        let middle = left + ((right - left) / 2);
-
-        let grapheme_bounds = match get_grapheme_boundaries(font, text, middle) {
-            Some(bounds) => bounds,
-            None => return HitTestPoint::default(),
-        };
+    let run = runs.iter().find(|(_, start_x, end_x)| {
+        start_x.min(*end_x) <= point.x && point.x <= start_x.max(*end_x)
+    });
+    let (range, start_x, end_x) = match run {
+        Some((range, start_x, end_x)) => (range.clone(), *start_x, *end_x),
+        None => return HitTestPoint::new(left_idx, false),
+    };
 
-        if let Some(hit) = point_x_in_grapheme(point.x, &grapheme_bounds) {
-            return hit;
+    // the grapheme boundaries in the run, and the distance of each from the
+    // start of the run, which only grows
+    let mut edges: Vec<usize> = std::iter::once(range.start)
+        .chain(
+            text[range.clone()]
+                .grapheme_indices(true)
+                .map(|(idx, _)| range.start + idx)
+                .filter(|&idx| idx > range.start),
+        )
+        .collect();
+    edges.push(range.end);
+    let dir = if end_x < start_x { -1.0 } else { 1.0 };
+    let x_of = |idx: usize| {
+        if idx == range.end {
+            end_x
+        } else {
+            line.x_of(idx)
         }
-
-        // since it's not a hit, check if closer to start or finish
-        // and move the appropriate search boundary
-        if point.x < grapheme_bounds.leading {
-            right = middle;
-        } else if point.x > grapheme_bounds.trailing {
-            left = middle + 1;
+    };
+    let target = (point.x - start_x) * dir;
+
+    // the first edge at or past the point; the point is in the grapheme that
+    // ends there
+    let (mut lo, mut hi) = (0, edges.len() - 1);
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if (x_of(edges[mid]) - start_x) * dir >= target {
+            hi = mid;
         } else {
-            unreachable!("hit_test_point conditional is exhaustive");
+            lo = mid;
         }
     }
+    let bounds = GraphemeBoundaries {
+        curr_idx: edges[hi - 1],
+        next_idx: edges[hi],
+        leading: x_of(edges[hi - 1]),
+        trailing: x_of(edges[hi]),
+    };
+    point_x_in_grapheme(point.x, &bounds)
+        .unwrap_or_else(|| HitTestPoint::new(bounds.curr_idx, true))
 }
 
 // NOTE this is the same as the old, non-line-aware version of hit_test_text_position.
-// Future: instead of passing Font, should there be some other line-level text layout?
-fn hit_test_line_position(font: &ScaledFont, text: &str, text_position: usize) -> f64 {
+fn hit_test_line_position(line: &Line, text_position: usize) -> f64 {
     // Using substrings with unicode grapheme awareness
+    let text = line.text;
 
     let text_len = text.len();
 
     if text_position == 0 {
-        return 0.0;
+        return line.x_of(0);
     }
 
     if text_position as usize >= text_len {
-        return font.text_extents(&text).x_advance;
+        return line.x_of(text_len);
     }
 
     // Already checked that text_position > 0 and text_position < count.
@@ -427,8 +1016,8 @@ fn hit_test_line_position(font: &ScaledFont, text: &str, text_position: usize) -
 
     grapheme_indices
         .last()
-        .map(|(idx, _)| font.text_extents(&text[..idx]).x_advance)
-        .unwrap_or_else(|| font.text_extents(&text).x_advance)
+        .map(|(idx, _)| line.x_of(idx))
+        .unwrap_or_else(|| line.x_of(text_len))
 }
 
 fn scale_matrix(scale: f64) -> Matrix {
@@ -445,7 +1034,11 @@ fn scale_matrix(scale: f64) -> Matrix {
 #[cfg(test)]
 mod test {
     use super::*;
-    use piet::TextLayout;
+    use piet::kurbo::{Shape, Vec2};
+    use piet::{
+        BaselineShift, Color, FontFeature, FontVariation, FontWidth, Placeholder, TextLayout,
+        UnderlineStyle,
+    };
 
     macro_rules! assert_close {
         ($val:expr, $target:expr, $tolerance:expr) => {{
@@ -464,6 +1057,152 @@ mod test {
         }};
     }
 
+    #[test]
+    fn load_font_rejects_garbage() {
+        let mut text = CairoText::new();
+        assert!(text.load_font(b"definitely not a font").is_err());
+    }
+
+    /// A font for tests that shouldn't depend on the fonts that are installed.
+    const TEST_FONT: &[u8] = include_bytes!("../resources/JetBrainsMono-Regular.ttf");
+
+    fn glyph_ids(layout: &CairoTextLayout) -> Vec<u32> {
+        layout
+            .glyph_runs()
+            .iter()
+            .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.id))
+            .collect()
+    }
+
+    #[test]
+    fn load_font() {
+        let mut text = CairoText::new();
+        let family = text.load_font(TEST_FONT).unwrap();
+        assert_eq!(family.name(), "JetBrains Mono");
+        let layout = text
+            .new_text_layout("iimm")
+            .font(family.clone(), 20.0)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].font, family);
+        // the font is monospaced
+        let half = layout.hit_test_text_position(2).point.x;
+        assert_close!(layout.size().width, 2.0 * half, 0.001);
+    }
+
+    #[test]
+    fn loaded_fonts_belong_to_their_factory() {
+        let mut text = CairoText::new();
+        let family = text.load_font(TEST_FONT).unwrap();
+        let font = CairoFont::new(family);
+        let defaults = util::LayoutDefaults::default();
+        let loaded = font.face(&mut text.loaded_fonts.borrow_mut(), &defaults);
+
+        let clone = text.clone();
+        assert!(loaded == font.face(&mut clone.loaded_fonts.borrow_mut(), &defaults));
+        let other = CairoText::new();
+        assert!(loaded != font.face(&mut other.loaded_fonts.borrow_mut(), &defaults));
+    }
+
+    #[test]
+    fn font_features_layout() {
+        let mut text = CairoText::new();
+        let family = text.load_font(TEST_FONT).unwrap();
+        let input = "a->b";
+        let ligated = text
+            .new_text_layout(input)
+            .font(family.clone(), 20.0)
+            .build()
+            .unwrap();
+        let plain = text
+            .new_text_layout(input)
+            .font(family.clone(), 20.0)
+            .default_attribute(FontFeature::new(b"calt", 0))
+            .build()
+            .unwrap();
+        // the font draws arrows with contextual alternates
+        assert_ne!(glyph_ids(&ligated), glyph_ids(&plain));
+        assert_close!(ligated.size().width, plain.size().width, 0.001);
+
+        // a feature on a range only affects that range
+        let ranged = text
+            .new_text_layout("a->b a->b")
+            .font(family, 20.0)
+            .range_attribute(5.., FontFeature::new(b"calt", 0))
+            .build()
+            .unwrap();
+        let ids = glyph_ids(&ranged);
+        assert_eq!(&ids[..4], &glyph_ids(&ligated)[..]);
+        assert_eq!(&ids[5..], &glyph_ids(&plain)[..]);
+    }
+
+    #[test]
+    fn width_and_variations_layout() {
+        let mut text = CairoText::new();
+        let family = text.load_font(TEST_FONT).unwrap();
+        let plain = text
+            .new_text_layout("piet text")
+            .font(family.clone(), 20.0)
+            .build()
+            .unwrap();
+        // the font only has one width and no axes, so it is used as it is
+        let styled = text
+            .new_text_layout("piet text")
+            .font(family.clone(), 20.0)
+            .default_attribute(FontWidth::CONDENSED)
+            .range_attribute(5.., vec![FontVariation::new(b"wght", 700.0)])
+            .build()
+            .unwrap();
+        let runs = styled.glyph_runs();
+        assert!(runs.iter().all(|run| run.font == family));
+        assert_eq!(glyph_ids(&styled), glyph_ids(&plain));
+        assert_close!(styled.size().width, plain.size().width, 0.001);
+    }
+
+    #[test]
+    fn letter_and_word_spacing_layout() {
+        let mut text = CairoText::new();
+        let family = text.load_font(TEST_FONT).unwrap();
+        let input = "ab cd";
+        let plain = text
+            .new_text_layout(input)
+            .font(family.clone(), 20.0)
+            .build()
+            .unwrap();
+        let width = plain.size().width;
+
+        // letter spacing goes after every cluster, word spacing after spaces
+        let spaced = text
+            .new_text_layout(input)
+            .font(family.clone(), 20.0)
+            .default_attribute(TextAttribute::LetterSpacing(2.0))
+            .default_attribute(TextAttribute::WordSpacing(3.0))
+            .build()
+            .unwrap();
+        assert_close!(spaced.size().width, width + 5.0 * 2.0 + 3.0, 0.001);
+        let pos = spaced.hit_test_text_position(3).point.x;
+        let plain_pos = plain.hit_test_text_position(3).point.x;
+        assert_close!(pos, plain_pos + 3.0 * 2.0 + 3.0, 0.001);
+
+        // negative spacing on a range brings its characters closer
+        let tight = text
+            .new_text_layout(input)
+            .font(family, 20.0)
+            .range_attribute(3.., TextAttribute::LetterSpacing(-1.0))
+            .build()
+            .unwrap();
+        assert_close!(tight.size().width, width - 2.0, 0.001);
+        let glyphs: Vec<f64> = tight
+            .glyph_runs()
+            .iter()
+            .flat_map(|run| run.glyphs.iter().map(|glyph| glyph.position.x))
+            .collect();
+        let advance = glyphs[1] - glyphs[0];
+        assert_close!(glyphs[4] - glyphs[3], advance - 1.0, 0.001);
+    }
+
     #[test]
     #[allow(clippy::float_cmp)]
     fn hit_test_empty_string() {
@@ -1332,4 +2071,745 @@ mod test {
         assert_eq!(pt.idx, 5);
         assert_eq!(pt.is_inside, false);
     }
+
+    #[test]
+    fn range_attributes_affect_measurement() {
+        let mut text = CairoText::new();
+        let plain = text.new_text_layout("piet text").build().unwrap();
+        let layout = text
+            .new_text_layout("piet text")
+            .range_attribute(5.., TextAttribute::FontSize(24.0))
+            .build()
+            .unwrap();
+
+        // the larger run makes the line both wider and taller
+        assert!(layout.size().width > plain.size().width);
+        assert!(layout.size().height > plain.size().height);
+        let line = layout.line_metric(0).unwrap();
+        assert!(line.baseline > plain.line_metric(0).unwrap().baseline);
+
+        // positions in the first run are unchanged
+        let pos = layout.hit_test_text_position(4);
+        assert_close!(pos.point.x, plain.hit_test_text_position(4).point.x, 0.001);
+        assert_close!(
+            layout.hit_test_text_position(9).point.x,
+            layout.size().width,
+            0.001
+        );
+
+        // and hit-testing round-trips across the run boundary
+        for idx in &[2, 5, 7] {
+            let point = layout.hit_test_text_position(*idx).point;
+            let hit = layout.hit_test_point(Point::new(point.x + 0.1, point.y));
+            assert_eq!(hit.idx, *idx);
+        }
+    }
+
+    #[test]
+    fn alignment_offsets_lines() {
+        let mut text = CairoText::new();
+        let input = "piet text is the best text";
+        let start = text
+            .new_text_layout(input)
+            .max_width(100.0)
+            .build()
+            .unwrap();
+        let center = text
+            .new_text_layout(input)
+            .max_width(100.0)
+            .alignment(TextAlignment::Center)
+            .build()
+            .unwrap();
+        let end = text
+            .new_text_layout(input)
+            .max_width(100.0)
+            .alignment(TextAlignment::End)
+            .build()
+            .unwrap();
+
+        assert!(start.line_count() > 1);
+        for line in 0..start.line_count() {
+            let lm = start.line_metric(line).unwrap();
+            let content_end = lm.end_offset - lm.trailing_whitespace;
+            let line_width = start.hit_test_text_position(content_end).point.x;
+            let free_space = 100.0 - line_width;
+
+            let center_x = center.hit_test_text_position(lm.start_offset).point.x;
+            assert_close!(center_x, free_space / 2.0, 0.001);
+            let end_x = end.hit_test_text_position(content_end).point.x;
+            assert_close!(end_x, 100.0, 0.001);
+
+            // hit-testing agrees with the shifted positions
+            let start_x = end.hit_test_text_position(lm.start_offset).point.x;
+            let hit = end.hit_test_point(Point::new(start_x + 0.1, lm.y_offset + 1.0));
+            assert_eq!(hit.idx, lm.start_offset);
+            let hit = end.hit_test_point(Point::new(end_x - 0.1, lm.y_offset + 1.0));
+            assert_eq!(hit.idx, content_end);
+        }
+
+        let rects = center.rects_for_range(..);
+        assert_eq!(rects.len(), center.line_count());
+        assert_close!(rects[0].x0, center.hit_test_text_position(0).point.x, 0.001);
+    }
+
+    #[test]
+    fn justified_lines_fill_width() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("piet text is the best text")
+            .max_width(100.0)
+            .alignment(TextAlignment::Justified)
+            .build()
+            .unwrap();
+
+        let last_line = layout.line_count() - 1;
+        assert!(last_line > 0);
+        for line in 0..layout.line_count() {
+            let lm = layout.line_metric(line).unwrap();
+            let content_end = lm.end_offset - lm.trailing_whitespace;
+            let start_x = layout.hit_test_text_position(lm.start_offset).point.x;
+            let end_x = layout.hit_test_text_position(content_end).point.x;
+            assert_close!(start_x, 0.0, 0.001);
+            let has_spaces = layout.text()[lm.start_offset..content_end].contains(' ');
+            if line != last_line && has_spaces {
+                assert_close!(end_x, 100.0, 0.001);
+            } else {
+                assert!(end_x < 100.0);
+            }
+        }
+    }
+
+    #[test]
+    fn justified_multibyte_trailing_whitespace() {
+        // lines can break after U+3000 IDEOGRAPHIC SPACE, which is three bytes
+        let input = "piet text\u{3000}is the\u{3000}best text";
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout(input)
+            .max_width(60.0)
+            .alignment(TextAlignment::Justified)
+            .build()
+            .unwrap();
+
+        assert!(layout.line_count() > 1);
+        for line in 0..layout.line_count() {
+            let lm = layout.line_metric(line).unwrap();
+            let line_text = &input[lm.range()];
+            assert_eq!(
+                lm.trailing_whitespace,
+                line_text.len() - line_text.trim_end().len()
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn arabic_runs_right_to_left() {
+        let input = "\u{0633}\u{0644}\u{0627}\u{0645}"; // سلام
+        let mut text = CairoText::new();
+        let layout = text.new_text_layout(input).build().unwrap();
+        let width = layout.size().width;
+        assert!(width > 0.0);
+
+        // the start of the text is on the right
+        assert_close!(layout.hit_test_text_position(0).point.x, width, 0.001);
+        assert_close!(
+            layout.hit_test_text_position(input.len()).point.x,
+            0.0,
+            0.001
+        );
+
+        let hit = layout.hit_test_point(Point::new(width - 0.5, 5.0));
+        assert_eq!(hit.idx, 0);
+        assert!(hit.is_inside);
+        let hit = layout.hit_test_point(Point::new(-5.0, 5.0));
+        assert_eq!(hit.idx, input.len());
+        assert!(!hit.is_inside);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn mixed_direction_selection() {
+        // "abc " followed by two hebrew letters, then " def"
+        let input = "abc \u{05D0}\u{05D1} def";
+        let hebrew_start = 4;
+        let hebrew_end = 8;
+        let mut text = CairoText::new();
+        let layout = text.new_text_layout(input).build().unwrap();
+
+        // the hebrew is drawn right to left, between the english words
+        let start_x = layout.hit_test_text_position(hebrew_start).point.x;
+        let end_x = layout.hit_test_text_position(hebrew_end).point.x;
+        assert!(start_x > end_x);
+
+        // selecting from the middle of the hebrew into the english gives
+        // two separate rects
+        let rects = layout.rects_for_range(hebrew_start + 2..input.len());
+        assert_eq!(rects.len(), 2);
+        assert!(rects[0].x1 <= rects[1].x0);
+
+        // selecting the whole line gives one
+        let rects = layout.rects_for_range(..);
+        assert_eq!(rects.len(), 1);
+        assert_close!(rects[0].width(), layout.size().width, 0.001);
+    }
+
+    #[test]
+    fn right_to_left_alignment() {
+        let mut text = CairoText::new();
+        let input = "piet text";
+        let rtl = text
+            .new_text_layout(input)
+            .max_width(200.0)
+            .direction(TextDirection::RightToLeft)
+            .build()
+            .unwrap();
+
+        // latin text still reads left to right, but starts on the right
+        let start_x = rtl.hit_test_text_position(0).point.x;
+        let end_x = rtl.hit_test_text_position(input.len()).point.x;
+        assert!(start_x < end_x);
+        assert_close!(end_x, 200.0, 0.001);
+
+        let end = text
+            .new_text_layout(input)
+            .max_width(200.0)
+            .direction(TextDirection::RightToLeft)
+            .alignment(TextAlignment::End)
+            .build()
+            .unwrap();
+        assert_close!(end.hit_test_text_position(0).point.x, 0.0, 0.001);
+    }
+
+    #[test]
+    fn paragraph_style() {
+        let mut text = CairoText::new();
+        let input = "piet text\nmost best";
+        let plain = text.new_text_layout(input).build().unwrap();
+        let styled = text
+            .new_text_layout(input)
+            .line_height(LineHeight::Absolute(30.0))
+            .paragraph_spacing(10.0)
+            .first_line_indent(20.0)
+            .build()
+            .unwrap();
+
+        let first = styled.line_metric(0).unwrap();
+        let second = styled.line_metric(1).unwrap();
+        assert_close!(first.height, 30.0, 0.001);
+        assert_close!(second.y_offset, 40.0, 0.001);
+        // the extra space is split above and below the text
+        let plain_first = plain.line_metric(0).unwrap();
+        let extra = (30.0 - plain_first.height) / 2.0;
+        assert_close!(first.baseline, plain_first.baseline + extra, 0.001);
+
+        // both paragraphs start with an indented line
+        assert_close!(styled.hit_test_text_position(0).point.x, 20.0, 0.001);
+        assert_close!(styled.hit_test_text_position(10).point.x, 20.0, 0.001);
+        assert_close!(styled.size().width, plain.size().width + 20.0, 0.001);
+
+        // the space between paragraphs hits the second line
+        let hit = styled.hit_test_point(Point::new(0.0, 35.0));
+        assert_eq!(hit.idx, 10);
+        let rects = styled.rects_for_range(10..14);
+        assert_close!(rects[0].y0, 40.0, 0.001);
+    }
+
+    #[test]
+    fn hanging_indent() {
+        let mut text = CairoText::new();
+        let input = "piet text piet text";
+        let width = text
+            .new_text_layout("piet text")
+            .build()
+            .unwrap()
+            .size()
+            .width;
+        let layout = text
+            .new_text_layout(input)
+            .max_width(width + 10.0)
+            .hanging_indent(11.0)
+            .build()
+            .unwrap();
+
+        // the second line would fit without the indent
+        assert_eq!(layout.line_text(0), Some("piet text "));
+        assert_eq!(layout.line_text(1), Some("piet "));
+        assert_eq!(layout.line_text(2), Some("text"));
+        assert_close!(layout.hit_test_text_position(10).point.x, 11.0, 0.001);
+    }
+
+    #[test]
+    fn max_lines() {
+        let mut text = CairoText::new();
+        let input = "piet text\nmost best\npiet text";
+        let two_lines = text
+            .new_text_layout("piet text\nmost best")
+            .build()
+            .unwrap();
+        let clipped = text.new_text_layout(input).max_lines(2).build().unwrap();
+        assert_eq!(clipped.line_count(), 2);
+        assert_eq!(clipped.text(), input);
+        assert_close!(clipped.size().height, two_lines.size().height, 0.001);
+
+        let ellipsis = text
+            .new_text_layout(input)
+            .max_lines(1)
+            .overflow(TextOverflow::Ellipsis)
+            .build()
+            .unwrap();
+        let first_line = text.new_text_layout("piet text").build().unwrap();
+        assert_eq!(ellipsis.line_count(), 1);
+        assert_eq!(ellipsis.text(), input);
+        // the line keeps its own range
+        assert_eq!(ellipsis.line_metric(0).unwrap().end_offset, 10);
+        assert_eq!(ellipsis.line_text(0), Some("piet text\n"));
+        assert!(ellipsis.size().width > first_line.size().width);
+        // the ellipsis stands in for the rest of the text
+        let hit = ellipsis.hit_test_point(Point::new(1000.0, 0.0));
+        assert_eq!(hit.idx, input.len());
+        let hit = ellipsis.hit_test_point(Point::new(ellipsis.size().width - 1.0, 0.0));
+        assert_eq!(hit.idx, input.len());
+    }
+
+    #[test]
+    fn overflow_width() {
+        let mut text = CairoText::new();
+        let input = "piet text most best";
+        let width = text
+            .new_text_layout("piet text")
+            .build()
+            .unwrap()
+            .size()
+            .width;
+
+        let end = text
+            .new_text_layout(input)
+            .max_width(width)
+            .max_lines(1)
+            .overflow(TextOverflow::Ellipsis)
+            .build()
+            .unwrap();
+        assert_eq!(end.line_count(), 1);
+        assert!(end.size().width <= width);
+        assert_eq!(end.hit_test_point(Point::ZERO).idx, 0);
+
+        let middle = text
+            .new_text_layout(input)
+            .max_width(width)
+            .max_lines(1)
+            .overflow(TextOverflow::MiddleEllipsis)
+            .build()
+            .unwrap();
+        assert_eq!(middle.line_count(), 1);
+        assert_eq!(middle.line_metric(0).unwrap().end_offset, 10);
+        assert!(middle.size().width <= width);
+        // both ends of the text are still visible
+        assert_eq!(middle.hit_test_point(Point::ZERO).idx, 0);
+        let end_x = middle.hit_test_text_position(input.len()).point.x;
+        assert_close!(end_x, middle.size().width, 0.001);
+    }
+
+    #[test]
+    fn baseline_shift() {
+        let mut text = CairoText::new();
+        let plain = text.new_text_layout("H2O").build().unwrap();
+        let plain_line = plain.line_metric(0).unwrap();
+        let raised = text
+            .new_text_layout("m2")
+            .range_attribute(
+                1..2,
+                BaselineShift::Offset {
+                    offset: 20.0,
+                    scale: 1.0,
+                },
+            )
+            .build()
+            .unwrap();
+        let lowered = text
+            .new_text_layout("H2O")
+            .range_attribute(
+                1..2,
+                BaselineShift::Offset {
+                    offset: -20.0,
+                    scale: 1.0,
+                },
+            )
+            .build()
+            .unwrap();
+
+        // the line grows upwards for raised text, and downwards for lowered text
+        let line = raised.line_metric(0).unwrap();
+        assert_close!(line.baseline, plain_line.baseline + 20.0, 0.001);
+        let line = lowered.line_metric(0).unwrap();
+        assert_close!(line.baseline, plain_line.baseline, 0.001);
+        assert_close!(line.height, plain_line.height + 20.0, 0.001);
+        assert_close!(
+            lowered.rects_for_range(1..2)[0].height(),
+            line.height,
+            0.001
+        );
+
+        // subscripts are smaller
+        let subscript = text
+            .new_text_layout("H2O")
+            .range_attribute(1..2, BaselineShift::SUBSCRIPT)
+            .build()
+            .unwrap();
+        assert!(subscript.size().width < plain.size().width);
+    }
+
+    #[test]
+    fn decorations() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("piet text")
+            .range_attribute(0..4, UnderlineStyle::Wavy)
+            .range_attribute(0..4, TextAttribute::DecorationColor(Color::RED))
+            .range_attribute(5.., TextAttribute::Overline(true))
+            .range_attribute(5.., TextAttribute::DecorationThickness(3.0))
+            .build()
+            .unwrap();
+
+        let first = &layout.runs.run_at(0).decorations;
+        assert_eq!(first.underline, Some(UnderlineStyle::Wavy));
+        assert_eq!(first.color, Some(Color::RED));
+        assert!(!first.overline);
+
+        let second = layout.runs.run_at(5);
+        assert_eq!(second.decorations.underline, None);
+        assert!(second.decorations.overline);
+        let metrics = second.decoration_metrics();
+        assert_close!(metrics.thickness, 3.0, 0.001);
+        // overlines are above the baseline
+        assert!(metrics.overline_offset < 0.0);
+    }
+
+    #[test]
+    fn background_color() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("piet text")
+            .alignment(TextAlignment::End)
+            .max_width(200.0)
+            .range_attribute(5.., TextAttribute::BackgroundColor(Color::BLACK))
+            .build()
+            .unwrap();
+        assert_eq!(layout.runs.run_at(0).background, None);
+        assert_eq!(layout.runs.run_at(5).background, Some(Color::BLACK));
+    }
+
+    #[test]
+    fn placeholders() {
+        let mut text = CairoText::new();
+        let input = "piet \u{FFFC} text";
+        let plain = text.new_text_layout("piet  text").build().unwrap();
+        let layout = text
+            .new_text_layout(input)
+            .range_attribute(5..8, Placeholder::new(30.0, 40.0))
+            .build()
+            .unwrap();
+
+        // the box sits on the baseline, and makes the line taller
+        let line = layout.line_metric(0).unwrap();
+        assert_close!(line.baseline, 40.0, 0.001);
+        assert_close!(layout.size().width, plain.size().width + 30.0, 0.001);
+        let rects = layout.placeholder_rects();
+        assert_eq!(rects.len(), 1);
+        let (range, rect) = rects[0].clone();
+        assert_eq!(range, 5..8);
+        let x = layout.hit_test_text_position(5).point.x;
+        assert_eq!(rect, Rect::new(x, 0.0, x + 30.0, 40.0));
+
+        // a box that hangs below the baseline
+        let layout = text
+            .new_text_layout(input)
+            .range_attribute(5..8, Placeholder::new(30.0, 40.0).with_baseline(10.0))
+            .build()
+            .unwrap();
+        let line = layout.line_metric(0).unwrap();
+        assert_close!(line.height - line.baseline, 30.0, 0.001);
+        assert_close!(
+            layout.placeholder_rects()[0].1.y1,
+            line.baseline + 30.0,
+            0.001
+        );
+    }
+
+    #[test]
+    fn glyph_runs() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("piet text\nsecond")
+            .font(FontFamily::SANS_SERIF, 16.0)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].range, 0..10);
+        assert_eq!(runs[1].range, 10..16);
+        assert_close!(runs[0].font_size, 16.0, 0.001);
+
+        // the newline has no glyph
+        let glyphs = &runs[0].glyphs;
+        let clusters: Vec<_> = glyphs.iter().map(|glyph| glyph.cluster).collect();
+        assert_eq!(clusters, (0..9).collect::<Vec<_>>());
+        let line = layout.line_metric(0).unwrap();
+        assert_close!(glyphs[0].position.x, 0.0, 0.001);
+        assert_close!(glyphs[0].position.y, line.baseline, 0.001);
+        let last = glyphs.last().unwrap();
+        assert_close!(
+            last.position.x + last.advance,
+            layout.hit_test_text_position(9).point.x,
+            0.001
+        );
+
+        let line = layout.line_metric(1).unwrap();
+        let glyph = runs[1].glyphs[0];
+        assert_close!(glyph.position.y, line.y_offset + line.baseline, 0.001);
+
+        // in a right-to-left paragraph, the runs of a line go right to left in
+        // text order, but are reported left to right
+        let layout = text
+            .new_text_layout("abc \u{5d0}\u{5d1}\u{5d2} def")
+            .direction(TextDirection::RightToLeft)
+            .build()
+            .unwrap();
+        let runs = layout.glyph_runs();
+        assert!(runs.len() > 1);
+        assert!(runs[0].range.start > runs[runs.len() - 1].range.start);
+        for pair in runs.windows(2) {
+            let last = pair[0].glyphs.last().unwrap();
+            let next = pair[1].glyphs[0];
+            assert!(next.position.x >= last.position.x + last.advance - 0.001);
+        }
+    }
+
+    #[test]
+    fn text_on_path() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("ab\u{301}c")
+            .font(FontFamily::SANS_SERIF, 16.0)
+            .build()
+            .unwrap();
+
+        // a path straight down, so the text is turned a quarter clockwise
+        let mut path = BezPath::new();
+        path.move_to((50.0, 10.0));
+        path.line_to((50.0, 200.0));
+        let clusters = util::text_on_path(&layout, &path, 5.0, TextAlignment::Start);
+        // the combining accent is part of the cluster of the "b"
+        let ranges: Vec<_> = clusters.iter().map(|c| c.range.clone()).collect();
+        assert_eq!(ranges, vec![0..1, 1..4, 4..5]);
+
+        for glyph in layout.glyph_runs().iter().flat_map(|run| &run.glyphs) {
+            let cluster = clusters
+                .iter()
+                .find(|c| c.range.contains(&glyph.cluster))
+                .unwrap();
+            // each glyph's origin is on the path, as far along it as it is
+            // along the baseline
+            let origin = cluster.transform * glyph.position;
+            assert_close!(origin.x, 50.0, 0.001);
+            assert_close!(origin.y, 15.0 + glyph.position.x, 0.001);
+            let end = cluster.transform * (glyph.position + Vec2::new(glyph.advance, 0.0));
+            assert_close!(end.x, 50.0, 0.001);
+            assert_close!(end.y - origin.y, glyph.advance, 0.001);
+        }
+
+        // centered text is centered on the offset
+        let clusters = util::text_on_path(&layout, &path, 100.0, TextAlignment::Center);
+        let first = clusters[0].transform * clusters[0].bounds.origin();
+        let last = clusters[2].transform * Point::new(clusters[2].bounds.x1, 0.0);
+        assert_close!((first.y + last.y) / 2.0, 110.0, 0.001);
+    }
+
+    #[test]
+    fn glyph_outlines() {
+        let mut text = CairoText::new();
+        let layout = text
+            .new_text_layout("piet text")
+            .font(FontFamily::SANS_SERIF, 16.0)
+            .build()
+            .unwrap();
+        let glyph_count: usize = layout.glyph_runs().iter().map(|r| r.glyphs.len()).sum();
+        let outlines = layout.glyph_outlines();
+        assert_eq!(outlines.len(), glyph_count);
+        // the space has no outline
+        assert!(outlines[4].elements().is_empty());
+
+        // the outline sits inside the line, with the 'p' below the baseline
+        let bounds = layout.outline().bounding_box();
+        let line = layout.line_metric(0).unwrap();
+        assert!(bounds.x0 >= 0.0 && bounds.x1 <= layout.size().width + 1.0);
+        assert!(bounds.y0 >= 0.0 && bounds.y1 <= line.height);
+        assert!(bounds.y1 > line.baseline);
+    }
+
+    #[test]
+    fn cursor_affinity() {
+        let mut text = CairoText::new();
+        let full = text.new_text_layout("hello world").build().unwrap();
+        let layout = text
+            .new_text_layout("hello world")
+            .max_width(full.size().width - 1.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+        let first = layout.line_metric(0).unwrap();
+        let second = layout.line_metric(1).unwrap();
+
+        // position 6 is both the end of "hello " and the start of "world"
+        let downstream = layout.cursor_rect(6, Affinity::Downstream);
+        assert_close!(downstream.x0, 0.0, 0.001);
+        assert_close!(downstream.y0, second.y_offset, 0.001);
+        let upstream = layout.cursor_rect(6, Affinity::Upstream);
+        assert_close!(upstream.y0, first.y_offset, 0.001);
+        assert_close!(upstream.height(), first.height, 0.001);
+        assert!(upstream.x0 > layout.hit_test_text_position(5).point.x);
+
+        let hit = layout.hit_test_point(Point::new(upstream.x0 + 50.0, first.baseline));
+        assert_eq!(hit.idx, 6);
+        assert_eq!(hit.affinity, Affinity::Upstream);
+        let hit = layout.hit_test_point(Point::new(0.0, second.y_offset + second.baseline));
+        assert_eq!(hit.idx, 6);
+        assert_eq!(hit.affinity, Affinity::Downstream);
+    }
+
+    /// Forwards only the required `TextLayout` methods and `glyph_runs`, so
+    /// that the provided caret methods use their default implementations.
+    #[derive(Clone)]
+    struct DefaultCaret(CairoTextLayout);
+
+    impl TextLayout for DefaultCaret {
+        fn size(&self) -> Size {
+            self.0.size()
+        }
+
+        fn trailing_whitespace_width(&self) -> f64 {
+            self.0.trailing_whitespace_width()
+        }
+
+        fn image_bounds(&self) -> Rect {
+            self.0.image_bounds()
+        }
+
+        fn text(&self) -> &str {
+            self.0.text()
+        }
+
+        fn line_text(&self, line_number: usize) -> Option<&str> {
+            self.0.line_text(line_number)
+        }
+
+        fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+            self.0.line_metric(line_number)
+        }
+
+        fn line_count(&self) -> usize {
+            self.0.line_count()
+        }
+
+        fn hit_test_point(&self, point: Point) -> HitTestPoint {
+            self.0.hit_test_point(point)
+        }
+
+        fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
+            self.0.hit_test_text_position(idx)
+        }
+
+        fn glyph_runs(&self) -> Vec<GlyphRun> {
+            self.0.glyph_runs()
+        }
+    }
+
+    #[test]
+    fn default_cursor_rect() {
+        let mut text = CairoText::new();
+        let full = text.new_text_layout("hello world").build().unwrap();
+        let layout = text
+            .new_text_layout("hello world")
+            .max_width(full.size().width - 1.0)
+            .build()
+            .unwrap();
+        let default = DefaultCaret(layout.clone());
+        let first = layout.line_metric(0).unwrap();
+        let second = layout.line_metric(1).unwrap();
+
+        // at the soft break, an upstream caret is after the trailing space
+        let upstream = default.cursor_rect(6, Affinity::Upstream);
+        assert_close!(upstream.y0, first.y_offset, 0.001);
+        assert_close!(
+            upstream.x0,
+            layout.cursor_rect(6, Affinity::Upstream).x0,
+            0.001
+        );
+        assert!(upstream.x0 > layout.hit_test_text_position(5).point.x);
+        let downstream = default.cursor_rect(6, Affinity::Downstream);
+        assert_close!(downstream.y0, second.y_offset, 0.001);
+        assert_close!(downstream.x0, 0.0, 0.001);
+
+        // elsewhere, affinity doesn't matter
+        for idx in &[0, 3, 8, 11] {
+            let expected = layout.cursor_rect(*idx, Affinity::Downstream);
+            assert_eq!(default.cursor_rect(*idx, Affinity::Upstream), expected);
+            assert_eq!(default.cursor_rect(*idx, Affinity::Downstream), expected);
+        }
+    }
+
+    #[test]
+    fn cursor_navigation() {
+        let mut text = CairoText::new();
+        let full = text.new_text_layout("hello world").build().unwrap();
+        let layout = text
+            .new_text_layout("hello world")
+            .max_width(full.size().width - 1.0)
+            .build()
+            .unwrap();
+
+        assert_eq!(layout.line_start_offset(3, Affinity::Downstream), 0);
+        assert_eq!(layout.line_start_offset(6, Affinity::Upstream), 0);
+        assert_eq!(layout.line_start_offset(6, Affinity::Downstream), 6);
+        assert_eq!(
+            layout.line_end_offset(0, Affinity::Downstream),
+            (6, Affinity::Upstream)
+        );
+        assert_eq!(
+            layout.line_end_offset(7, Affinity::Downstream),
+            (11, Affinity::Downstream)
+        );
+
+        assert_eq!(layout.next_word_offset(0), 5);
+        assert_eq!(layout.next_word_offset(5), 11);
+        assert_eq!(layout.prev_word_offset(11), 6);
+        assert_eq!(layout.prev_word_offset(6), 0);
+
+        // moving down and back up keeps the caret near the same x
+        let x = layout.hit_test_text_position(1).point.x;
+        let (below, _) = layout.offset_below(1, Affinity::Downstream, x);
+        assert_eq!(layout.hit_test_text_position(below).line, 1);
+        let (above, _) = layout.offset_above(below, Affinity::Downstream, x);
+        assert_eq!(above, 1);
+        assert_eq!(
+            layout.offset_above(1, Affinity::Downstream, x),
+            (0, Affinity::Downstream)
+        );
+        assert_eq!(
+            layout.offset_below(below, Affinity::Downstream, x),
+            (11, Affinity::Downstream)
+        );
+
+        // a combining accent is part of the grapheme before it
+        let layout = text.new_text_layout("e\u{301}x").build().unwrap();
+        assert_eq!(layout.next_grapheme_offset(0), 3);
+        assert_eq!(layout.next_grapheme_offset(3), 4);
+        assert_eq!(layout.prev_grapheme_offset(3), 0);
+        assert_eq!(layout.prev_grapheme_offset(0), 0);
+
+        // offsets inside a character are moved back to its start
+        let layout = text.new_text_layout("h\u{e9} ll\u{f6}").build().unwrap();
+        assert_eq!(layout.next_word_offset(2), 3);
+        assert_eq!(layout.prev_word_offset(7), 4);
+    }
 }
@@ -0,0 +1,131 @@
+//! Bidirectional text, with the Unicode Bidirectional Algorithm.
+
+use std::ops::Range;
+use std::rc::Rc;
+
+use piet::TextDirection;
+use unic_bidi::{BidiInfo, Level};
+
+/// The resolved embedding levels of a layout's text.
+#[derive(Clone)]
+pub(crate) struct BidiLevels {
+    /// The level of each byte of the text.
+    levels: Rc<[Level]>,
+    /// The range and base level of each paragraph.
+    paragraphs: Rc<[(Range<usize>, Level)]>,
+    /// The level of a paragraph with no text.
+    default_level: Level,
+}
+
+impl BidiLevels {
+    pub(crate) fn new(text: &str, direction: TextDirection) -> Self {
+        let base_level = match direction {
+            TextDirection::Auto => None,
+            TextDirection::LeftToRight => Some(Level::ltr()),
+            TextDirection::RightToLeft => Some(Level::rtl()),
+        };
+        let info = BidiInfo::new(text, base_level);
+        let paragraphs = info
+            .paragraphs
+            .iter()
+            .map(|para| (para.range.clone(), para.level))
+            .collect();
+        BidiLevels {
+            levels: info.levels.into(),
+            paragraphs,
+            default_level: base_level.unwrap_or_else(Level::ltr),
+        }
+    }
+
+    /// Split `range` into pieces that each have a single level.
+    pub(crate) fn runs(&self, range: Range<usize>) -> Vec<(Range<usize>, Level)> {
+        let mut runs: Vec<(Range<usize>, Level)> = Vec::new();
+        for idx in range.clone() {
+            let level = self.levels[idx];
+            match runs.last_mut() {
+                Some((run, run_level)) if *run_level == level => run.end = idx + 1,
+                _ => runs.push((idx..idx + 1, level)),
+            }
+        }
+        if runs.is_empty() {
+            runs.push((range.clone(), self.paragraph_level(range.start)));
+        }
+        runs
+    }
+
+    /// The base level of the paragraph containing `idx`.
+    pub(crate) fn paragraph_level(&self, idx: usize) -> Level {
+        self.paragraphs
+            .iter()
+            .find(|(range, _)| idx < range.end)
+            .or_else(|| self.paragraphs.last())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default_level)
+    }
+}
+
+/// The visual order of a line's runs, given their levels in text order.
+///
+/// This is rule L2 of the bidi algorithm: from the highest level down to the
+/// lowest odd level, reverse every sequence of runs at that level or higher.
+pub(crate) fn visual_order(levels: &[Level]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..levels.len()).collect();
+    let highest = match levels.iter().max() {
+        Some(level) => level.number(),
+        None => return order,
+    };
+    let odd_levels = levels.iter().map(Level::number).filter(|n| n % 2 == 1);
+    let lowest_odd = match odd_levels.min() {
+        Some(level) => level,
+        None => return order,
+    };
+
+    for level in (lowest_odd..=highest).rev() {
+        let mut start = 0;
+        while start < order.len() {
+            if levels[order[start]].number() < level {
+                start += 1;
+                continue;
+            }
+            let mut end = start;
+            while end < order.len() && levels[order[end]].number() >= level {
+                end += 1;
+            }
+            order[start..end].reverse();
+            start = end;
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn levels(numbers: &[u8]) -> Vec<Level> {
+        numbers.iter().map(|n| Level::new(*n).unwrap()).collect()
+    }
+
+    #[test]
+    fn reorder_runs() {
+        assert_eq!(visual_order(&levels(&[0, 0, 0])), vec![0, 1, 2]);
+        assert_eq!(visual_order(&levels(&[1, 1, 1])), vec![2, 1, 0]);
+        // an english phrase in an arabic paragraph
+        assert_eq!(visual_order(&levels(&[1, 2, 1])), vec![2, 1, 0]);
+        // an arabic phrase containing a number, in an english paragraph
+        assert_eq!(visual_order(&levels(&[0, 1, 2, 1, 0])), vec![0, 3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn explicit_direction() {
+        let text = "abc";
+        let rtl = BidiLevels::new(text, TextDirection::RightToLeft);
+        assert!(rtl.paragraph_level(0).is_rtl());
+        assert_eq!(rtl.runs(0..3).len(), 1);
+        assert_eq!(rtl.runs(0..3)[0].1.number(), 2);
+
+        let auto = BidiLevels::new("\u{05D0}\u{05D1} abc", TextDirection::Auto);
+        assert!(auto.paragraph_level(0).is_rtl());
+        assert_eq!(auto.runs(0..8).len(), 2);
+    }
+}
@@ -0,0 +1,200 @@
+//! Finding system fonts with fontconfig.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::PathBuf;
+use std::ptr;
+
+use fontconfig_sys as fc;
+use fontconfig_sys::constants::{
+    FC_CHARSET, FC_FAMILY, FC_FILE, FC_INDEX, FC_SLANT, FC_SLANT_ITALIC, FC_SLANT_ROMAN, FC_WEIGHT,
+    FC_WIDTH,
+};
+
+use piet::{util, FontFamily, FontStyle};
+
+/// The location of a font file on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct FontMatch {
+    pub(crate) path: PathBuf,
+    pub(crate) index: u32,
+}
+
+/// The style we are asking fontconfig for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FontQuery {
+    /// A CSS weight, from 1 to 1000.
+    pub(crate) weight: u16,
+    /// An OpenType width class, from 1 to 9.
+    pub(crate) width: u16,
+    pub(crate) italic: bool,
+}
+
+/// CSS weights and the fontconfig weights they correspond to, as in
+/// fontconfig's own `FcWeightFromOpenType`.
+const WEIGHTS: [(f64, f64); 12] = [
+    (100., 0.),
+    (200., 40.),
+    (300., 50.),
+    (350., 55.),
+    (380., 75.),
+    (400., 80.),
+    (500., 100.),
+    (600., 180.),
+    (700., 200.),
+    (800., 205.),
+    (900., 210.),
+    (1000., 215.),
+];
+
+impl FontQuery {
+    pub(crate) fn new(attrs: &util::LayoutDefaults) -> Self {
+        FontQuery {
+            weight: attrs.weight.to_raw(),
+            width: attrs.width.to_raw(),
+            italic: attrs.style == FontStyle::Italic,
+        }
+    }
+
+    /// The width as a percentage of normal, which is also what fontconfig
+    /// uses.
+    pub(crate) fn width_percentage(&self) -> f32 {
+        piet::FontWidth::new(self.width).percentage()
+    }
+}
+
+/// Find the system font that best matches `family` and `query`.
+///
+/// If `ch` is provided, fontconfig will prefer fonts that contain it; this is
+/// how we find fallback fonts.
+///
+/// fontconfig always returns *something* if any fonts are installed, so this
+/// will only return `None` if fontconfig itself is unavailable.
+pub(crate) fn match_font(
+    family: &FontFamily,
+    query: FontQuery,
+    ch: Option<char>,
+) -> Option<FontMatch> {
+    let family = CString::new(family_name(family)).ok()?;
+    let weight = fontconfig_weight(query.weight);
+    // fontconfig prefers oblique faces to roman ones when there is no italic
+    let slant = if query.italic {
+        FC_SLANT_ITALIC
+    } else {
+        FC_SLANT_ROMAN
+    };
+
+    unsafe {
+        let pattern = fc::FcPatternCreate();
+        if pattern.is_null() {
+            return None;
+        }
+        fc::FcPatternAddString(pattern, FC_FAMILY.as_ptr(), family.as_ptr() as *const _);
+        fc::FcPatternAddInteger(pattern, FC_WEIGHT.as_ptr(), weight);
+        fc::FcPatternAddInteger(pattern, FC_SLANT.as_ptr(), slant);
+        let width = query.width_percentage().round() as c_int;
+        fc::FcPatternAddInteger(pattern, FC_WIDTH.as_ptr(), width);
+        if let Some(ch) = ch {
+            let charset = fc::FcCharSetCreate();
+            fc::FcCharSetAddChar(charset, ch as u32);
+            // the pattern takes its own reference to the charset
+            fc::FcPatternAddCharSet(pattern, FC_CHARSET.as_ptr(), charset);
+            fc::FcCharSetDestroy(charset);
+        }
+        fc::FcConfigSubstitute(ptr::null_mut(), pattern, fc::FcMatchPattern);
+        fc::FcDefaultSubstitute(pattern);
+
+        let mut result = fc::FcResultNoMatch;
+        let matched = fc::FcFontMatch(ptr::null_mut(), pattern, &mut result);
+        fc::FcPatternDestroy(pattern);
+        if matched.is_null() {
+            return None;
+        }
+
+        let font = get_string(matched, FC_FILE.as_ptr())
+            .and_then(|path| path_from_bytes(path.to_bytes()))
+            .map(|path| FontMatch {
+                path,
+                index: get_integer(matched, FC_INDEX.as_ptr()).unwrap_or(0) as u32,
+            });
+        fc::FcPatternDestroy(matched);
+        font
+    }
+}
+
+/// fontconfig gives us paths as bytes; on Unix these can be any bytes.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    Some(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+/// fontconfig gives us paths as bytes; elsewhere they are UTF-8.
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    std::str::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// fontconfig knows the CSS generic families, except for `system-ui`.
+fn family_name(family: &FontFamily) -> &str {
+    if family == &FontFamily::SYSTEM_UI {
+        FontFamily::SANS_SERIF.name()
+    } else {
+        family.name()
+    }
+}
+
+/// Convert a CSS weight to a fontconfig weight, interpolating between the
+/// named weights.
+fn fontconfig_weight(weight: u16) -> c_int {
+    let weight = (weight as f64).max(WEIGHTS[0].0);
+    let idx = WEIGHTS
+        .iter()
+        .position(|(css, _)| weight <= *css)
+        .unwrap_or(WEIGHTS.len() - 1)
+        .max(1);
+    let (css0, fc0) = WEIGHTS[idx - 1];
+    let (css1, fc1) = WEIGHTS[idx];
+    let t = (weight - css0) / (css1 - css0);
+    (fc0 + t * (fc1 - fc0)).round() as c_int
+}
+
+/// The returned string borrows from `pattern`.
+unsafe fn get_string<'a>(pattern: *mut fc::FcPattern, object: *const c_char) -> Option<&'a CStr> {
+    let mut value: *mut fc::FcChar8 = ptr::null_mut();
+    if fc::FcPatternGetString(pattern, object, 0, &mut value) == fc::FcResultMatch
+        && !value.is_null()
+    {
+        Some(CStr::from_ptr(value as *const c_char))
+    } else {
+        None
+    }
+}
+
+unsafe fn get_integer(pattern: *mut fc::FcPattern, object: *const c_char) -> Option<c_int> {
+    let mut value: c_int = 0;
+    if fc::FcPatternGetInteger(pattern, object, 0, &mut value) == fc::FcResultMatch {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontconfig_sys::constants::{
+        FC_WEIGHT_BLACK, FC_WEIGHT_BOLD, FC_WEIGHT_REGULAR, FC_WEIGHT_THIN,
+    };
+
+    #[test]
+    fn weight_mapping() {
+        assert_eq!(fontconfig_weight(1), FC_WEIGHT_THIN);
+        assert_eq!(fontconfig_weight(100), FC_WEIGHT_THIN);
+        assert_eq!(fontconfig_weight(400), FC_WEIGHT_REGULAR);
+        assert_eq!(fontconfig_weight(700), FC_WEIGHT_BOLD);
+        assert_eq!(fontconfig_weight(900), FC_WEIGHT_BLACK);
+        assert_eq!(fontconfig_weight(450), 90);
+    }
+}
@@ -0,0 +1,577 @@
+//! Loading fonts through FreeType, from memory or from files found by
+//! fontconfig.
+
+use std::collections::{HashMap, HashSet};
+use std::os::raw::{c_int, c_uint};
+use std::rc::Rc;
+
+use cairo::{FontFace, FontSlant, UserDataKey};
+use freetype::face::StyleFlag;
+use freetype::Library;
+
+use piet::{Error, FontFamily, FontVariation, FontWeight, FontWidth};
+
+use super::fontconfig::{self, FontMatch, FontQuery};
+
+/// Lets us get from a cairo font face back to the font it was created from.
+static FONT_DATA_KEY: UserDataKey<FontData> = UserDataKey::new();
+
+// cairo-rs doesn't expose a safe constructor for FreeType font faces.
+extern "C" {
+    fn cairo_ft_font_face_create_for_ft_face(
+        face: freetype::ffi::FT_Face,
+        load_flags: c_int,
+    ) -> *mut cairo_sys::cairo_font_face_t;
+    fn cairo_ft_font_face_set_synthesize(
+        font_face: *mut cairo_sys::cairo_font_face_t,
+        synth_flags: c_uint,
+    );
+}
+
+const CAIRO_FT_SYNTHESIZE_BOLD: c_uint = 1 << 0;
+const CAIRO_FT_SYNTHESIZE_OBLIQUE: c_uint = 1 << 1;
+
+/// How many characters with no fallback font we remember, before we forget
+/// them all and start over.
+const MAX_MISSING_CHARS: usize = 1024;
+
+/// Faces lighter than this are emboldened when a bold weight is requested.
+const SYNTHETIC_BOLD_WEIGHT: FontWeight = FontWeight::SEMI_BOLD;
+
+/// The font behind a font face that we created ourselves.
+///
+/// This is attached to the cairo font face, which keeps the FreeType face (and
+/// the font data it borrows) alive for as long as cairo holds on to it. The
+/// face also keeps its FreeType library alive, as cairo may outlive the
+/// `LoadedFonts` that created it.
+pub(crate) struct FontData {
+    // parsed once for shaping. It borrows `bytes`, so it is declared first,
+    // to be dropped before them.
+    shaping_face: Option<rustybuzz::Face<'static>>,
+    bytes: Rc<Vec<u8>>,
+    index: u32,
+    ft_face: freetype::Face,
+    style: FaceStyle,
+    synthesis: Synthesis,
+    // declared after the face, so that it is dropped last
+    _library: Rc<Library>,
+}
+
+/// The style of a font, as described by the font itself.
+#[derive(Debug, Clone, Copy)]
+struct FaceStyle {
+    weight: u16,
+    /// As a percentage of normal width.
+    width: f32,
+    italic: bool,
+    /// The range of the font's 'wght' axis, if it is a variable font.
+    weight_axis: Option<(f32, f32)>,
+    /// The range of the font's 'wdth' axis, if it is a variable font.
+    width_axis: Option<(f32, f32)>,
+}
+
+/// The styles that cairo fakes for a face, because the font lacks them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub(crate) struct Synthesis {
+    pub(crate) bold: bool,
+    pub(crate) oblique: bool,
+}
+
+/// A face that matches a requested weight and style, along with the font
+/// variations to apply when it is scaled.
+#[derive(Clone)]
+pub(crate) struct ResolvedFace {
+    pub(crate) face: FontFace,
+    /// Variation settings in the format cairo expects, such as `wght=350`.
+    pub(crate) variations: Option<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct LoadedFonts {
+    // initialized the first time a font is loaded
+    library: Option<Rc<Library>>,
+    // multiple faces (e.g. regular & bold) can share a family name, so this
+    // isn't a map. We assume only a handful of custom fonts will be loaded.
+    faces: Vec<LoadedFace>,
+    // system fonts, by file; `None` if the file couldn't be loaded
+    system_faces: HashMap<FontMatch, Option<FontFace>>,
+    // fontconfig queries are slow enough that we remember their answers
+    matches: HashMap<(FontFamily, FontQuery), Option<FontMatch>>,
+    // the fallback faces found so far for each request, which are tried
+    // before asking fontconfig for another one
+    fallbacks: HashMap<(FontFamily, FontQuery), Vec<FontFace>>,
+    // characters that no fallback face could display
+    missing: HashSet<char>,
+    // faces with synthetic styles, keyed by the address of the face they
+    // were made from; those faces are kept alive above.
+    synthesized: HashMap<(usize, Synthesis), Option<FontFace>>,
+}
+
+struct LoadedFace {
+    family: FontFamily,
+    face: FontFace,
+}
+
+impl LoadedFonts {
+    pub(crate) fn add(&mut self, font_data: &[u8]) -> Result<FontFamily, Error> {
+        let face = self.create_face(Rc::new(font_data.to_owned()), 0, Synthesis::default())?;
+        let data = face_data(&face).unwrap();
+        let family = data.family_name().ok_or(Error::FontLoadingFailed)?;
+
+        self.faces.push(LoadedFace {
+            family: family.clone(),
+            face,
+        });
+        Ok(family)
+    }
+
+    /// Returns the face that best matches the requested family and style.
+    ///
+    /// Fonts loaded at runtime take precedence over system fonts with the
+    /// same family name. If fontconfig can't help us, we fall back to the
+    /// cairo "toy" API, which can't be used for shaping.
+    pub(crate) fn face(
+        &mut self,
+        family: &FontFamily,
+        query: FontQuery,
+        variations: &[FontVariation],
+    ) -> ResolvedFace {
+        let face = match self.loaded_face(family, query) {
+            Some(face) => Some(face),
+            None => self.system_face(family, query),
+        };
+        match face {
+            Some(face) => self.resolve(face, query, variations),
+            // cairo synthesizes styles for toy faces itself
+            None => ResolvedFace {
+                face: toy_face(family, query),
+                variations: None,
+            },
+        }
+    }
+
+    /// Returns a face that can display `ch`, for use when the requested font
+    /// can't.
+    ///
+    /// The face will be as similar to `family` as fontconfig can manage.
+    /// Fallback faces we have already found are preferred, so that fontconfig
+    /// is only asked once for each script, rather than for each character.
+    pub(crate) fn fallback_face(
+        &mut self,
+        family: &FontFamily,
+        query: FontQuery,
+        variations: &[FontVariation],
+        ch: char,
+    ) -> Option<ResolvedFace> {
+        if self.missing.contains(&ch) {
+            return None;
+        }
+        let key = (family.clone(), query);
+        let known = self
+            .fallbacks
+            .get(&key)
+            .and_then(|faces| faces.iter().find(|face| has_glyph(face, ch)).cloned());
+        let face = match known {
+            Some(face) => face,
+            None => {
+                let face = fontconfig::match_font(family, query, Some(ch))
+                    .and_then(|font_match| self.file_face(font_match))
+                    .filter(|face| has_glyph(face, ch));
+                match face {
+                    Some(face) => {
+                        self.fallbacks.entry(key).or_default().push(face.clone());
+                        face
+                    }
+                    None => {
+                        if self.missing.len() >= MAX_MISSING_CHARS {
+                            self.missing.clear();
+                        }
+                        self.missing.insert(ch);
+                        return None;
+                    }
+                }
+            }
+        };
+        Some(self.resolve(face, query, variations))
+    }
+
+    /// Prefer faces with the right slant, then the nearest width, and then
+    /// the nearest weight.
+    fn loaded_face(&self, family: &FontFamily, query: FontQuery) -> Option<FontFace> {
+        self.faces
+            .iter()
+            .filter(|f| &f.family == family)
+            .filter_map(|f| Some((f, face_data(&f.face)?.style)))
+            .min_by_key(|(_, style)| {
+                let (min_width, max_width) = style.width_range();
+                let (min_weight, max_weight) = style.weight_range();
+                (
+                    style.italic != query.italic,
+                    width_distance(query.width_percentage(), min_width, max_width),
+                    weight_distance(query.weight, min_weight, max_weight),
+                )
+            })
+            .map(|(f, _)| f.face.clone())
+    }
+
+    /// Pick the weight and width of a variable font, and synthesize the styles
+    /// that the face doesn't have.
+    ///
+    /// Explicit `variations` take precedence over the weight and width.
+    fn resolve(
+        &mut self,
+        face: FontFace,
+        query: FontQuery,
+        variations: &[FontVariation],
+    ) -> ResolvedFace {
+        let data = match face_data(&face) {
+            Some(data) => data,
+            None => {
+                return ResolvedFace {
+                    face,
+                    variations: None,
+                }
+            }
+        };
+        let style = data.style;
+        let (min, max) = style.weight_range();
+        let weight = (query.weight as f32).max(min).min(max);
+        let (min, max) = style.width_range();
+        let width = query.width_percentage().max(min).min(max);
+
+        let mut axes = Vec::new();
+        if style.weight_axis.is_some() {
+            axes.push(FontVariation::new(b"wght", weight));
+        }
+        if style.width_axis.is_some() {
+            axes.push(FontVariation::new(b"wdth", width));
+        }
+        for variation in variations {
+            axes.retain(|v| v.tag() != variation.tag());
+            axes.push(*variation);
+        }
+        let variations = if axes.is_empty() {
+            None
+        } else {
+            let axes: Vec<String> = axes.iter().map(ToString::to_string).collect();
+            Some(axes.join(","))
+        };
+
+        let bold_weight = SYNTHETIC_BOLD_WEIGHT.to_raw() as f32;
+        let synthesis = Synthesis {
+            bold: query.weight as f32 >= bold_weight && weight < bold_weight,
+            oblique: query.italic && !style.italic,
+        };
+        let face = if synthesis == Synthesis::default() {
+            face
+        } else {
+            self.synthesized_face(&face, &data, synthesis)
+                .unwrap_or(face)
+        };
+        ResolvedFace { face, variations }
+    }
+
+    fn synthesized_face(
+        &mut self,
+        face: &FontFace,
+        data: &FontData,
+        synthesis: Synthesis,
+    ) -> Option<FontFace> {
+        let key = (face.to_raw_none() as usize, synthesis);
+        if let Some(face) = self.synthesized.get(&key) {
+            return face.clone();
+        }
+        let face = self
+            .create_face(data.bytes.clone(), data.index, synthesis)
+            .ok();
+        self.synthesized.insert(key, face.clone());
+        face
+    }
+
+    fn system_face(&mut self, family: &FontFamily, query: FontQuery) -> Option<FontFace> {
+        let font_match = self
+            .matches
+            .entry((family.clone(), query))
+            .or_insert_with(|| fontconfig::match_font(family, query, None))
+            .clone()?;
+        self.file_face(font_match)
+    }
+
+    /// The face for a font file, which is only read once.
+    fn file_face(&mut self, font_match: FontMatch) -> Option<FontFace> {
+        if let Some(face) = self.system_faces.get(&font_match) {
+            return face.clone();
+        }
+        let face = std::fs::read(&font_match.path).ok().and_then(|bytes| {
+            self.create_face(Rc::new(bytes), font_match.index, Synthesis::default())
+                .ok()
+        });
+        self.system_faces.insert(font_match, face.clone());
+        face
+    }
+
+    fn create_face(
+        &mut self,
+        bytes: Rc<Vec<u8>>,
+        index: u32,
+        synthesis: Synthesis,
+    ) -> Result<FontFace, Error> {
+        if self.library.is_none() {
+            let library = Library::init().map_err(|_| Error::FontLoadingFailed)?;
+            self.library = Some(Rc::new(library));
+        }
+        let library = self.library.clone().unwrap();
+        let mut ft_face = library
+            .new_memory_face(bytes.clone(), index as isize)
+            .map_err(|_| Error::FontLoadingFailed)?;
+        let style =
+            FaceStyle::new(&bytes, index).unwrap_or_else(|| FaceStyle::from_flags(&ft_face));
+
+        let face = unsafe {
+            let raw = cairo_ft_font_face_create_for_ft_face(ft_face.raw_mut(), 0);
+            if cairo_sys::cairo_font_face_status(raw) != cairo_sys::STATUS_SUCCESS {
+                // cairo returns an inert error object, which still needs freeing
+                cairo_sys::cairo_font_face_destroy(raw);
+                return Err(Error::FontLoadingFailed);
+            }
+            let flags = synthesis.flags();
+            if flags != 0 {
+                cairo_ft_font_face_set_synthesize(raw, flags);
+            }
+            FontFace::from_raw_full(raw)
+        };
+        // SAFETY: the bytes are on the heap, where they stay put for as long as
+        // `FontData` holds on to them, which is longer than the shaping face.
+        let static_bytes: &'static [u8] =
+            unsafe { std::slice::from_raw_parts(bytes.as_ptr(), bytes.len()) };
+        // FreeType also encodes the named instance of a variable font in the
+        // index, which HarfBuzz doesn't expect.
+        let shaping_face = rustybuzz::Face::from_slice(static_bytes, index & 0xFFFF);
+        face.set_user_data(
+            &FONT_DATA_KEY,
+            Rc::new(FontData {
+                shaping_face,
+                bytes,
+                index,
+                ft_face,
+                style,
+                synthesis,
+                _library: library,
+            }),
+        );
+        Ok(face)
+    }
+}
+
+impl FontData {
+    /// The face HarfBuzz shapes with, if the font could be parsed.
+    pub(crate) fn shaping_face(&self) -> Option<&rustybuzz::Face<'_>> {
+        self.shaping_face.as_ref()
+    }
+
+    /// The size of the font's em square, in font units.
+    pub(crate) fn units_per_em(&self) -> f64 {
+        self.ft_face.em_size() as f64
+    }
+
+    /// The family name the font gives for itself.
+    pub(crate) fn family_name(&self) -> Option<FontFamily> {
+        self.ft_face.family_name().map(FontFamily::new_unchecked)
+    }
+
+    pub(crate) fn synthesis(&self) -> Synthesis {
+        self.synthesis
+    }
+
+    /// Whether the font's character map covers `ch`.
+    pub(crate) fn has_glyph(&self, ch: char) -> bool {
+        self.ft_face.get_char_index(ch as usize) != 0
+    }
+
+    /// The underline position and thickness, in ems.
+    ///
+    /// The position is measured upwards from the baseline, as in the font
+    /// itself.
+    pub(crate) fn underline_metrics(&self) -> Option<(f64, f64)> {
+        let units_per_em = self.ft_face.em_size() as f64;
+        if units_per_em <= 0.0 || self.ft_face.underline_thickness() <= 0 {
+            return None;
+        }
+        Some((
+            self.ft_face.underline_position() as f64 / units_per_em,
+            self.ft_face.underline_thickness() as f64 / units_per_em,
+        ))
+    }
+}
+
+fn has_glyph(face: &FontFace, ch: char) -> bool {
+    face_data(face).map_or(false, |data| data.has_glyph(ch))
+}
+
+/// The font behind `face`, if we created it; faces from the toy API have
+/// no data we can get at.
+pub(crate) fn face_data(face: &FontFace) -> Option<Rc<FontData>> {
+    face.get_user_data(&FONT_DATA_KEY)
+}
+
+impl FaceStyle {
+    fn new(bytes: &[u8], index: u32) -> Option<Self> {
+        let face = ttf_parser::Face::from_slice(bytes, index & 0xFFFF).ok()?;
+        let axis = |tag| {
+            face.variation_axes()
+                .find(|axis| axis.tag == ttf_parser::Tag::from_bytes(tag))
+                .map(|axis| (axis.min_value, axis.max_value))
+        };
+        Some(FaceStyle {
+            weight: face.weight().to_number(),
+            width: FontWidth::new(face.width().to_number()).percentage(),
+            italic: face.is_italic() || face.is_oblique(),
+            weight_axis: axis(b"wght"),
+            width_axis: axis(b"wdth"),
+        })
+    }
+
+    /// The style of a font that ttf-parser can't read, such as a Type 1 font.
+    fn from_flags(ft_face: &freetype::Face) -> Self {
+        let flags = ft_face.style_flags();
+        FaceStyle {
+            weight: if flags.contains(StyleFlag::BOLD) {
+                FontWeight::BOLD.to_raw()
+            } else {
+                FontWeight::REGULAR.to_raw()
+            },
+            width: FontWidth::NORMAL.percentage(),
+            italic: flags.contains(StyleFlag::ITALIC),
+            weight_axis: None,
+            width_axis: None,
+        }
+    }
+
+    /// The weights this face can be drawn at without synthesis.
+    fn weight_range(&self) -> (f32, f32) {
+        self.weight_axis
+            .unwrap_or((self.weight as f32, self.weight as f32))
+    }
+
+    /// The widths this face can be drawn at.
+    fn width_range(&self) -> (f32, f32) {
+        self.width_axis.unwrap_or((self.width, self.width))
+    }
+}
+
+impl Synthesis {
+    fn flags(self) -> c_uint {
+        let mut flags = 0;
+        if self.bold {
+            flags |= CAIRO_FT_SYNTHESIZE_BOLD;
+        }
+        if self.oblique {
+            flags |= CAIRO_FT_SYNTHESIZE_OBLIQUE;
+        }
+        flags
+    }
+}
+
+impl PartialEq for ResolvedFace {
+    fn eq(&self, other: &Self) -> bool {
+        self.face.to_raw_none() == other.face.to_raw_none() && self.variations == other.variations
+    }
+}
+
+fn toy_face(family: &FontFamily, query: FontQuery) -> FontFace {
+    let slant = if query.italic {
+        FontSlant::Italic
+    } else {
+        FontSlant::Normal
+    };
+    let weight = if query.weight >= SYNTHETIC_BOLD_WEIGHT.to_raw() {
+        cairo::FontWeight::Bold
+    } else {
+        cairo::FontWeight::Normal
+    };
+    FontFace::toy_create(family.name(), slant, weight)
+}
+
+/// How far `weight` is from a face that covers `min..=max`, roughly following
+/// the CSS font matching rules: weights below 400 look for lighter faces
+/// first, weights above 500 look for bolder faces first, and weights in
+/// between look for faces no bolder than 500 first.
+fn weight_distance(weight: u16, min: f32, max: f32) -> u32 {
+    let weight = weight as f32;
+    let distance = if weight < min {
+        let lighter_first = weight < 400.0 || (weight <= 500.0 && min > 500.0);
+        if lighter_first {
+            min - weight + 1000.0
+        } else {
+            min - weight
+        }
+    } else if weight > max {
+        if weight > 500.0 {
+            weight - max + 1000.0
+        } else {
+            weight - max
+        }
+    } else {
+        0.0
+    };
+    distance as u32
+}
+
+/// How far `width` is from a face that covers `min..=max`; as in CSS,
+/// condensed widths look for narrower faces first, and expanded widths look
+/// for wider faces first.
+fn width_distance(width: f32, min: f32, max: f32) -> u32 {
+    let normal = FontWidth::NORMAL.percentage();
+    let distance = if width < min {
+        let penalty = if width <= normal { 1000.0 } else { 0.0 };
+        min - width + penalty
+    } else if width > max {
+        let penalty = if width > normal { 1000.0 } else { 0.0 };
+        width - max + penalty
+    } else {
+        0.0
+    };
+    distance as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nearest_weight() {
+        let closest = |weight, faces: &[(f32, f32)]| {
+            faces
+                .iter()
+                .min_by_key(|(min, max)| weight_distance(weight, *min, *max))
+                .copied()
+        };
+        let faces = [(300., 300.), (400., 400.), (700., 700.)];
+        assert_eq!(closest(400, &faces), Some((400., 400.)));
+        assert_eq!(closest(500, &faces), Some((400., 400.)));
+        assert_eq!(closest(600, &faces), Some((700., 700.)));
+        assert_eq!(closest(200, &faces), Some((300., 300.)));
+        assert_eq!(closest(950, &faces), Some((700., 700.)));
+
+        // variable fonts cover a range of weights
+        let faces = [(400., 400.), (200., 900.)];
+        assert_eq!(closest(650, &faces), Some((200., 900.)));
+    }
+
+    #[test]
+    fn nearest_width() {
+        let closest = |width, faces: &[(f32, f32)]| {
+            faces
+                .iter()
+                .min_by_key(|(min, max)| width_distance(width, *min, *max))
+                .copied()
+        };
+        let faces = [(75., 75.), (100., 100.), (125., 125.)];
+        assert_eq!(closest(100., &faces), Some((100., 100.)));
+        // condensed prefers narrower, expanded prefers wider
+        assert_eq!(closest(87.5, &faces), Some((75., 75.)));
+        assert_eq!(closest(112.5, &faces), Some((125., 125.)));
+        assert_eq!(closest(200., &faces), Some((125., 125.)));
+        assert_eq!(closest(50., &faces), Some((75., 75.)));
+    }
+}
@@ -1,32 +1,4 @@
-use cairo::ScaledFont;
 use piet::HitTestPoint;
-use unicode_segmentation::UnicodeSegmentation;
-
-use super::hit_test_line_position;
-
-/// get grapheme boundaries, intended to act on a line of text, not a full text layout that has
-/// both horizontal and vertial components
-pub(crate) fn get_grapheme_boundaries(
-    font: &ScaledFont,
-    text: &str,
-    grapheme_position: usize,
-) -> Option<GraphemeBoundaries> {
-    let mut graphemes = UnicodeSegmentation::grapheme_indices(text, true);
-    let (text_position, _) = graphemes.nth(grapheme_position)?;
-    let (next_text_position, _) = graphemes.next().unwrap_or_else(|| (text.len(), ""));
-
-    let curr_edge = hit_test_line_position(font, text, text_position);
-    let next_edge = hit_test_line_position(font, text, next_text_position);
-
-    let res = GraphemeBoundaries {
-        curr_idx: text_position,
-        next_idx: next_text_position,
-        leading: curr_edge,
-        trailing: next_edge,
-    };
-
-    Some(res)
-}
 
 pub(crate) fn point_x_in_grapheme(
     point_x: f64,
@@ -37,12 +9,17 @@ pub(crate) fn point_x_in_grapheme(
     let curr_idx = grapheme_boundaries.curr_idx;
     let next_idx = grapheme_boundaries.next_idx;
 
-    if point_x >= leading && point_x <= trailing {
+    // in right-to-left text, the leading edge is on the right
+    let (left, right) = if leading <= trailing {
+        (leading, trailing)
+    } else {
+        (trailing, leading)
+    };
+    if point_x >= left && point_x <= right {
         // Check which boundary it's closer to.
-        // Round up to next grapheme boundary if
-        let midpoint = leading + ((trailing - leading) / 2.0);
+        // Round up to next grapheme boundary if it's a tie.
         let is_inside = true;
-        let idx = if point_x >= midpoint {
+        let idx = if (point_x - trailing).abs() <= (point_x - leading).abs() {
             next_idx
         } else {
             curr_idx
@@ -64,13 +41,45 @@ pub(crate) struct GraphemeBoundaries {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::text::runs::{Line, LineAlignment, Runs};
     use crate::text::*;
+    use unic_bidi::Level;
+    use unicode_segmentation::UnicodeSegmentation;
+
+    use crate::text::hit_test_line_position;
+
+    /// get grapheme boundaries, intended to act on a line of text, not a full text layout that has
+    /// both horizontal and vertial components
+    ///
+    /// This is linear in the length of the line, so hit testing doesn't use it.
+    fn get_grapheme_boundaries(
+        line: &Line,
+        grapheme_position: usize,
+    ) -> Option<GraphemeBoundaries> {
+        let mut graphemes = UnicodeSegmentation::grapheme_indices(line.text, true);
+        let (text_position, _) = graphemes.nth(grapheme_position)?;
+        let (next_text_position, _) = graphemes.next().unwrap_or_else(|| (line.text.len(), ""));
+
+        let curr_edge = hit_test_line_position(line, text_position);
+        let next_edge = hit_test_line_position(line, next_text_position);
+
+        let res = GraphemeBoundaries {
+            curr_idx: text_position,
+            next_idx: next_text_position,
+            leading: curr_edge,
+            trailing: next_edge,
+        };
+
+        Some(res)
+    }
 
     #[test]
     fn test_grapheme_boundaries() {
         let text = "piet";
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
+        let runs = Runs::simple(text, font);
+        let line = Line::new(text, 0, &runs, LineAlignment::default(), Level::ltr(), None);
 
         let expected_3 = GraphemeBoundaries {
             curr_idx: 3,
@@ -81,14 +90,14 @@ mod test {
 
         // test grapheme boundaries
         assert_eq!(
-            get_grapheme_boundaries(&font, text, 3).unwrap().curr_idx,
+            get_grapheme_boundaries(&line, 3).unwrap().curr_idx,
             expected_3.curr_idx
         );
         assert_eq!(
-            get_grapheme_boundaries(&font, text, 3).unwrap().next_idx,
+            get_grapheme_boundaries(&line, 3).unwrap().next_idx,
             expected_3.next_idx
         );
-        assert_eq!(get_grapheme_boundaries(&font, text, 4), None);
+        assert_eq!(get_grapheme_boundaries(&line, 4), None);
     }
 
     #[test]
@@ -107,5 +116,14 @@ mod test {
         assert_eq!(point_x_in_grapheme(11.0, &bounds), expected_curr);
         assert_eq!(point_x_in_grapheme(12.0, &bounds), expected_next);
         assert_eq!(point_x_in_grapheme(13.0, &bounds), expected_next);
+
+        let rtl_bounds = GraphemeBoundaries {
+            leading: 14.0,
+            trailing: 10.0,
+            ..bounds
+        };
+        assert_eq!(point_x_in_grapheme(13.0, &rtl_bounds), expected_curr);
+        assert_eq!(point_x_in_grapheme(11.0, &rtl_bounds), expected_next);
+        assert_eq!(point_x_in_grapheme(15.0, &rtl_bounds), None);
     }
 }
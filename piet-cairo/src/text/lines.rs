@@ -1,9 +1,51 @@
-use cairo::ScaledFont;
+use std::ops::Range;
+
+use piet::{util, LineHeight};
 use xi_unicode::LineBreakIterator;
 
+use super::runs::Runs;
 use super::LineMetric;
 
-pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64) -> Vec<LineMetric> {
+/// How the lines of a layout are spaced and indented.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ParagraphStyle {
+    pub(crate) line_height: LineHeight,
+    pub(crate) paragraph_spacing: f64,
+    pub(crate) first_line_indent: f64,
+    pub(crate) hanging_indent: f64,
+}
+
+impl ParagraphStyle {
+    /// The indent of a line that starts at `offset` in `text`.
+    pub(crate) fn indent(&self, text: &str, offset: usize) -> f64 {
+        if offset == 0 || util::trailing_nlf(&text[..offset]).is_some() {
+            self.first_line_indent
+        } else {
+            self.hanging_indent
+        }
+    }
+
+    /// The baseline and height of a line that covers `range`.
+    ///
+    /// The difference between the line height and the height of the fonts is
+    /// split evenly above and below the text.
+    pub(crate) fn line_extents(&self, runs: &Runs, range: Range<usize>) -> (f64, f64) {
+        let (baseline, natural_height) = runs.line_extents(range.clone());
+        let height = match self.line_height {
+            LineHeight::Normal => natural_height,
+            LineHeight::Absolute(height) => height,
+            LineHeight::Multiple(multiple) => multiple * runs.line_font_size(range),
+        };
+        (baseline + (height - natural_height) / 2.0, height)
+    }
+}
+
+pub(crate) fn calculate_line_metrics(
+    text: &str,
+    runs: &Runs,
+    width: f64,
+    style: &ParagraphStyle,
+) -> Vec<LineMetric> {
     // first pass, completely naive and inefficient. Check at every break to see if line longer
     // than width.
     //
@@ -24,28 +66,34 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
     // One word is considered the smallest unit, don't break below words for now.
     //
     // Use font extents height (it's different from text extents height,
-    // which relates to bounding box)
+    // which relates to bounding box). When a line has multiple runs, it is as tall as
+    // the largest ascent plus the largest descent.
     //
     // For baseline, use use `FontExtent.ascent`. Needs to be positive?
     // see https://glyphsapp.com/tutorials/vertical-metrics
     // https://stackoverflow.com/questions/27631736/meaning-of-top-ascent-baseline-descent-bottom-and-leading-in-androids-font
     // https://www.cairographics.org/manual/cairo-cairo-scaled-font-t.html#cairo-font-extents-t
-    let mut line_metrics = Vec::new();
+    //
+    // Indented lines have less room; `state.width()` is the room for the next
+    // line.
+    let mut state = LineState {
+        style,
+        y_offset: 0.0,
+        indent: style.first_line_indent,
+        line_metrics: Vec::new(),
+    };
     let mut line_start = 0;
     let mut prev_break = 0;
-    let mut y_offset = 0.0;
 
-    // vertical measures constant across all lines for now (cairo toy text)
-    let height = font.extents().height;
-    let baseline = font.extents().ascent;
-
-    for (line_break, is_hard_break) in LineBreakIterator::new(text) {
+    // placeholders are never broken
+    let breaks = LineBreakIterator::new(text)
+        .filter(|&(idx, is_hard_break)| is_hard_break || !runs.is_inside_placeholder(idx));
+    for (line_break, is_hard_break) in breaks {
         if !is_hard_break {
             // this section is for soft breaks
-            let curr_str = &text[line_start..line_break];
-            let curr_width = font.text_extents(curr_str).x_advance;
+            let curr_width = runs.advance(line_start..line_break);
 
-            if curr_width > width {
+            if curr_width > state.width(width) {
                 // since curr_width is longer than desired line width, it's time to break ending
                 // at the previous break.
 
@@ -60,15 +108,7 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                 }
 
                 // first do the line to prev break
-                add_line_metric(
-                    text,
-                    line_start,
-                    prev_break,
-                    baseline,
-                    height,
-                    &mut y_offset,
-                    &mut line_metrics,
-                );
+                add_line_metric(text, line_start, prev_break, runs, &mut state);
 
                 // Now handle the graphemes between prev_break and current break. The
                 // implementation depends on how we're treating a single line that's wider than
@@ -76,19 +116,10 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                 //
                 // If it's shorter than desired width, just continue.
 
-                let curr_str = &text[prev_break..line_break];
-                let curr_width = font.text_extents(curr_str).x_advance;
+                let curr_width = runs.advance(prev_break..line_break);
 
-                if curr_width > width {
-                    add_line_metric(
-                        text,
-                        prev_break,
-                        line_break,
-                        baseline,
-                        height,
-                        &mut y_offset,
-                        &mut line_metrics,
-                    );
+                if curr_width > state.width(width) {
+                    add_line_metric(text, prev_break, line_break, runs, &mut state);
 
                     line_start = line_break;
                     prev_break = line_break;
@@ -106,10 +137,9 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
 
             // even when there's a hard break, need to check first to see if width is too wide. If
             // it is, need to break at the previous soft break first.
-            let curr_str = &text[line_start..line_break];
-            let curr_width = font.text_extents(curr_str).x_advance;
+            let curr_width = runs.advance(line_start..line_break);
 
-            if curr_width > width {
+            if curr_width > state.width(width) {
                 // if line is too wide but can't break down anymore, just skip to the next
                 // add_line_metric. But here, since prev_break is not equal to line_start, that
                 // means there another break opportunity so take it.
@@ -117,30 +147,14 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
                 // TODO consider refactoring to make more parallel with above soft break
                 // comparison.
                 if prev_break != line_start {
-                    add_line_metric(
-                        text,
-                        line_start,
-                        prev_break,
-                        baseline,
-                        height,
-                        &mut y_offset,
-                        &mut line_metrics,
-                    );
+                    add_line_metric(text, line_start, prev_break, runs, &mut state);
 
                     line_start = prev_break;
                 }
             }
 
             // now do the hard break
-            add_line_metric(
-                text,
-                line_start,
-                line_break,
-                baseline,
-                height,
-                &mut y_offset,
-                &mut line_metrics,
-            );
+            add_line_metric(text, line_start, line_break, runs, &mut state);
             line_start = line_break;
             prev_break = line_break;
         }
@@ -148,31 +162,38 @@ pub(crate) fn calculate_line_metrics(text: &str, font: &ScaledFont, width: f64)
 
     // the trailing line, if there is no explicit newline.
     if line_start != text.len() {
-        add_line_metric(
-            text,
-            line_start,
-            text.len(),
-            baseline,
-            height,
-            &mut y_offset,
-            &mut line_metrics,
-        );
+        add_line_metric(text, line_start, text.len(), runs, &mut state);
     }
 
-    line_metrics
+    state.line_metrics
+}
+
+/// The lines we have broken so far, and where the next one goes.
+struct LineState<'a> {
+    style: &'a ParagraphStyle,
+    y_offset: f64,
+    /// The indent of the next line.
+    indent: f64,
+    line_metrics: Vec<LineMetric>,
+}
+
+impl<'a> LineState<'a> {
+    /// The room for the next line, within the layout's `max_width`.
+    fn width(&self, max_width: f64) -> f64 {
+        max_width - self.indent
+    }
 }
 
 fn add_line_metric(
     text: &str,
     start_offset: usize,
     end_offset: usize,
-    baseline: f64,
-    height: f64,
-    y_offset: &mut f64,
-    line_metrics: &mut Vec<LineMetric>,
+    runs: &Runs,
+    state: &mut LineState,
 ) {
     let line = &text[start_offset..end_offset];
     let trailing_whitespace = count_trailing_whitespace(line);
+    let (baseline, height) = state.style.line_extents(runs, start_offset..end_offset);
 
     let line_metric = LineMetric {
         start_offset,
@@ -180,16 +201,21 @@ fn add_line_metric(
         trailing_whitespace,
         baseline,
         height,
-        y_offset: *y_offset,
+        y_offset: state.y_offset,
     };
-    line_metrics.push(line_metric);
-    *y_offset += height;
+    state.line_metrics.push(line_metric);
+    state.y_offset += height;
+    state.indent = state.style.indent(text, end_offset);
+    if util::trailing_nlf(line).is_some() {
+        state.y_offset += state.style.paragraph_spacing;
+    }
 }
 
 // TODO: is non-breaking space trailing whitespace? Check with dwrite and
 // coretext
+/// The length of the trailing whitespace in `line`, in utf-8 code units.
 fn count_trailing_whitespace(line: &str) -> usize {
-    line.chars().rev().take_while(|c| c.is_whitespace()).count()
+    line.len() - line.trim_end().len()
 }
 
 #[cfg(test)]
@@ -203,7 +229,12 @@ mod test {
         input: &str,
         font: &ScaledFont,
     ) {
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let line_metrics = calculate_line_metrics(
+            input,
+            &Runs::simple(input, font.clone()),
+            width,
+            &ParagraphStyle::default(),
+        );
 
         for (i, (metric, exp)) in line_metrics.iter().zip(expected).enumerate() {
             println!("calculated: {:?}\nexpected: {:?}", metric, exp);
@@ -229,7 +260,12 @@ mod test {
         let width = 50.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let line_metrics = calculate_line_metrics(
+            input,
+            &Runs::simple(input, font.clone()),
+            width,
+            &ParagraphStyle::default(),
+        );
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
@@ -259,7 +295,12 @@ mod test {
         let width = 50.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(14.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let line_metrics = calculate_line_metrics(
+            input,
+            &Runs::simple(input, font.clone()),
+            width,
+            &ParagraphStyle::default(),
+        );
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
@@ -287,7 +328,12 @@ mod test {
         let width = 10.0;
 
         let font = CairoFont::new(FontFamily::SANS_SERIF).resolve_simple(12.0);
-        let line_metrics = calculate_line_metrics(input, &font, width);
+        let line_metrics = calculate_line_metrics(
+            input,
+            &Runs::simple(input, font.clone()),
+            width,
+            &ParagraphStyle::default(),
+        );
 
         // Some print debugging, in case font size/width needs to be changed in future because of
         // brittle tests
@@ -487,5 +533,7 @@ mod test {
         assert_eq!(count_trailing_whitespace(" 1 "), 1);
         assert_eq!(count_trailing_whitespace(" 2  "), 2);
         assert_eq!(count_trailing_whitespace(" 3  \n"), 3);
+        // U+3000 IDEOGRAPHIC SPACE is three bytes long
+        assert_eq!(count_trailing_whitespace("4\u{3000}"), 3);
     }
 }
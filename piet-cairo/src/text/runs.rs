@@ -0,0 +1,596 @@
+//! Attribute runs, and measuring text across them.
+
+use std::ops::Range;
+use std::rc::Rc;
+
+use cairo::ScaledFont;
+use piet::{
+    util, Color, FontFamily, FontFeature, FontStyle, FontWeight, Placeholder, UnderlineStyle,
+};
+use unic_bidi::Level;
+
+use super::bidi;
+use super::fonts;
+use super::shaping::{self, Cluster, ShapedGlyph};
+
+const ELLIPSIS: char = '\u{2026}';
+
+/// A range of text that is drawn with a single font and style.
+#[derive(Clone)]
+pub(crate) struct Run {
+    pub(crate) range: Range<usize>,
+    pub(crate) font: ScaledFont,
+    /// The family of `font`; this is a fallback family if the requested one
+    /// is missing some of the run's characters.
+    pub(crate) family: FontFamily,
+    pub(crate) weight: FontWeight,
+    pub(crate) style: FontStyle,
+    pub(crate) fg_color: Color,
+    pub(crate) background: Option<Color>,
+    pub(crate) decorations: Decorations,
+    /// How far the run's baseline is raised above the line's baseline.
+    pub(crate) baseline_offset: f64,
+    /// If set, the run is an empty box that replaces its text.
+    pub(crate) placeholder: Option<Placeholder>,
+    /// The run's embedding level, from the bidi algorithm.
+    pub(crate) level: Level,
+    /// In visual order.
+    glyphs: Vec<ShapedGlyph>,
+    /// In text order.
+    clusters: Vec<Cluster>,
+}
+
+/// Text that is hidden because it doesn't fit in a layout's maximum number of
+/// lines, along with the ellipsis that is drawn in its place.
+#[derive(Clone)]
+pub(crate) struct Elision {
+    /// The hidden range of the layout's text.
+    pub(crate) range: Range<usize>,
+    /// A run covering `range`, that draws the ellipsis.
+    pub(crate) ellipsis: Run,
+}
+
+/// The runs of a layout, in text order.
+///
+/// There is always at least one run, even if the text is empty; this gives us
+/// a font to measure empty lines with.
+#[derive(Clone)]
+pub(crate) struct Runs(Rc<[Run]>);
+
+/// A single line of a layout, for measurement, hit-testing and drawing.
+///
+/// Positions passed to methods on this type are relative to the start of the
+/// line.
+pub(crate) struct Line<'a> {
+    pub(crate) text: &'a str,
+    offset: usize,
+    alignment: LineAlignment,
+    /// The end of the line's content, before trailing whitespace, in the
+    /// layout's text.
+    content_end: usize,
+    slices: Vec<LineSlice<'a>>,
+}
+
+/// The part of a run that falls on a line, and where it goes.
+pub(crate) struct LineSlice<'a> {
+    pub(crate) run: &'a Run,
+    /// The range of the slice in the layout's text.
+    pub(crate) range: Range<usize>,
+    /// The level used to order the slice; this differs from the run's level
+    /// for whitespace at the end of a line.
+    level: Level,
+    /// The left edge of the slice, relative to the start of the line.
+    pub(crate) x: f64,
+    pub(crate) width: f64,
+}
+
+/// Where a line is placed horizontally, as a result of text alignment.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineAlignment {
+    pub(crate) x_offset: f64,
+    /// Extra space added to each space in the line, for justified text.
+    pub(crate) word_spacing: f64,
+}
+
+/// The lines drawn under, through and over a run.
+#[derive(Clone)]
+pub(crate) struct Decorations {
+    pub(crate) underline: Option<UnderlineStyle>,
+    pub(crate) strikethrough: bool,
+    pub(crate) overline: bool,
+    /// The color of the lines, if it isn't the text color.
+    pub(crate) color: Option<Color>,
+    /// The thickness of the lines, if it isn't the font's.
+    pub(crate) thickness: Option<f64>,
+}
+
+/// The position of decoration lines, relative to the baseline.
+///
+/// Offsets are to the center of the line, and positive values are below
+/// the baseline.
+pub(crate) struct DecorationMetrics {
+    pub(crate) underline_offset: f64,
+    pub(crate) strikethrough_offset: f64,
+    pub(crate) overline_offset: f64,
+    pub(crate) thickness: f64,
+}
+
+impl Runs {
+    pub(crate) fn new(runs: Vec<Run>) -> Self {
+        assert!(!runs.is_empty(), "layouts always have at least one run");
+        Runs(runs.into())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn simple(text: &str, font: ScaledFont) -> Self {
+        let attrs = util::LayoutDefaults::default();
+        Runs::new(vec![Run::new(
+            text,
+            0..text.len(),
+            font,
+            &attrs,
+            Level::ltr(),
+        )])
+    }
+
+    /// The runs that intersect `range`, along with the intersecting range.
+    pub(crate) fn slices(&self, range: Range<usize>) -> impl Iterator<Item = (Range<usize>, &Run)> {
+        self.0.iter().filter_map(move |run| {
+            let start = run.range.start.max(range.start);
+            let end = run.range.end.min(range.end);
+            if start < end {
+                Some((start..end, run))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The run containing `idx`, or the last run if `idx` is past the end.
+    pub(crate) fn run_at(&self, idx: usize) -> &Run {
+        self.0
+            .iter()
+            .find(|run| idx < run.range.end)
+            .unwrap_or_else(|| self.0.last().unwrap())
+    }
+
+    /// The horizontal advance of `range`.
+    pub(crate) fn advance(&self, range: Range<usize>) -> f64 {
+        self.slices(range)
+            .map(|(range, run)| run.advance(range))
+            .sum()
+    }
+
+    /// The baseline and height of a line that covers `range`.
+    ///
+    /// The line is as tall as its tallest ascent plus its deepest descent.
+    pub(crate) fn line_extents(&self, range: Range<usize>) -> (f64, f64) {
+        let mut ascent = 0f64;
+        let mut descent = 0f64;
+        for run in self.line_runs(range) {
+            let (run_ascent, run_descent) = match run.placeholder {
+                Some(placeholder) => (
+                    placeholder.baseline,
+                    placeholder.height - placeholder.baseline,
+                ),
+                None => {
+                    let extents = run.font.extents();
+                    // font extents height includes the line gap
+                    (extents.ascent, extents.height - extents.ascent)
+                }
+            };
+            ascent = ascent.max(run_ascent + run.baseline_offset);
+            descent = descent.max(run_descent - run.baseline_offset);
+        }
+        (ascent, ascent + descent)
+    }
+
+    /// Whether `idx` is strictly inside a placeholder, where lines can't
+    /// break.
+    pub(crate) fn is_inside_placeholder(&self, idx: usize) -> bool {
+        self.0
+            .iter()
+            .any(|run| run.placeholder.is_some() && run.range.start < idx && idx < run.range.end)
+    }
+
+    /// The largest font size on a line that covers `range`.
+    pub(crate) fn line_font_size(&self, range: Range<usize>) -> f64 {
+        self.line_runs(range)
+            .iter()
+            .map(|run| run.font.get_font_matrix().yy)
+            .fold(0.0, f64::max)
+    }
+
+    /// The runs on a line that covers `range`; an empty line uses the run it
+    /// is in.
+    fn line_runs(&self, range: Range<usize>) -> Vec<&Run> {
+        let mut runs: Vec<&Run> = self.slices(range.clone()).map(|(_, run)| run).collect();
+        if runs.is_empty() {
+            runs.push(self.run_at(range.start));
+        }
+        runs
+    }
+}
+
+impl Run {
+    /// Shape the run covering `range` of the layout's `text`.
+    ///
+    /// Runs are shaped once, as a whole; we don't reshape across line breaks.
+    pub(crate) fn new(
+        text: &str,
+        range: Range<usize>,
+        font: ScaledFont,
+        attrs: &util::LayoutDefaults,
+        level: Level,
+    ) -> Self {
+        let family = fonts::face_data(&font.get_font_face())
+            .and_then(|data| data.family_name())
+            .unwrap_or_else(|| attrs.font.clone());
+        if let Some(placeholder) = attrs.placeholder {
+            // a placeholder is a single cluster with no glyphs
+            let cluster = Cluster {
+                range: range.clone(),
+                advance: placeholder.width,
+            };
+            return Run {
+                range,
+                font,
+                family,
+                weight: attrs.weight,
+                style: attrs.style,
+                fg_color: attrs.fg_color.clone(),
+                background: attrs.background_color.clone(),
+                decorations: Decorations::new(attrs),
+                baseline_offset: attrs.baseline_shift.offset(attrs.font_size),
+                placeholder: Some(placeholder),
+                level,
+                glyphs: Vec::new(),
+                clusters: vec![cluster],
+            };
+        }
+        let text = &text[range.clone()];
+        let mut features = attrs.font_features.clone();
+        // as in CSS, letter spacing turns off optional ligatures, unless they
+        // were asked for explicitly.
+        if attrs.letter_spacing != 0.0 {
+            for tag in &[b"liga", b"clig", b"dlig", b"hlig"] {
+                if !features.iter().any(|f| &f.tag() == *tag) {
+                    features.push(FontFeature::new(tag, 0));
+                }
+            }
+        }
+        let mut shaped = shaping::shape(text, range.start, &font, level.is_rtl(), &features);
+        shaped.add_spacing(text, range.start, attrs.letter_spacing, attrs.word_spacing);
+        Run {
+            range,
+            font,
+            family,
+            weight: attrs.weight,
+            style: attrs.style,
+            fg_color: attrs.fg_color.clone(),
+            background: attrs.background_color.clone(),
+            decorations: Decorations::new(attrs),
+            baseline_offset: attrs.baseline_shift.offset(attrs.font_size),
+            placeholder: None,
+            level,
+            glyphs: shaped.glyphs,
+            clusters: shaped.clusters,
+        }
+    }
+
+    /// A run that draws an ellipsis in place of `range`, in the style of `run`.
+    ///
+    /// The whole of `range` is a single cluster, so positions inside it are
+    /// placed proportionally across the ellipsis.
+    pub(crate) fn ellipsis(run: &Run, range: Range<usize>, level: Level) -> Self {
+        let has_ellipsis = fonts::face_data(&run.font.get_font_face())
+            .map(|data| data.has_glyph(ELLIPSIS))
+            .unwrap_or(true);
+        let text = if has_ellipsis { "\u{2026}" } else { "..." };
+        let shaped = shaping::shape(text, 0, &run.font, level.is_rtl(), &[]);
+        let advance = shaped.clusters.iter().map(|cluster| cluster.advance).sum();
+        let glyphs = shaped
+            .glyphs
+            .into_iter()
+            .map(|glyph| ShapedGlyph {
+                cluster: range.start,
+                ..glyph
+            })
+            .collect();
+        Run {
+            range: range.clone(),
+            font: run.font.clone(),
+            family: run.family.clone(),
+            weight: run.weight,
+            style: run.style,
+            fg_color: run.fg_color.clone(),
+            background: run.background.clone(),
+            decorations: run.decorations.clone(),
+            baseline_offset: run.baseline_offset,
+            placeholder: None,
+            level,
+            glyphs,
+            clusters: vec![Cluster { range, advance }],
+        }
+    }
+
+    /// The horizontal advance of the whole run.
+    pub(crate) fn width(&self) -> f64 {
+        self.clusters.iter().map(|cluster| cluster.advance).sum()
+    }
+
+    /// The horizontal advance of `range`, which should be inside this run.
+    ///
+    /// A range that ends inside a cluster gets a share of the cluster's
+    /// advance in proportion to the number of bytes it covers; this is how
+    /// we place carets inside ligatures.
+    pub(crate) fn advance(&self, range: Range<usize>) -> f64 {
+        self.clusters
+            .iter()
+            .map(|cluster| {
+                let start = cluster.range.start.max(range.start);
+                let end = cluster.range.end.min(range.end);
+                if start >= end {
+                    0.0
+                } else if end - start == cluster.range.len() {
+                    cluster.advance
+                } else {
+                    cluster.advance * (end - start) as f64 / cluster.range.len() as f64
+                }
+            })
+            .sum()
+    }
+
+    pub(crate) fn decoration_metrics(&self) -> DecorationMetrics {
+        let size = self.font.get_font_matrix().yy;
+        let extents = self.font.extents();
+        // fonts we loaded ourselves know their metrics; the toy API doesn't
+        // expose them, so we approximate them.
+        let underline_metrics =
+            fonts::face_data(&self.font.get_font_face()).and_then(|data| data.underline_metrics());
+        let (underline_offset, thickness) = match underline_metrics {
+            Some((position, thickness)) => (-position * size, thickness * size),
+            None => (extents.descent / 2.0, size / 16.0),
+        };
+        let thickness = self.decorations.thickness.unwrap_or(thickness);
+        // halfway up the x-height
+        let x_height = -self.font.text_extents("x").y_bearing;
+        DecorationMetrics {
+            underline_offset,
+            strikethrough_offset: -x_height / 2.0,
+            // just inside the top of the ascent
+            overline_offset: -extents.ascent + thickness / 2.0,
+            thickness,
+        }
+    }
+}
+
+impl Decorations {
+    fn new(attrs: &util::LayoutDefaults) -> Self {
+        Decorations {
+            underline: Some(attrs.underline_style).filter(|_| attrs.underline),
+            strikethrough: attrs.strikethrough,
+            overline: attrs.overline,
+            color: attrs.decoration_color.clone(),
+            thickness: attrs.decoration_thickness,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.underline.is_none() && !self.strikethrough && !self.overline
+    }
+}
+
+impl<'a> Line<'a> {
+    /// `text` is the text of the line, which starts at `offset` in the layout,
+    /// in a paragraph with the base level `paragraph_level`.
+    ///
+    /// If the line contains hidden text, its `elision` is drawn in its place.
+    pub(crate) fn new(
+        text: &'a str,
+        offset: usize,
+        runs: &'a Runs,
+        alignment: LineAlignment,
+        paragraph_level: Level,
+        elision: Option<&'a Elision>,
+    ) -> Self {
+        let mut content_end = offset + text.trim_end().len();
+        if let Some(elision) = elision {
+            // the ellipsis is never trailing whitespace
+            content_end = content_end.max(elision.range.end);
+        }
+        let mut line = Line {
+            text,
+            offset,
+            alignment,
+            content_end,
+            slices: Vec::new(),
+        };
+        let mut pieces: Vec<(Range<usize>, &Run)> = Vec::new();
+        for (range, run) in runs.slices(offset..offset + text.len()) {
+            match elision {
+                Some(elision) => {
+                    let before = range.start..range.end.min(elision.range.start);
+                    let after = range.start.max(elision.range.end)..range.end;
+                    for range in [before, after].iter().filter(|r| r.start < r.end) {
+                        pieces.push((range.clone(), run));
+                    }
+                }
+                None => pieces.push((range, run)),
+            }
+        }
+        if let Some(elision) = elision {
+            let idx = pieces
+                .iter()
+                .position(|(range, _)| range.start >= elision.range.end)
+                .unwrap_or_else(|| pieces.len());
+            pieces.insert(idx, (elision.range.clone(), &elision.ellipsis));
+        }
+
+        for (range, run) in pieces {
+            // whitespace at the end of a line takes the paragraph's direction
+            // (rule L1 of the bidi algorithm)
+            let content_end = line.content_end.max(range.start).min(range.end);
+            let pieces = [
+                (range.start..content_end, run.level),
+                (content_end..range.end, paragraph_level),
+            ];
+            for (range, level) in pieces.iter().cloned() {
+                if range.start < range.end {
+                    let width = run.advance(range.clone()) + line.spacing(range.clone());
+                    line.slices.push(LineSlice {
+                        run,
+                        range,
+                        level,
+                        x: 0.0,
+                        width,
+                    });
+                }
+            }
+        }
+
+        let levels: Vec<Level> = line.slices.iter().map(|slice| slice.level).collect();
+        let mut x = alignment.x_offset;
+        for idx in bidi::visual_order(&levels) {
+            let slice = &mut line.slices[idx];
+            slice.x = x;
+            x += slice.width;
+        }
+        line
+    }
+
+    /// The width of the line without and with its trailing whitespace, not
+    /// counting the offset from alignment.
+    pub(crate) fn widths(&self) -> (f64, f64) {
+        let full_width: f64 = self.slices.iter().map(|slice| slice.width).sum();
+        let trailing_width: f64 = self
+            .slices
+            .iter()
+            .filter(|slice| slice.range.start >= self.content_end)
+            .map(|slice| slice.width)
+            .sum();
+        (full_width - trailing_width, full_width)
+    }
+
+    /// The pieces of the line, in text order.
+    pub(crate) fn slices(&self) -> &[LineSlice<'a>] {
+        &self.slices
+    }
+
+    /// The x position of the caret at `idx`, including the offset from
+    /// alignment.
+    pub(crate) fn x_of(&self, idx: usize) -> f64 {
+        let idx = self.offset + idx;
+        let slice = self
+            .slices
+            .iter()
+            .find(|slice| slice.range.contains(&idx))
+            .or_else(|| self.slices.last());
+        match slice {
+            Some(slice) => self.x_in_slice(slice, idx),
+            None => self.alignment.x_offset,
+        }
+    }
+
+    /// The slices of the line, as ranges relative to the start of the line,
+    /// with the x positions of their start and end.
+    ///
+    /// Positions only move in one direction within a slice.
+    pub(crate) fn visual_runs(&self) -> Vec<(Range<usize>, f64, f64)> {
+        self.slices
+            .iter()
+            .map(|slice| {
+                let range = slice.range.start - self.offset..slice.range.end - self.offset;
+                let start_x = self.x_in_slice(slice, slice.range.start);
+                let end_x = self.x_in_slice(slice, slice.range.end);
+                (range, start_x, end_x)
+            })
+            .collect()
+    }
+
+    /// The horizontal extents of `range`, from left to right.
+    ///
+    /// In mixed-direction text a range can be split into several pieces;
+    /// adjacent pieces are merged.
+    pub(crate) fn x_ranges(&self, range: Range<usize>) -> Vec<(f64, f64)> {
+        let range = self.offset + range.start..self.offset + range.end;
+        let mut pieces: Vec<(f64, f64)> = self
+            .slices
+            .iter()
+            .filter_map(|slice| {
+                let start = slice.range.start.max(range.start);
+                let end = slice.range.end.min(range.end);
+                if start >= end {
+                    return None;
+                }
+                let x0 = self.x_in_slice(slice, start);
+                let x1 = self.x_in_slice(slice, end);
+                Some((x0.min(x1), x0.max(x1)))
+            })
+            .collect();
+        pieces.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut merged: Vec<(f64, f64)> = Vec::with_capacity(pieces.len());
+        for (x0, x1) in pieces {
+            match merged.last_mut() {
+                Some(last) if x0 - last.1 < 1e-6 => last.1 = last.1.max(x1),
+                _ => merged.push((x0, x1)),
+            }
+        }
+        merged
+    }
+
+    /// The x position of `idx`, which is in the layout's text and inside
+    /// (or at the end of) `slice`.
+    fn x_in_slice(&self, slice: &LineSlice, idx: usize) -> f64 {
+        let before = slice.range.start..idx.min(slice.range.end);
+        let advance = slice.run.advance(before.clone()) + self.spacing(before);
+        if slice.run.level.is_rtl() {
+            slice.x + slice.width - advance
+        } else {
+            slice.x + advance
+        }
+    }
+
+    /// The glyphs of `slice`, each with its pen position relative to the start
+    /// of the line.
+    pub(crate) fn glyphs<'b>(
+        &'b self,
+        slice: &'b LineSlice<'a>,
+    ) -> impl Iterator<Item = (f64, &'b ShapedGlyph)> + 'b {
+        let mut x = slice.x;
+        slice
+            .run
+            .glyphs
+            .iter()
+            .filter(move |glyph| slice.range.contains(&glyph.cluster))
+            .map(move |glyph| {
+                let pen = x;
+                x += glyph.x_advance;
+                if self.is_stretched_space(glyph.cluster) {
+                    x += self.alignment.word_spacing;
+                }
+                (pen, glyph)
+            })
+    }
+
+    /// The extra space added to `range` by justification.
+    fn spacing(&self, range: Range<usize>) -> f64 {
+        if self.alignment.word_spacing == 0.0 {
+            return 0.0;
+        }
+        // trailing whitespace is never stretched
+        let end = range.end.min(self.content_end);
+        if range.start >= end {
+            return 0.0;
+        }
+        let text = &self.text[range.start - self.offset..end - self.offset];
+        text.matches(' ').count() as f64 * self.alignment.word_spacing
+    }
+
+    fn is_stretched_space(&self, idx: usize) -> bool {
+        self.alignment.word_spacing != 0.0
+            && idx < self.content_end
+            && self.text[idx - self.offset..].starts_with(' ')
+    }
+}
@@ -0,0 +1,303 @@
+//! Turning text into positioned glyphs.
+
+use std::ops::Range;
+
+use cairo::ScaledFont;
+use piet::FontFeature;
+use rustybuzz::{Direction, Feature, Tag, UnicodeBuffer, Variation};
+
+use super::fonts::{self, FontData};
+
+/// A glyph, positioned relative to the pen.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ShapedGlyph {
+    pub(crate) id: u32,
+    /// The start of the glyph's cluster, in the layout's text.
+    pub(crate) cluster: usize,
+    pub(crate) x_advance: f64,
+    pub(crate) x_offset: f64,
+    /// Positive values are above the baseline.
+    pub(crate) y_offset: f64,
+}
+
+/// The smallest range of text that maps to a whole number of glyphs.
+///
+/// Ligatures and complex scripts can map several characters to one glyph, or
+/// several glyphs to one character; we can only position the text between
+/// clusters exactly.
+#[derive(Debug, Clone)]
+pub(crate) struct Cluster {
+    pub(crate) range: Range<usize>,
+    pub(crate) advance: f64,
+}
+
+pub(crate) struct ShapedText {
+    /// The glyphs to draw, in visual (left to right) order.
+    pub(crate) glyphs: Vec<ShapedGlyph>,
+    /// Every cluster, in text order.
+    pub(crate) clusters: Vec<Cluster>,
+}
+
+impl ShapedText {
+    /// Add `letter_spacing` after every cluster, and `word_spacing` after
+    /// every space as well.
+    ///
+    /// `text` is the text that was shaped, which starts at `offset` in the
+    /// layout.
+    pub(crate) fn add_spacing(
+        &mut self,
+        text: &str,
+        offset: usize,
+        letter_spacing: f64,
+        word_spacing: f64,
+    ) {
+        if letter_spacing == 0.0 && word_spacing == 0.0 {
+            return;
+        }
+        for cluster in &mut self.clusters {
+            let cluster_text = &text[cluster.range.start - offset..cluster.range.end - offset];
+            if cluster_text.starts_with(char::is_control) {
+                continue;
+            }
+            let mut spacing = letter_spacing;
+            if is_word_separator(cluster_text) {
+                spacing += word_spacing;
+            }
+            cluster.advance += spacing;
+            // the space goes after the cluster's last glyph
+            let glyph = self
+                .glyphs
+                .iter_mut()
+                .rev()
+                .find(|glyph| cluster.range.contains(&glyph.cluster));
+            if let Some(glyph) = glyph {
+                glyph.x_advance += spacing;
+            }
+        }
+    }
+}
+
+/// Whether `cluster` gets word spacing; as in CSS, this is only the space
+/// and no-break space characters.
+fn is_word_separator(cluster: &str) -> bool {
+    cluster == " " || cluster == "\u{00A0}"
+}
+
+/// Shape `text`, which starts at `offset` in the layout, with `font`.
+///
+/// The whole of `text` has the same direction; mixed-direction text is split
+/// into runs by the bidi algorithm first.
+///
+/// Fonts that we loaded ourselves are shaped with HarfBuzz (by way of
+/// rustybuzz); faces from cairo's toy API can only be shaped by cairo, which
+/// doesn't handle complex scripts or font features.
+pub(crate) fn shape(
+    text: &str,
+    offset: usize,
+    font: &ScaledFont,
+    rtl: bool,
+    features: &[FontFeature],
+) -> ShapedText {
+    let shaped = fonts::face_data(&font.get_font_face())
+        .and_then(|data| shape_with_harfbuzz(text, font, &data, rtl, features));
+    let mut glyphs = shaped.unwrap_or_else(|| shape_with_cairo(text, font, rtl));
+
+    // control characters such as newlines take up no space, and shouldn't
+    // be drawn as missing glyphs.
+    for glyph in &mut glyphs {
+        if text[glyph.cluster..].starts_with(char::is_control) {
+            glyph.x_advance = 0.0;
+        }
+        glyph.cluster += offset;
+    }
+    let clusters = clusters(&glyphs, offset..offset + text.len());
+    glyphs.retain(|glyph| !text[glyph.cluster - offset..].starts_with(char::is_control));
+
+    ShapedText { glyphs, clusters }
+}
+
+fn shape_with_harfbuzz(
+    text: &str,
+    font: &ScaledFont,
+    data: &FontData,
+    rtl: bool,
+    features: &[FontFeature],
+) -> Option<Vec<ShapedGlyph>> {
+    // the face is cheap to clone, and each font can have its own variations
+    let mut face = data.shaping_face()?.clone();
+    face.set_variations(&variations(font));
+    let units_per_em = data.units_per_em();
+    if units_per_em <= 0.0 {
+        return None;
+    }
+    let matrix = font.get_font_matrix();
+    let x_scale = matrix.xx / units_per_em;
+    let y_scale = matrix.yy / units_per_em;
+    // cairo widens emboldened glyphs by the same amount as FreeType
+    let embolden = if data.synthesis().bold {
+        matrix.yy / 24.0
+    } else {
+        0.0
+    };
+
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(text);
+    buffer.guess_segment_properties();
+    buffer.set_direction(if rtl {
+        Direction::RightToLeft
+    } else {
+        Direction::LeftToRight
+    });
+
+    let features: Vec<Feature> = features
+        .iter()
+        .map(|f| Feature::new(Tag::from_bytes(&f.tag()), f.value(), ..))
+        .collect();
+    let output = rustybuzz::shape(&face, &features, buffer);
+    let glyphs = output
+        .glyph_infos()
+        .iter()
+        .zip(output.glyph_positions())
+        .map(|(info, pos)| ShapedGlyph {
+            id: info.codepoint,
+            cluster: info.cluster as usize,
+            x_advance: match pos.x_advance {
+                0 => 0.0,
+                advance => advance as f64 * x_scale + embolden,
+            },
+            x_offset: pos.x_offset as f64 * x_scale,
+            y_offset: pos.y_offset as f64 * y_scale,
+        })
+        .collect();
+    Some(glyphs)
+}
+
+/// The variations cairo will draw `font` with, such as the weight of a
+/// variable font.
+fn variations(font: &ScaledFont) -> Vec<Variation> {
+    font.get_font_options()
+        .get_variations()
+        .map(|variations| {
+            variations
+                .split(',')
+                .filter_map(|variation| variation.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Shape with cairo's built-in shaper, which maps characters to glyphs one
+/// to one and lays them out left to right.
+///
+/// Right-to-left text is handled by reversing the glyphs, which is good
+/// enough for scripts that don't need shaping.
+fn shape_with_cairo(text: &str, font: &ScaledFont, rtl: bool) -> Vec<ShapedGlyph> {
+    let (glyphs, text_clusters) = font.text_to_glyphs(0.0, 0.0, text);
+    let mut cluster_starts = Vec::with_capacity(glyphs.len());
+    let mut cluster_start = 0;
+    for cluster in &text_clusters {
+        for _ in 0..cluster.num_glyphs {
+            cluster_starts.push(cluster_start);
+        }
+        cluster_start += cluster.num_bytes as usize;
+    }
+
+    // cairo gives us absolute positions; we want advances.
+    let total_advance = font.text_extents(text).x_advance;
+    let mut shaped: Vec<ShapedGlyph> = glyphs
+        .iter()
+        .enumerate()
+        .map(|(i, glyph)| {
+            let next_x = glyphs.get(i + 1).map(|g| g.x).unwrap_or(total_advance);
+            ShapedGlyph {
+                id: glyph.index as u32,
+                cluster: cluster_starts.get(i).copied().unwrap_or(0),
+                x_advance: next_x - glyph.x,
+                x_offset: 0.0,
+                y_offset: -glyph.y,
+            }
+        })
+        .collect();
+    if rtl {
+        shaped.reverse();
+    }
+    shaped
+}
+
+/// Group glyphs into clusters that cover all of `range`.
+fn clusters(glyphs: &[ShapedGlyph], range: Range<usize>) -> Vec<Cluster> {
+    let mut starts: Vec<usize> = glyphs.iter().map(|g| g.cluster).collect();
+    starts.sort_unstable();
+    starts.dedup();
+    if starts.is_empty() {
+        return Vec::new();
+    }
+    // any text before the first cluster belongs to it
+    let first_cluster = starts[0];
+    starts[0] = range.start;
+
+    let ends = starts.iter().skip(1).copied().chain(Some(range.end));
+    let mut clusters: Vec<Cluster> = starts
+        .iter()
+        .zip(ends)
+        .map(|(&start, end)| Cluster {
+            range: start..end,
+            advance: 0.0,
+        })
+        .collect();
+    for glyph in glyphs {
+        let cluster = if glyph.cluster == first_cluster {
+            0
+        } else {
+            match clusters.binary_search_by_key(&glyph.cluster, |c| c.range.start) {
+                Ok(idx) => idx,
+                Err(idx) => idx - 1,
+            }
+        };
+        clusters[cluster].advance += glyph.x_advance;
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn glyph(cluster: usize, x_advance: f64) -> ShapedGlyph {
+        ShapedGlyph {
+            id: 0,
+            cluster,
+            x_advance,
+            x_offset: 0.0,
+            y_offset: 0.0,
+        }
+    }
+
+    #[test]
+    fn clusters_cover_text() {
+        // right-to-left text, where the first four bytes make a cluster of
+        // two glyphs and the last two bytes make a cluster of one.
+        let glyphs = [glyph(4, 5.0), glyph(0, 3.0), glyph(0, 1.0)];
+        let clusters = clusters(&glyphs, 0..6);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].range, 0..4);
+        assert_eq!(clusters[1].range, 4..6);
+        assert!((clusters[0].advance - 4.0).abs() < 1e-9);
+        assert!((clusters[1].advance - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn spacing() {
+        let text = "a b\n";
+        let glyphs = vec![glyph(10, 5.0), glyph(11, 2.0), glyph(12, 5.0)];
+        let mut shaped = ShapedText {
+            clusters: clusters(&glyphs, 10..14),
+            glyphs,
+        };
+        shaped.add_spacing(text, 10, 1.0, 3.0);
+        let advances: Vec<f64> = shaped.glyphs.iter().map(|g| g.x_advance).collect();
+        assert_eq!(advances, vec![6.0, 6.0, 6.0]);
+        let total: f64 = shaped.clusters.iter().map(|c| c.advance).sum();
+        assert!((total - 18.0).abs() < 1e-9);
+    }
+}
@@ -38,7 +38,10 @@ use core_text::{
 use foreign_types::ForeignType;
 
 use piet::kurbo::{Affine, Rect};
-use piet::{util, Color, FontFamily, FontFamilyInner, TextAlignment};
+use piet::{
+    util, Color, FontFamily, FontFamilyInner, LineHeight, Placeholder, TextAlignment,
+    TextDirection, UnderlineStyle,
+};
 
 #[derive(Clone)]
 pub(crate) struct AttributedString {
@@ -70,16 +73,76 @@ impl_TCFType!(
     CTParagraphStyleGetTypeID
 );
 
+pub enum __CTRunDelegate {}
+type CTRunDelegateRef = *const __CTRunDelegate;
+
+declare_TCFType!(CTRunDelegate, CTRunDelegateRef);
+impl_TCFType!(CTRunDelegate, CTRunDelegateRef, CTRunDelegateGetTypeID);
+
+/// The callbacks Core Text uses to measure a run delegate; `ref_con` is a
+/// boxed `Placeholder`.
+#[repr(C)]
+struct CTRunDelegateCallbacks {
+    version: CFIndex,
+    dealloc: extern "C" fn(ref_con: *mut c_void),
+    get_ascent: extern "C" fn(ref_con: *mut c_void) -> CGFloat,
+    get_descent: extern "C" fn(ref_con: *mut c_void) -> CGFloat,
+    get_width: extern "C" fn(ref_con: *mut c_void) -> CGFloat,
+}
+
+const K_CT_RUN_DELEGATE_VERSION_1: CFIndex = 1;
+
+static PLACEHOLDER_CALLBACKS: CTRunDelegateCallbacks = CTRunDelegateCallbacks {
+    version: K_CT_RUN_DELEGATE_VERSION_1,
+    dealloc: placeholder_dealloc,
+    get_ascent: placeholder_ascent,
+    get_descent: placeholder_descent,
+    get_width: placeholder_width,
+};
+
+extern "C" fn placeholder_dealloc(ref_con: *mut c_void) {
+    unsafe { drop(Box::from_raw(ref_con as *mut Placeholder)) }
+}
+
+extern "C" fn placeholder_ascent(ref_con: *mut c_void) -> CGFloat {
+    let placeholder = unsafe { &*(ref_con as *const Placeholder) };
+    placeholder.baseline
+}
+
+extern "C" fn placeholder_descent(ref_con: *mut c_void) -> CGFloat {
+    let placeholder = unsafe { &*(ref_con as *const Placeholder) };
+    placeholder.height - placeholder.baseline
+}
+
+extern "C" fn placeholder_width(ref_con: *mut c_void) -> CGFloat {
+    let placeholder = unsafe { &*(ref_con as *const Placeholder) };
+    placeholder.width
+}
+
 #[repr(u32)]
 enum CTParagraphStyleSpecifier {
     Alignment = 0,
-    //FirstLineHeadIndent = 1,
-    //HeadIndent = 2,
+    FirstLineHeadIndent = 1,
+    HeadIndent = 2,
     //TailIndent = 3,
     //TabStops = 4,
     //TabInterval = 5,
     //LineBreakMode = 6,
+    //LineHeightMultiple = 7,
+    MaximumLineHeight = 8,
+    MinimumLineHeight = 9,
+    ParagraphSpacing = 11,
     // there are many more of these
+    BaseWritingDirection = 13,
+}
+
+/// The parts of a paragraph style that affect the position of lines.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ParagraphSpacing {
+    pub(crate) line_height: LineHeight,
+    pub(crate) paragraph_spacing: f64,
+    pub(crate) first_line_indent: f64,
+    pub(crate) hanging_indent: f64,
 }
 
 #[repr(u8)]
@@ -91,6 +154,13 @@ enum CTTextAlignment {
     Natural = 4,
 }
 
+#[repr(i8)]
+enum CTWritingDirection {
+    Natural = -1,
+    LeftToRight = 0,
+    RightToLeft = 1,
+}
+
 #[repr(C)]
 struct CTParagraphStyleSetting {
     spec: CTParagraphStyleSpecifier,
@@ -99,6 +169,15 @@ struct CTParagraphStyleSetting {
 }
 
 impl CTParagraphStyleSetting {
+    /// A setting with a `CGFloat` value; `value` must outlive the setting.
+    fn float(spec: CTParagraphStyleSpecifier, value: &CGFloat) -> Self {
+        CTParagraphStyleSetting {
+            spec,
+            value: value as *const CGFloat as *const c_void,
+            value_size: std::mem::size_of::<CGFloat>(),
+        }
+    }
+
     fn alignment(alignment: TextAlignment, is_rtl: bool) -> Self {
         static LEFT: CTTextAlignment = CTTextAlignment::Left;
         static RIGHT: CTTextAlignment = CTTextAlignment::Right;
@@ -120,6 +199,24 @@ impl CTParagraphStyleSetting {
             value_size: std::mem::size_of::<CTTextAlignment>(),
         }
     }
+
+    fn base_writing_direction(direction: TextDirection) -> Self {
+        static NATURAL: CTWritingDirection = CTWritingDirection::Natural;
+        static LTR: CTWritingDirection = CTWritingDirection::LeftToRight;
+        static RTL: CTWritingDirection = CTWritingDirection::RightToLeft;
+
+        let direction: *const CTWritingDirection = match direction {
+            TextDirection::Auto => &NATURAL,
+            TextDirection::LeftToRight => &LTR,
+            TextDirection::RightToLeft => &RTL,
+        };
+
+        CTParagraphStyleSetting {
+            spec: CTParagraphStyleSpecifier::BaseWritingDirection,
+            value: direction as *const c_void,
+            value_size: std::mem::size_of::<CTWritingDirection>(),
+        }
+    }
 }
 
 impl AttributedString {
@@ -132,11 +229,55 @@ impl AttributedString {
         AttributedString { inner, rtl }
     }
 
-    pub(crate) fn set_alignment(&mut self, alignment: TextAlignment) {
-        let alignment = CTParagraphStyleSetting::alignment(alignment, self.rtl);
-        let settings = [alignment];
+    /// `font_size` is the default font size, which line heights that are a
+    /// multiple of the font size are relative to.
+    pub(crate) fn set_paragraph_style(
+        &mut self,
+        alignment: TextAlignment,
+        direction: TextDirection,
+        spacing: ParagraphSpacing,
+        font_size: f64,
+    ) {
+        let rtl = match direction {
+            TextDirection::Auto => self.rtl,
+            TextDirection::LeftToRight => false,
+            TextDirection::RightToLeft => true,
+        };
+        let mut settings = vec![
+            CTParagraphStyleSetting::alignment(alignment, rtl),
+            CTParagraphStyleSetting::base_writing_direction(direction),
+            CTParagraphStyleSetting::float(
+                CTParagraphStyleSpecifier::ParagraphSpacing,
+                &spacing.paragraph_spacing,
+            ),
+            CTParagraphStyleSetting::float(
+                CTParagraphStyleSpecifier::FirstLineHeadIndent,
+                &spacing.first_line_indent,
+            ),
+            CTParagraphStyleSetting::float(
+                CTParagraphStyleSpecifier::HeadIndent,
+                &spacing.hanging_indent,
+            ),
+        ];
+        // Core Text's line height multiple is relative to the natural line
+        // height, so we turn multiples of the font size into fixed heights.
+        let line_height = match spacing.line_height {
+            LineHeight::Normal => None,
+            LineHeight::Absolute(height) => Some(height),
+            LineHeight::Multiple(multiple) => Some(multiple * font_size),
+        };
+        if let Some(line_height) = line_height.as_ref() {
+            settings.push(CTParagraphStyleSetting::float(
+                CTParagraphStyleSpecifier::MinimumLineHeight,
+                line_height,
+            ));
+            settings.push(CTParagraphStyleSetting::float(
+                CTParagraphStyleSpecifier::MaximumLineHeight,
+                line_height,
+            ));
+        }
         unsafe {
-            let style = CTParagraphStyleCreate(settings.as_ptr(), 1);
+            let style = CTParagraphStyleCreate(settings.as_ptr(), settings.len());
             let style = CTParagraphStyle::wrap_under_create_rule(style);
             self.inner.set_attribute(
                 self.range(),
@@ -153,15 +294,22 @@ impl AttributedString {
         }
     }
 
+    /// Set the underline style of a range; Core Text has no wavy underlines,
+    /// so those are drawn as single lines.
     #[allow(non_upper_case_globals)]
-    pub(crate) fn set_underline(&mut self, range: CFRange, underline: bool) {
+    pub(crate) fn set_underline(&mut self, range: CFRange, underline: Option<UnderlineStyle>) {
         const kCTUnderlineStyleNone: i32 = 0x00;
         const kCTUnderlineStyleSingle: i32 = 0x01;
-
-        let value = if underline {
-            kCTUnderlineStyleSingle
-        } else {
-            kCTUnderlineStyleNone
+        const kCTUnderlineStyleDouble: i32 = 0x09;
+        const kCTUnderlinePatternDot: i32 = 0x0100;
+        const kCTUnderlinePatternDash: i32 = 0x0200;
+
+        let value = match underline {
+            None => kCTUnderlineStyleNone,
+            Some(UnderlineStyle::Single) | Some(UnderlineStyle::Wavy) => kCTUnderlineStyleSingle,
+            Some(UnderlineStyle::Double) => kCTUnderlineStyleDouble,
+            Some(UnderlineStyle::Dotted) => kCTUnderlineStyleSingle | kCTUnderlinePatternDot,
+            Some(UnderlineStyle::Dashed) => kCTUnderlineStyleSingle | kCTUnderlinePatternDash,
         };
         unsafe {
             self.inner.set_attribute(
@@ -172,6 +320,73 @@ impl AttributedString {
         }
     }
 
+    /// Set the space added after each character; this is what Core Text calls
+    /// kerning.
+    pub(crate) fn set_kern(&mut self, range: CFRange, kern: f64) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                string_attributes::kCTKernAttributeName,
+                &CFNumber::from(kern).as_CFType(),
+            )
+        }
+    }
+
+    /// Raise the baseline of a range by `offset`; negative values lower it.
+    pub(crate) fn set_baseline_offset(&mut self, range: CFRange, offset: f64) {
+        unsafe {
+            self.inner.set_attribute(
+                range,
+                kCTBaselineOffsetAttributeName,
+                &CFNumber::from(offset).as_CFType(),
+            )
+        }
+    }
+
+    /// Replace the glyphs in a range with a single empty box the size of
+    /// `placeholder`.
+    ///
+    /// Each glyph gets its own box, so the box is given to the first character,
+    /// which is `first_len` utf-16 code units long, and the rest of the range
+    /// gets boxes with no width.
+    pub(crate) fn set_placeholder(
+        &mut self,
+        range: CFRange,
+        first_len: isize,
+        placeholder: Placeholder,
+    ) {
+        let first = CFRange::init(range.location, first_len);
+        let rest = CFRange::init(range.location + first_len, range.length - first_len);
+        self.set_run_delegate(first, placeholder);
+        if rest.length > 0 {
+            let empty = Placeholder {
+                width: 0.0,
+                ..placeholder
+            };
+            self.set_run_delegate(rest, empty);
+        }
+    }
+
+    fn set_run_delegate(&mut self, range: CFRange, placeholder: Placeholder) {
+        unsafe {
+            // the delegate owns the box, and frees it in its dealloc callback
+            let ref_con = Box::into_raw(Box::new(placeholder)) as *mut c_void;
+            let delegate = CTRunDelegateCreate(&PLACEHOLDER_CALLBACKS, ref_con);
+            let delegate = CTRunDelegate::wrap_under_create_rule(delegate);
+            self.inner
+                .set_attribute(range, kCTRunDelegateAttributeName, &delegate)
+        }
+    }
+
+    pub(crate) fn set_underline_color(&mut self, range: CFRange, color: &Color) {
+        let (r, g, b, a) = color.as_rgba();
+        let color = CGColor::rgb(r, g, b, a);
+        unsafe {
+            self.inner
+                .set_attribute(range, kCTUnderlineColorAttributeName, &color.as_CFType())
+        }
+    }
+
     pub(crate) fn set_fg_color(&mut self, range: CFRange, color: &Color) {
         let (r, g, b, a) = color.as_rgba();
         let color = CGColor::rgb(r, g, b, a);
@@ -385,6 +600,9 @@ pub(crate) fn make_font(desc: &CTFontDescriptor, pt_size: f64, affine: Affine) -
 #[link(name = "CoreText", kind = "framework")]
 extern "C" {
     static kCTFontFamilyNameKey: CFStringRef;
+    static kCTBaselineOffsetAttributeName: CFStringRef;
+    static kCTUnderlineColorAttributeName: CFStringRef;
+    static kCTRunDelegateAttributeName: CFStringRef;
 
     pub static kCTFontVariationAxisIdentifierKey: CFStringRef;
     //static kCTFontVariationAxisMinimumValueKey: CFStringRef;
@@ -398,6 +616,11 @@ extern "C" {
         language: CFStringRef,
     ) -> CTFontRef;
     fn CTParagraphStyleGetTypeID() -> CFTypeID;
+    fn CTRunDelegateGetTypeID() -> CFTypeID;
+    fn CTRunDelegateCreate(
+        callbacks: *const CTRunDelegateCallbacks,
+        ref_con: *mut c_void,
+    ) -> CTRunDelegateRef;
     fn CTParagraphStyleCreate(
         settings: *const CTParagraphStyleSetting,
         count: usize,
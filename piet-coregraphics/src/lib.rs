@@ -24,7 +24,7 @@ use piet::kurbo::{Affine, PathEl, Point, QuadBez, Rect, Shape, Size};
 use piet::util::unpremul;
 use piet::{
     Color, Error, FixedGradient, Image, ImageFormat, InterpolationMode, IntoBrush, LineCap,
-    LineJoin, RenderContext, RoundInto, StrokeStyle,
+    LineJoin, RenderContext, RoundInto, StrokeStyle, TextLayout,
 };
 
 pub use crate::text::{CoreGraphicsText, CoreGraphicsTextLayout, CoreGraphicsTextLayoutBuilder};
@@ -241,6 +241,12 @@ impl<'a> RenderContext for CoreGraphicsContext<'a> {
 
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
         let pos = pos.into();
+        for (range, color) in layout.backgrounds.iter() {
+            let brush = self.solid_brush(color.clone());
+            for rect in layout.rects_for_range(range.clone()) {
+                self.fill(rect + pos.to_vec2(), &brush);
+            }
+        }
         self.ctx.save();
         // inverted coordinate system; text is drawn from bottom left corner,
         // and (0, 0) in context is also bottom left.
@@ -24,11 +24,14 @@ use core_text::{
 
 use piet::kurbo::{Affine, Point, Rect, Size};
 use piet::{
-    util, Error, FontFamily, FontStyle, FontWeight, HitTestPoint, HitTestPosition, LineMetric,
-    Text, TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
+    util, Affinity, BaselineShift, Color, Error, FontFamily, FontStyle, FontWeight, HitTestPoint,
+    HitTestPosition, LineHeight, LineMetric, Placeholder, Text, TextAlignment, TextAttribute,
+    TextDirection, TextLayout, TextLayoutBuilder, TextStorage,
 };
 
-use crate::ct_helpers::{self, AttributedString, FontCollection, Frame, Framesetter, Line};
+use crate::ct_helpers::{
+    self, AttributedString, FontCollection, Frame, Framesetter, Line, ParagraphSpacing,
+};
 
 #[derive(Clone)]
 pub struct CoreGraphicsText {
@@ -69,12 +72,20 @@ pub struct CoreGraphicsTextLayout {
     line_metrics: Rc<[LineMetric]>,
     x_offsets: Rc<[f64]>,
     trailing_ws_width: f64,
+    /// Core Text doesn't draw backgrounds, so we fill them ourselves.
+    pub(crate) backgrounds: Rc<[(Range<usize>, Color)]>,
+    placeholders: Rc<[(Range<usize>, Placeholder)]>,
 }
 
 /// Building text layouts for `CoreGraphics`.
+///
+/// The maximum number of lines and the overflow mode are not supported yet;
+/// all of the text is laid out.
 pub struct CoreGraphicsTextLayoutBuilder {
     width: f64,
     alignment: TextAlignment,
+    direction: TextDirection,
+    spacing: ParagraphSpacing,
     text: Rc<dyn TextStorage>,
     /// the end bound up to which we have already added attrs to our AttributedString
     last_resolved_pos: usize,
@@ -87,6 +98,8 @@ pub struct CoreGraphicsTextLayoutBuilder {
     default_baseline: f64,
     default_line_height: f64,
     attrs: Attributes,
+    backgrounds: Vec<(Range<usize>, Color)>,
+    placeholders: Vec<(Range<usize>, Placeholder)>,
 }
 
 /// A helper type for storing and resolving attributes
@@ -97,6 +110,7 @@ struct Attributes {
     size: Option<Span<f64>>,
     weight: Option<Span<FontWeight>>,
     style: Option<Span<FontStyle>>,
+    shift: Option<Span<BaselineShift>>,
 }
 
 /// during construction, `Span`s represent font attributes that have been applied
@@ -136,12 +150,26 @@ impl CoreGraphicsTextLayoutBuilder {
     /// of the relevant types is added, we know that spans in the string up to
     /// the start of the newly added span can no longer be changed, and we can resolve them.
     fn add(&mut self, attr: TextAttribute, range: Range<usize>) {
+        if let TextAttribute::BackgroundColor(color) = attr {
+            return self.backgrounds.push((range, color));
+        }
         if !self.has_set_default_attrs {
             self.set_default_attrs();
         }
+        if let TextAttribute::Placeholder(placeholder) = attr {
+            self.placeholders.push((range.clone(), placeholder));
+        }
         // Some attributes are 'standalone' and can just be added to the attributed string
         // immediately.
-        if matches!(&attr, TextAttribute::TextColor(_) | TextAttribute::Underline(_)) {
+        if matches!(
+            &attr,
+            TextAttribute::TextColor(_)
+                | TextAttribute::Underline(_)
+                | TextAttribute::UnderlineStyle(_)
+                | TextAttribute::DecorationColor(_)
+                | TextAttribute::LetterSpacing(_)
+                | TextAttribute::Placeholder(_)
+        ) {
             return self.add_immediately(attr, range);
         }
 
@@ -168,19 +196,44 @@ impl CoreGraphicsTextLayoutBuilder {
         self.attr_string.set_font(whole_range, &font);
         self.attr_string
             .set_fg_color(whole_range, &self.attrs.defaults.fg_color);
-        self.attr_string
-            .set_underline(whole_range, self.attrs.defaults.underline);
+        let defaults = &self.attrs.defaults;
+        let underline = Some(defaults.underline_style).filter(|_| defaults.underline);
+        self.attr_string.set_underline(whole_range, underline);
+        if let Some(color) = &defaults.decoration_color {
+            self.attr_string.set_underline_color(whole_range, color);
+        }
+        if self.attrs.defaults.letter_spacing != 0.0 {
+            self.attr_string
+                .set_kern(whole_range, self.attrs.defaults.letter_spacing);
+        }
     }
 
     fn add_immediately(&mut self, attr: TextAttribute, range: Range<usize>) {
         let utf16_start = util::count_utf16(&self.text[..range.start]);
-        let utf16_len = util::count_utf16(&self.text[range]);
+        let text = &self.text[range];
+        let utf16_len = util::count_utf16(text);
         let range = CFRange::init(utf16_start as isize, utf16_len as isize);
         match attr {
             TextAttribute::TextColor(color) => {
                 self.attr_string.set_fg_color(range, &color);
             }
-            TextAttribute::Underline(flag) => self.attr_string.set_underline(range, flag),
+            TextAttribute::Underline(flag) => {
+                let style = Some(self.attrs.defaults.underline_style).filter(|_| flag);
+                self.attr_string.set_underline(range, style);
+            }
+            TextAttribute::UnderlineStyle(style) => {
+                self.attr_string.set_underline(range, Some(style))
+            }
+            // Core Text only colors underlines.
+            TextAttribute::DecorationColor(color) => {
+                self.attr_string.set_underline_color(range, &color)
+            }
+            TextAttribute::LetterSpacing(spacing) => self.attr_string.set_kern(range, spacing),
+            TextAttribute::Placeholder(placeholder) => {
+                let first_len = first_char_len_utf16(text);
+                self.attr_string
+                    .set_placeholder(range, first_len, placeholder)
+            }
             _ => unreachable!(),
         }
     }
@@ -210,6 +263,11 @@ impl CoreGraphicsTextLayoutBuilder {
                         &font,
                     );
                 }
+                let shift = self.attrs.baseline_shift();
+                if shift != BaselineShift::None {
+                    let offset = shift.offset(self.attrs.size());
+                    self.attr_string.set_baseline_offset(range, offset);
+                }
                 self.last_resolved_pos = next_span_end;
                 self.last_resolved_utf16 += range_end_utf16;
                 self.update_after_adding_span();
@@ -245,7 +303,8 @@ impl CoreGraphicsTextLayoutBuilder {
         unsafe {
             let family_key =
                 CFString::wrap_under_create_rule(font_descriptor::kCTFontFamilyNameAttribute);
-            let family_name = ct_helpers::ct_family_name(self.attrs.font(), self.attrs.size());
+            let size = self.attrs.size() * self.attrs.baseline_shift().scale();
+            let family_name = ct_helpers::ct_family_name(self.attrs.font(), size);
             let weight_key = CFString::wrap_under_create_rule(font_descriptor::kCTFontWeightTrait);
             let weight = convert_to_coretext(self.attrs.weight());
 
@@ -265,7 +324,7 @@ impl CoreGraphicsTextLayoutBuilder {
                 (traits_key, traits.as_CFType()),
             ]);
             let descriptor = font_descriptor::new_from_attributes(&attributes);
-            let font = font::new_from_descriptor(&descriptor, self.attrs.size());
+            let font = font::new_from_descriptor(&descriptor, size);
 
             let needs_synthetic_ital = self.attrs.italic() && !font.symbolic_traits().is_italic();
             let has_var_axes = font.get_variation_axes().is_some();
@@ -308,7 +367,7 @@ impl CoreGraphicsTextLayoutBuilder {
                     descriptor
                 };
 
-            ct_helpers::make_font(&descriptor, self.attrs.size(), affine)
+            ct_helpers::make_font(&descriptor, size, affine)
         }
     }
 
@@ -329,8 +388,16 @@ impl Attributes {
             TextAttribute::Weight(w) => self.weight = Some(Span::new(w, range)),
             TextAttribute::FontSize(s) => self.size = Some(Span::new(s, range)),
             TextAttribute::Style(s) => self.style = Some(Span::new(s, range)),
-            TextAttribute::Strikethrough(_) => { /* Unimplemented for now as coregraphics doesn't have native strikethrough support. */
+            TextAttribute::BaselineShift(s) => self.shift = Some(Span::new(s, range)),
+            TextAttribute::Strikethrough(_)
+            | TextAttribute::Overline(_)
+            | TextAttribute::DecorationThickness(_) => { /* Unimplemented for now as coregraphics doesn't have native strikethrough, overline or line thickness support. */
+            }
+            TextAttribute::FontFeatures(_)
+            | TextAttribute::Width(_)
+            | TextAttribute::FontVariations(_) => { /* Unimplemented for now; Core Text needs these set on the font descriptor. */
             }
+            TextAttribute::WordSpacing(_) => { /* Core Text has no word spacing. */ }
             _ => unreachable!(),
         }
     }
@@ -342,6 +409,13 @@ impl Attributes {
             .unwrap_or(self.defaults.font_size)
     }
 
+    fn baseline_shift(&self) -> BaselineShift {
+        self.shift
+            .as_ref()
+            .map(|s| s.payload)
+            .unwrap_or(self.defaults.baseline_shift)
+    }
+
     fn weight(&self) -> FontWeight {
         self.weight
             .as_ref()
@@ -374,6 +448,7 @@ impl Attributes {
             .min(self.size.as_ref().map(Span::range_end).unwrap_or(max))
             .min(self.weight.as_ref().map(Span::range_end).unwrap_or(max))
             .min(self.style.as_ref().map(Span::range_end).unwrap_or(max))
+            .min(self.shift.as_ref().map(Span::range_end).unwrap_or(max))
             .min(max)
     }
 
@@ -391,6 +466,9 @@ impl Attributes {
         if self.size.as_ref().map(Span::range_end) == Some(last_pos) {
             self.size = None;
         }
+        if self.shift.as_ref().map(Span::range_end) == Some(last_pos) {
+            self.shift = None;
+        }
     }
 }
 
@@ -484,6 +562,8 @@ impl CoreGraphicsTextLayoutBuilder {
         CoreGraphicsTextLayoutBuilder {
             width: f64::INFINITY,
             alignment: TextAlignment::default(),
+            direction: TextDirection::default(),
+            spacing: ParagraphSpacing::default(),
             attrs: Default::default(),
             text,
             last_resolved_pos: 0,
@@ -492,6 +572,8 @@ impl CoreGraphicsTextLayoutBuilder {
             has_set_default_attrs: false,
             default_baseline: 0.0,
             default_line_height: 0.0,
+            backgrounds: Vec::new(),
+            placeholders: Vec::new(),
         }
     }
 }
@@ -515,6 +597,31 @@ impl TextLayoutBuilder for CoreGraphicsTextLayoutBuilder {
         self
     }
 
+    fn direction(mut self, direction: TextDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    fn line_height(mut self, height: LineHeight) -> Self {
+        self.spacing.line_height = height;
+        self
+    }
+
+    fn paragraph_spacing(mut self, spacing: f64) -> Self {
+        self.spacing.paragraph_spacing = spacing;
+        self
+    }
+
+    fn first_line_indent(mut self, indent: f64) -> Self {
+        self.spacing.first_line_indent = indent;
+        self
+    }
+
+    fn hanging_indent(mut self, indent: f64) -> Self {
+        self.spacing.hanging_indent = indent;
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         debug_assert!(
             !self.has_set_default_attrs,
@@ -538,13 +645,31 @@ impl TextLayoutBuilder for CoreGraphicsTextLayoutBuilder {
 
     fn build(mut self) -> Result<Self::Out, Error> {
         self.finalize();
-        self.attr_string.set_alignment(self.alignment);
+        if let Some(color) = self.attrs.defaults.background_color.clone() {
+            self.backgrounds.insert(0, (0..self.text.len(), color));
+        }
+        if let Some(placeholder) = self.attrs.defaults.placeholder {
+            let whole_range = self.attr_string.range();
+            let first_len = first_char_len_utf16(&self.text);
+            self.attr_string
+                .set_placeholder(whole_range, first_len, placeholder);
+            self.placeholders
+                .insert(0, (0..self.text.len(), placeholder));
+        }
+        self.attr_string.set_paragraph_style(
+            self.alignment,
+            self.direction,
+            self.spacing,
+            self.attrs.defaults.font_size,
+        );
         Ok(CoreGraphicsTextLayout::new(
             self.text,
             self.attr_string,
             self.width,
             self.default_baseline,
             self.default_line_height,
+            self.backgrounds.into(),
+            self.placeholders.into(),
         ))
     }
 }
@@ -640,6 +765,10 @@ impl TextLayout for CoreGraphicsTextLayout {
             point_in_string_space.x >= 0. && point_in_string_space.x <= typo_bounds.width;
         let is_inside = is_inside_x && is_inside_y;
 
+        // past the end of a wrapped line, the caret stays on the line
+        if offset == metric.end_offset && line_num + 1 < self.line_metrics.len() {
+            return HitTestPoint::with_affinity(offset, is_inside, Affinity::Upstream);
+        }
         HitTestPoint::new(offset, is_inside)
     }
 
@@ -670,6 +799,21 @@ impl TextLayout for CoreGraphicsTextLayout {
         let y_pos = metric.y_offset + metric.baseline;
         HitTestPosition::new(Point::new(x_pos, y_pos), line_num)
     }
+
+    fn placeholder_rects(&self) -> Vec<(Range<usize>, Rect)> {
+        self.placeholders
+            .iter()
+            .filter(|(range, _)| !range.is_empty())
+            .map(|(range, placeholder)| {
+                let pos = self.hit_test_text_position(range.start);
+                let metric = &self.line_metrics[pos.line];
+                let y0 = metric.y_offset + metric.baseline - placeholder.baseline;
+                let origin = Point::new(pos.point.x, y0);
+                let size = Size::new(placeholder.width, placeholder.height);
+                (range.clone(), Rect::from_origin_size(origin, size))
+            })
+            .collect()
+    }
 }
 
 impl CoreGraphicsTextLayout {
@@ -679,6 +823,8 @@ impl CoreGraphicsTextLayout {
         width_constraint: f64,
         default_baseline: f64,
         default_line_height: f64,
+        backgrounds: Rc<[(Range<usize>, Color)]>,
+        placeholders: Rc<[(Range<usize>, Placeholder)]>,
     ) -> Self {
         let framesetter = Framesetter::new(&attr_string);
 
@@ -698,6 +844,8 @@ impl CoreGraphicsTextLayout {
             line_metrics: Rc::new([]),
             x_offsets: Rc::new([]),
             trailing_ws_width: 0.0,
+            backgrounds,
+            placeholders,
         };
         layout.update_width(width_constraint).unwrap();
         layout
@@ -898,6 +1046,11 @@ fn build_line_metrics(
     (metrics, x_offsets, max_width_with_ws)
 }
 
+/// The length of the first character of `text` in utf-16 code units.
+fn first_char_len_utf16(text: &str) -> isize {
+    text.chars().next().map_or(0, char::len_utf16) as isize
+}
+
 fn count_trailing_ws(s: &str) -> usize {
     //FIXME: this is just ascii whitespace
     s.as_bytes()
@@ -1038,6 +1191,23 @@ mod tests {
         assert_eq!(pt.is_inside, false);
     }
 
+    #[test]
+    fn hit_test_end_of_wrapped_line() {
+        let a_font = FontFamily::new_unchecked("Helvetica");
+        let layout = CoreGraphicsTextLayoutBuilder::new("hello world")
+            .font(a_font, 16.0)
+            .max_width(60.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+        let first = layout.line_metric(0).unwrap();
+        let pt = layout.hit_test_point(Point::new(100.0, 5.0));
+        assert_eq!(pt.idx, first.end_offset);
+        assert_eq!(pt.affinity, Affinity::Upstream);
+        let pt = layout.hit_test_point(Point::new(100.0, first.height + 5.0));
+        assert_eq!(pt.affinity, Affinity::Downstream);
+    }
+
     #[test]
     fn hit_test_empty_string() {
         let a_font = FontFamily::new_unchecked("Helvetica");
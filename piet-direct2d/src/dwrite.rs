@@ -17,13 +17,15 @@ use winapi::shared::winerror::{HRESULT, SUCCEEDED, S_OK};
 use winapi::um::dwrite::{
     DWriteCreateFactory, IDWriteFactory, IDWriteFontCollection, IDWriteFontFamily,
     IDWriteLocalizedStrings, IDWriteTextFormat, IDWriteTextLayout, DWRITE_FACTORY_TYPE_SHARED,
-    DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE, DWRITE_FONT_STYLE_ITALIC,
+    DWRITE_FONT_STRETCH, DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE, DWRITE_FONT_STYLE_ITALIC,
     DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT, DWRITE_FONT_WEIGHT_NORMAL,
-    DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS, DWRITE_OVERHANG_METRICS,
+    DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS, DWRITE_LINE_SPACING_METHOD_UNIFORM,
+    DWRITE_OVERHANG_METRICS, DWRITE_READING_DIRECTION_LEFT_TO_RIGHT,
     DWRITE_READING_DIRECTION_RIGHT_TO_LEFT, DWRITE_TEXT_ALIGNMENT_CENTER,
     DWRITE_TEXT_ALIGNMENT_JUSTIFIED, DWRITE_TEXT_ALIGNMENT_LEADING, DWRITE_TEXT_ALIGNMENT_TRAILING,
     DWRITE_TEXT_METRICS, DWRITE_TEXT_RANGE,
 };
+use winapi::um::dwrite_1::IDWriteTextLayout1;
 use winapi::um::unknwnbase::IUnknown;
 use winapi::um::winnls::GetUserDefaultLocaleName;
 use winapi::Interface;
@@ -32,7 +34,7 @@ use wio::com::ComPtr;
 use wio::wide::{FromWide, ToWide};
 
 use piet::kurbo::Insets;
-use piet::{FontFamily as PietFontFamily, FontStyle, FontWeight, TextAlignment};
+use piet::{FontFamily as PietFontFamily, FontStyle, FontWeight, FontWidth, TextAlignment};
 
 use crate::Brush;
 
@@ -341,6 +343,27 @@ impl TextLayout {
         }
     }
 
+    /// Set the reading direction for this entire layout.
+    pub(crate) fn set_rtl(&mut self, rtl: bool) {
+        let direction = if rtl {
+            DWRITE_READING_DIRECTION_RIGHT_TO_LEFT
+        } else {
+            DWRITE_READING_DIRECTION_LEFT_TO_RIGHT
+        };
+
+        unsafe {
+            self.0.SetReadingDirection(direction);
+        }
+    }
+
+    /// Give every line of this layout the same height and baseline.
+    pub(crate) fn set_uniform_line_spacing(&mut self, height: f32, baseline: f32) {
+        unsafe {
+            self.0
+                .SetLineSpacing(DWRITE_LINE_SPACING_METHOD_UNIFORM, height, baseline);
+        }
+    }
+
     /// Set the weight for a range of this layout. `start` and `len` are in utf16.
     pub(crate) fn set_weight(&mut self, range: Utf16Range, weight: FontWeight) {
         let weight = weight.to_raw() as DWRITE_FONT_WEIGHT;
@@ -372,6 +395,23 @@ impl TextLayout {
         }
     }
 
+    pub(crate) fn set_stretch(&mut self, range: Utf16Range, width: FontWidth) {
+        // DirectWrite's stretch values are the OpenType width classes
+        let stretch = width.to_raw() as DWRITE_FONT_STRETCH;
+        unsafe {
+            self.0.SetFontStretch(stretch, range.into());
+        }
+    }
+
+    pub(crate) fn set_letter_spacing(&mut self, range: Utf16Range, spacing: f32) {
+        // character spacing needs IDWriteTextLayout1, from Windows 8
+        if let Ok(layout) = self.0.cast::<IDWriteTextLayout1>() {
+            unsafe {
+                layout.SetCharacterSpacing(0.0, spacing, 0.0, range.into());
+            }
+        }
+    }
+
     pub(crate) fn set_underline(&mut self, range: Utf16Range, flag: bool) {
         let flag = if flag { TRUE } else { FALSE };
         unsafe {
@@ -392,6 +432,21 @@ impl TextLayout {
         }
     }
 
+    /// The font size at the utf-16 offset `position`, and the range around
+    /// it that has the same size.
+    pub(crate) fn get_size(&self, position: usize) -> (f32, Utf16Range) {
+        let mut size = 0.0;
+        let mut range = DWRITE_TEXT_RANGE {
+            startPosition: 0,
+            length: 0,
+        };
+        unsafe {
+            self.0.GetFontSize(position as u32, &mut size, &mut range);
+        }
+        let range = Utf16Range::new(range.startPosition as usize, range.length as usize);
+        (size, range)
+    }
+
     pub(crate) fn set_foregound_brush(&mut self, range: Utf16Range, brush: Brush) {
         unsafe {
             self.0
@@ -18,8 +18,9 @@ use wio::wide::ToWide;
 use piet::kurbo::{Insets, Point, Rect, Size};
 use piet::util;
 use piet::{
-    Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, RenderContext, Text,
-    TextAlignment, TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
+    Affinity, BaselineShift, Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineHeight,
+    LineMetric, RenderContext, Text, TextAlignment, TextAttribute, TextDirection, TextLayout,
+    TextLayoutBuilder, TextStorage,
 };
 
 use crate::conv;
@@ -62,8 +63,22 @@ pub struct D2DTextLayout {
     // in order to generate the brushes.
     colors: Rc<[(Utf16Range, Color)]>,
     needs_to_set_colors: Cell<bool>,
+    // DirectWrite doesn't draw backgrounds, so we fill them ourselves.
+    backgrounds: Rc<[(Range<usize>, Color)]>,
 }
 
+/// The Direct2D text layout builder.
+///
+/// Paragraph spacing and indents are not supported, as DirectWrite has no
+/// equivalent. Neither are the maximum number of lines and the overflow mode:
+/// DirectWrite can trim text that is too wide, but it can't limit the number
+/// of lines.
+///
+/// A `BaselineShift` scales the font size of its text, but the text stays on
+/// the baseline. `FontFeatures` are ignored, as they need an
+/// `IDWriteTypography`, and so are `FontVariations`, which need
+/// `IDWriteTextLayout4`. DirectWrite has no word spacing, so `WordSpacing`
+/// is ignored too.
 pub struct D2DTextLayoutBuilder {
     text: Rc<dyn TextStorage>,
     layout: Result<dwrite::TextLayout, Error>,
@@ -71,7 +86,11 @@ pub struct D2DTextLayoutBuilder {
     loaded_fonts: Rc<RefCell<LoadedFonts>>,
     default_font: FontFamily,
     default_font_size: f64,
+    line_height: LineHeight,
     colors: Vec<(Utf16Range, Color)>,
+    backgrounds: Vec<(Range<usize>, Color)>,
+    // baseline shifts scale the final font size, so they are applied when we build
+    baseline_shifts: Vec<(Utf16Range, BaselineShift)>,
     // just used to assert api is used as expected
     last_range_start_pos: usize,
 }
@@ -128,9 +147,12 @@ impl Text for D2DText {
             text,
             len_utf16: wide_str.len(),
             colors: Vec::new(),
+            backgrounds: Vec::new(),
+            baseline_shifts: Vec::new(),
             loaded_fonts: self.loaded_fonts.clone(),
             default_font: FontFamily::default(),
             default_font_size: piet::util::DEFAULT_FONT_SIZE,
+            line_height: LineHeight::default(),
             last_range_start_pos: 0,
         }
     }
@@ -158,6 +180,19 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
         self
     }
 
+    fn direction(mut self, direction: TextDirection) -> Self {
+        if let Ok(layout) = self.layout.as_mut() {
+            layout.set_rtl(util::is_rtl(self.text.as_str(), direction));
+        }
+        self
+    }
+
+    fn line_height(mut self, height: LineHeight) -> Self {
+        // applied when we build, once the default font size is known
+        self.line_height = height;
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         debug_assert!(
             self.last_range_start_pos == 0,
@@ -191,13 +226,39 @@ impl TextLayoutBuilder for D2DTextLayoutBuilder {
     }
 
     fn build(self) -> Result<Self::Out, Error> {
-        let (default_line_height, default_baseline) = self.get_default_line_height_and_baseline();
-        let layout = self.layout?;
+        let (mut default_line_height, mut default_baseline) =
+            self.get_default_line_height_and_baseline();
+        let mut layout = self.layout?;
+        for (range, shift) in &self.baseline_shifts {
+            // the range may cover several font sizes
+            let end = range.start + range.len;
+            let mut start = range.start;
+            while start < end {
+                let (size, size_range) = layout.get_size(start);
+                let size_end = (size_range.start + size_range.len).min(end).max(start + 1);
+                let size = size as f64 * shift.scale();
+                layout.set_size(Utf16Range::new(start, size_end - start), size as f32);
+                start = size_end;
+            }
+        }
+
+        let line_height = match self.line_height {
+            LineHeight::Normal => None,
+            LineHeight::Absolute(height) => Some(height),
+            LineHeight::Multiple(multiple) => Some(multiple * self.default_font_size),
+        };
+        if let Some(line_height) = line_height {
+            // the extra space is split above and below the text
+            default_baseline += (line_height - default_line_height) / 2.0;
+            default_line_height = line_height;
+            layout.set_uniform_line_spacing(default_line_height as f32, default_baseline as f32);
+        }
 
         let mut layout = D2DTextLayout {
             text: self.text,
             colors: self.colors.into(),
             needs_to_set_colors: Cell::new(true),
+            backgrounds: self.backgrounds.into(),
             line_metrics: Rc::new([]),
             layout: Rc::new(RefCell::new(layout)),
             size: Size::ZERO,
@@ -221,6 +282,7 @@ impl D2DTextLayoutBuilder {
     /// used for both range and default attributes
     fn add_attribute_shared(&mut self, attr: TextAttribute, range: Option<Range<usize>>) {
         if let Ok(layout) = self.layout.as_mut() {
+            let text_range = range.clone().unwrap_or(0..self.text.len());
             let utf16_range = match range {
                 Some(range) => {
                     let start = util::count_utf16(&self.text[..range.start]);
@@ -253,7 +315,38 @@ impl D2DTextLayoutBuilder {
                 TextAttribute::Style(style) => layout.set_style(utf16_range, style),
                 TextAttribute::Underline(flag) => layout.set_underline(utf16_range, flag),
                 TextAttribute::Strikethrough(flag) => layout.set_strikethrough(utf16_range, flag),
+                // DirectWrite only draws single lines, in the text color
+                TextAttribute::UnderlineStyle(_) => layout.set_underline(utf16_range, true),
+                TextAttribute::Overline(_)
+                | TextAttribute::DecorationColor(_)
+                | TextAttribute::DecorationThickness(_) => (),
                 TextAttribute::TextColor(color) => self.colors.push((utf16_range, color)),
+                TextAttribute::BackgroundColor(color) => self.backgrounds.push((text_range, color)),
+                TextAttribute::Width(width) => layout.set_stretch(utf16_range, width),
+                // DirectWrite takes these as an IDWriteTypography, which we
+                // don't support yet; this is documented on the builder.
+                TextAttribute::FontFeatures(_) => (),
+                // font axes need IDWriteTextLayout4; this is documented on
+                // the builder.
+                TextAttribute::FontVariations(_) => (),
+                TextAttribute::LetterSpacing(spacing) => {
+                    layout.set_letter_spacing(utf16_range, spacing as f32)
+                }
+                // DirectWrite has no word spacing
+                TextAttribute::WordSpacing(_) => (),
+                // moving the baseline needs a custom text renderer, so we
+                // only change the size; as with other range attributes, a
+                // later shift ends any earlier one at its start.
+                TextAttribute::BaselineShift(shift) => {
+                    for (prev, _) in self.baseline_shifts.iter_mut() {
+                        if prev.start + prev.len > utf16_range.start {
+                            prev.len = utf16_range.start.saturating_sub(prev.start);
+                        }
+                    }
+                    self.baseline_shifts.push((utf16_range, shift));
+                }
+                // reserving space needs an IDWriteInlineObject, which we don't support yet
+                TextAttribute::Placeholder(_) => (),
             }
         }
     }
@@ -356,6 +449,20 @@ impl TextLayout for D2DTextLayout {
         let text_position = util::count_until_utf16(&self.text, text_position_16)
             .unwrap_or_else(|| self.text.len());
 
+        // past the end of a wrapped line, the caret stays on the line
+        let line_num = self
+            .line_metrics
+            .iter()
+            .position(|lm| point.y < lm.y_offset + lm.height)
+            .unwrap_or_else(|| self.line_metrics.len().saturating_sub(1));
+        let is_wrapped_end = self.line_metrics.get(line_num).map_or(false, |lm| {
+            text_position == lm.end_offset
+                && line_num + 1 < self.line_metrics.len()
+                && util::trailing_nlf(&self.text[lm.range()]).is_none()
+        });
+        if is_wrapped_end {
+            return HitTestPoint::with_affinity(text_position, htp.is_inside, Affinity::Upstream);
+        }
         HitTestPoint::new(text_position, htp.is_inside)
     }
 
@@ -423,6 +530,12 @@ impl D2DTextLayout {
     pub fn draw(&self, pos: Point, ctx: &mut D2DRenderContext) {
         if !self.text.is_empty() {
             self.resolve_colors_if_needed(ctx);
+            for (range, color) in self.backgrounds.iter() {
+                let brush = ctx.solid_brush(color.clone());
+                for rect in self.rects_for_range(range.clone()) {
+                    ctx.fill(rect + pos.to_vec2(), &brush);
+                }
+            }
             let pos = conv::to_point2f(pos);
             let black_brush = ctx.solid_brush(Color::BLACK);
             let text_options = D2D1_DRAW_TEXT_OPTIONS_NONE;
@@ -1002,6 +1115,25 @@ mod test {
         );
     }
 
+    #[test]
+    fn hit_test_end_of_wrapped_line() {
+        let mut text = D2DText::new_for_test();
+        let font = text.font_family("Segoe UI").unwrap();
+        let layout = text
+            .new_text_layout("hello world")
+            .font(font, 12.0)
+            .max_width(40.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+        let first = layout.line_metric(0).unwrap();
+        let pt = layout.hit_test_point(Point::new(100.0, 5.0));
+        assert_eq!(pt.idx, first.end_offset);
+        assert_eq!(pt.affinity, Affinity::Upstream);
+        let pt = layout.hit_test_point(Point::new(100.0, first.height + 5.0));
+        assert_eq!(pt.affinity, Affinity::Downstream);
+    }
+
     #[test]
     // very basic testing that multiline works
     fn test_multiline_hit_test_point_basic() {
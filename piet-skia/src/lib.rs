@@ -1,6 +1,6 @@
 use std::fmt;
 
-use piet::kurbo::{Affine, PathEl, Point, Rect, Shape, Size};
+use piet::kurbo::{Affine, BezPath, PathEl, Point, Rect, Shape, Size};
 use piet::{
     Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient, Image, ImageFormat,
     InterpolationMode, IntoBrush, LineCap, LineJoin, RenderContext, StrokeStyle, TextLayout,
@@ -9,14 +9,17 @@ use skia_safe;
 use skia_safe::canvas::SrcRectConstraint;
 use skia_safe::effects::gradient_shader::{linear, radial};
 use skia_safe::paint::{Cap, Join};
+use skia_safe::path::Verb;
 use skia_safe::shader::Shader;
 use skia_safe::ClipOp;
 use skia_safe::{
     AlphaType, BlurStyle, ColorType, Data, MaskFilter, Paint, PaintStyle, Path, TileMode,
 };
 use std::borrow::Cow;
+pub use animation::*;
 pub use text::*;
 
+mod animation;
 mod simple_text;
 mod text;
 
@@ -68,10 +71,28 @@ pub struct SkiaRenderContext<'a> {
 
 impl<'a> SkiaRenderContext<'a> {
     pub fn new(canvas: &'a mut skia_safe::Canvas) -> Self {
-        SkiaRenderContext {
-            canvas,
-            text: SkiaText,
-        }
+        Self::with_text(canvas, SkiaText::new())
+    }
+
+    /// Create a render context that shares an existing text factory.
+    ///
+    /// Fonts loaded through a [`SkiaText`] are only available to layouts
+    /// created by that factory (or its clones), so applications that load
+    /// custom fonts should keep a factory around and pass it in here.
+    pub fn with_text(canvas: &'a mut skia_safe::Canvas, text: SkiaText) -> Self {
+        SkiaRenderContext { canvas, text }
+    }
+
+    /// Draw a frame of a Lottie animation into `dst_rect`.
+    ///
+    /// The current transform and clip apply, as for any other drawing.
+    pub fn draw_animation(
+        &mut self,
+        animation: &mut SkiaAnimation,
+        frame: f64,
+        dst_rect: impl Into<Rect>,
+    ) {
+        animation.render(self.canvas, frame, dst_rect.into());
     }
 
     pub fn get_skia(&mut self) -> &mut skia_safe::Canvas {
@@ -112,6 +133,50 @@ fn create_path(shape: impl Shape) -> Path {
     path
 }
 
+fn to_bez_path(path: &Path) -> BezPath {
+    let mut bez_path = BezPath::new();
+    let mut iter = skia_safe::path::Iter::new(path, false);
+    while let Some((verb, points)) = iter.next() {
+        let point = |i: usize| Point::new(points[i].x as f64, points[i].y as f64);
+        match verb {
+            Verb::Move => bez_path.move_to(point(0)),
+            Verb::Line => bez_path.line_to(point(1)),
+            Verb::Quad => bez_path.quad_to(point(1), point(2)),
+            Verb::Conic => {
+                let weight = iter.conic_weight().unwrap_or(1.0) as f64;
+                push_conic(&mut bez_path, [point(0), point(1), point(2)], weight, 4);
+            }
+            Verb::Cubic => bez_path.curve_to(point(1), point(2), point(3)),
+            Verb::Close => bez_path.close_path(),
+            Verb::Done => break,
+        }
+    }
+    bez_path
+}
+
+/// Approximates a conic with quadratic curves, as kurbo has no conics,
+/// splitting it in half at most `depth` times.
+fn push_conic(path: &mut BezPath, [p0, p1, p2]: [Point; 3], weight: f64, depth: usize) {
+    // skia's estimate of the distance between a conic and the quad with the
+    // same control points (`SkConic::computeQuadPOW2`)
+    const TOLERANCE: f64 = 0.1;
+    let a = weight - 1.0;
+    let k = a / (4.0 * (2.0 + a));
+    let error = (p0.to_vec2() - 2.0 * p1.to_vec2() + p2.to_vec2()).hypot() * k.abs();
+    if error <= TOLERANCE || depth == 0 {
+        path.quad_to(p1, p2);
+        return;
+    }
+    // split at the middle, which gives two conics with the same weight
+    let scale = 1.0 / (1.0 + weight);
+    let c0 = ((p0.to_vec2() + weight * p1.to_vec2()) * scale).to_point();
+    let c1 = ((weight * p1.to_vec2() + p2.to_vec2()) * scale).to_point();
+    let mid = c0.midpoint(c1);
+    let weight = ((1.0 + weight) / 2.0).sqrt();
+    push_conic(path, [p0, c0, mid], weight, depth - 1);
+    push_conic(path, [mid, c1, p2], weight, depth - 1);
+}
+
 pub fn convert_color(color: Color) -> skia_safe::Color {
     let rgba = color.as_rgba_u32();
     // swap r and a
@@ -285,7 +350,7 @@ impl<'a> RenderContext for SkiaRenderContext<'a> {
         match layout {
             SkiaTextLayout::Paragraph(paragraph) => {
                 process_brush(&paragraph.fg_color());
-                paragraph.paragraph.paint(&mut self.canvas, pos);
+                paragraph.paint(self.canvas, pos);
             }
             SkiaTextLayout::Simple(simple) => {
                 process_brush(&simple.fg_color);
@@ -476,3 +541,120 @@ impl<'a> RenderContext for SkiaRenderContext<'a> {
         self.canvas.draw_path(&path, &paint);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piet::{FontFamily, GradientStop, Text, TextLayoutBuilder};
+    use skia_safe::{ImageInfo, Surface};
+
+    const SIZE: usize = 100;
+
+    /// Draw into a white `SIZE` by `SIZE` surface, and return its RGBA pixels.
+    fn render(draw: impl FnOnce(&mut SkiaRenderContext)) -> Vec<u8> {
+        let mut surface = Surface::new_raster_n32_premul((SIZE as i32, SIZE as i32)).unwrap();
+        {
+            let mut rc = SkiaRenderContext::new(surface.canvas());
+            rc.clear(Color::WHITE);
+            draw(&mut rc);
+        }
+        let image_info = ImageInfo::new(
+            (SIZE as i32, SIZE as i32),
+            ColorType::RGBA8888,
+            AlphaType::Premul,
+            None,
+        );
+        let mut pixels = vec![0u8; SIZE * SIZE * 4];
+        assert!(surface.read_pixels(&image_info, &mut pixels, SIZE * 4, (0, 0)));
+        pixels
+    }
+
+    fn pixel(pixels: &[u8], x: usize, y: usize) -> &[u8] {
+        let idx = (y * SIZE + x) * 4;
+        &pixels[idx..idx + 4]
+    }
+
+    fn is_white(pixels: &[u8]) -> bool {
+        pixels.iter().all(|c| *c == 255)
+    }
+
+    fn layout(rc: &mut SkiaRenderContext) -> SkiaTextLayout {
+        rc.text()
+            .new_text_layout("HH")
+            .font(FontFamily::SANS_SERIF, 40.0)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn draw_text_with_gradient() {
+        let pixels = render(|rc| {
+            let layout = layout(rc);
+            let gradient = rc
+                .gradient(FixedLinearGradient {
+                    start: Point::new(0.0, 0.0),
+                    end: Point::new(SIZE as f64, 0.0),
+                    stops: vec![
+                        GradientStop {
+                            pos: 0.0,
+                            color: Color::RED,
+                        },
+                        GradientStop {
+                            pos: 1.0,
+                            color: Color::BLUE,
+                        },
+                    ],
+                })
+                .unwrap();
+            rc.draw_text_with_brush(&layout, (10.0, 10.0), &gradient)
+                .unwrap();
+        });
+        assert!(!is_white(&pixels));
+    }
+
+    #[test]
+    fn stroke_text() {
+        let pixels = render(|rc| {
+            let layout = layout(rc);
+            rc.stroke_text(
+                &layout,
+                (10.0, 10.0),
+                &Color::BLACK,
+                2.0,
+                &StrokeStyle::new(),
+            )
+            .unwrap();
+        });
+        assert!(!is_white(&pixels));
+    }
+
+    #[test]
+    fn clip_text_is_restored() {
+        let pixels = render(|rc| {
+            let layout = layout(rc);
+            let all = Rect::new(0.0, 0.0, SIZE as f64, SIZE as f64);
+            rc.save().unwrap();
+            rc.clip_text(&layout, (10.0, 10.0)).unwrap();
+            rc.fill(all, &Color::BLACK);
+            rc.restore().unwrap();
+            rc.fill(Rect::new(80.0, 80.0, SIZE as f64, SIZE as f64), &Color::RED);
+        });
+        // the text is filled, and nothing else until the clip is restored
+        assert!(pixels.chunks(4).any(|p| p == [0, 0, 0, 255]));
+        assert!(is_white(pixel(&pixels, 70, 90)));
+        assert_eq!(pixel(&pixels, 90, 90), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn draw_text_on_path() {
+        let pixels = render(|rc| {
+            let layout = layout(rc);
+            let mut path = BezPath::new();
+            path.move_to((0.0, 80.0));
+            path.quad_to((50.0, 20.0), (SIZE as f64, 80.0));
+            rc.draw_text_on_path(&layout, &path, 0.0, piet::TextAlignment::Start)
+                .unwrap();
+        });
+        assert!(!is_white(&pixels));
+    }
+}
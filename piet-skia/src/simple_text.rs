@@ -1,11 +1,15 @@
 use std::rc::Rc;
 
-use piet::kurbo::{Point, Rect, Size};
-use piet::{Color, HitTestPoint, HitTestPosition, LineMetric, TextLayout, TextStorage};
+use piet::kurbo::{BezPath, Point, Rect, Size};
+use piet::{
+    Color, Glyph, GlyphRun, HitTestPoint, HitTestPosition, LineMetric, TextLayout, TextStorage,
+};
 use skia_safe::Font;
 use std::fmt;
 use xi_unicode::LineBreakIterator;
 
+use crate::text::{font_info, glyph_outline};
+
 #[derive(Debug, Clone)]
 pub struct SimpleLineMetric {
     pub start_offset: usize,
@@ -137,4 +141,57 @@ impl TextLayout for SkiaSimpleTextLayout {
         // TODO
         HitTestPosition::new(Point::new(0., 0.), 0)
     }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        let (font, weight, style) = font_info(&self.font);
+        let font_size = self.font.size() as f64;
+        self.line_metrics
+            .iter()
+            .filter_map(|line| {
+                let text = &self.text()[line.start_offset..line.end_offset];
+                let text = text.trim_end_matches(|c| c == '\n' || c == '\r');
+                // without shaping, each char maps to exactly one glyph
+                let ids = self.font.str_to_glyphs_vec(text);
+                let mut widths = vec![0.0; ids.len()];
+                self.font.get_widths(&ids, &mut widths);
+                // this matches how lines are placed in `draw_text`
+                let y = line.y_offset + line.bounds.height() as f64;
+                let mut x = 0.0;
+                let glyphs: Vec<Glyph> = text
+                    .char_indices()
+                    .zip(ids.iter().zip(widths))
+                    .map(|((offset, _), (id, width))| {
+                        let glyph = Glyph {
+                            id: *id as u32,
+                            position: Point::new(x, y),
+                            advance: width as f64,
+                            cluster: line.start_offset + offset,
+                        };
+                        x += width as f64;
+                        glyph
+                    })
+                    .collect();
+                if glyphs.is_empty() {
+                    return None;
+                }
+                Some(GlyphRun {
+                    range: line.start_offset..line.start_offset + text.len(),
+                    font: font.clone(),
+                    font_size,
+                    weight,
+                    style,
+                    color: self.fg_color.clone(),
+                    glyphs,
+                })
+            })
+            .collect()
+    }
+
+    fn glyph_outlines(&self) -> Vec<BezPath> {
+        self.glyph_runs()
+            .iter()
+            .flat_map(|run| run.glyphs.iter())
+            .map(|glyph| glyph_outline(&self.font, glyph))
+            .collect()
+    }
 }
@@ -1,29 +1,89 @@
-use std::ops::RangeBounds;
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::ops::{Range, RangeBounds};
 use std::rc::Rc;
 
-use piet::kurbo::{Point, Rect, Size};
+use piet::kurbo::{Affine, BezPath, Point, Rect, Size};
 use piet::{
-    util, Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder, TextStorage,
+    util, Affinity, BaselineShift, Color, Error, FontFamily, Glyph, GlyphRun, HitTestPoint,
+    HitTestPosition, LineHeight, LineMetric, Text, TextAttribute, TextLayout, TextLayoutBuilder,
+    TextOverflow, TextStorage, UnderlineStyle,
 };
+use skia_safe::font_arguments::{variation_position::Coordinate, VariationPosition};
 use skia_safe::font_style::{FontStyle, Slant, Weight, Width};
 use skia_safe::textlayout::{
-    FontCollection, Paragraph, ParagraphBuilder, ParagraphStyle, RectHeightStyle, RectWidthStyle,
-    TextStyle,
+    FontCollection, Paragraph, ParagraphBuilder, ParagraphStyle, PlaceholderAlignment,
+    PlaceholderStyle, RectHeightStyle, RectWidthStyle, StrutStyle, TextBaseline, TextDecoration,
+    TextDecorationStyle, TextDirection as SkTextDirection, TextStyle, TypefaceFontProvider,
 };
 use skia_safe::typeface::Typeface;
-use skia_safe::{Contains, Font, FontMgr, Paint};
+use skia_safe::{Canvas, Contains, Data, Font, FontArguments, FontMgr, FourByteTag, Paint, Vector};
+use unicode_segmentation::UnicodeSegmentation;
 
 use std::fmt;
 
 use crate::simple_text::*;
 
+/// The skia text factory.
+///
+/// Fonts loaded with [`load_font`] are registered in a font provider that is
+/// shared between all clones of this type, and take precedence over system
+/// fonts with the same family name.
+///
+/// The font collection and the typefaces resolved through it are also shared,
+/// so that building many layouts does not repeat font lookups.
+///
+/// [`load_font`]: #method.load_font
 #[derive(Clone)]
-pub struct SkiaText;
+pub struct SkiaText {
+    font_provider: TypefaceFontProvider,
+    font_collection: FontCollection,
+    typefaces: Rc<RefCell<HashMap<TypefaceKey, Typeface>>>,
+}
+
+/// The attributes that select a typeface.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct TypefaceKey {
+    family: FontFamily,
+    weight: piet::FontWeight,
+    style: piet::FontStyle,
+    width: piet::FontWidth,
+    /// Variation tags and the bits of their values, which are floats.
+    variations: Vec<([u8; 4], u32)>,
+}
 
 impl SkiaText {
     pub fn new() -> Self {
-        SkiaText
+        let font_provider = TypefaceFontProvider::new();
+        let mut font_collection = FontCollection::new();
+        font_collection.set_default_font_manager(FontMgr::new(), None::<&str>);
+        let asset_font_manager: FontMgr = font_provider.clone().into();
+        font_collection.set_asset_font_manager(Some(asset_font_manager));
+        SkiaText {
+            font_provider,
+            font_collection,
+            typefaces: Default::default(),
+        }
+    }
+
+    /// Return the typeface for these attributes, resolving it if needed.
+    fn typeface(&self, attrs: &util::LayoutDefaults) -> Typeface {
+        let key = TypefaceKey {
+            family: attrs.font.clone(),
+            weight: attrs.weight,
+            style: attrs.style,
+            width: attrs.width,
+            variations: attrs
+                .font_variations
+                .iter()
+                .map(|v| (v.tag(), v.value().to_bits()))
+                .collect(),
+        };
+        self.typefaces
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| build_typeface(&self.font_collection, attrs))
+            .clone()
     }
 }
 
@@ -33,22 +93,191 @@ pub enum SkiaTextLayout {
     Paragraph(ParagraphTextLayout),
 }
 
+#[derive(Clone)]
 pub struct ParagraphTextLayout {
     pub text: Rc<dyn TextStorage>,
     pub width: f32,
-    // Paragraph doesn't support Clone trait so we need to store some info to rebuild it
-    // we store Rc here cause we need to clone this data too
+    // Paragraph doesn't support Clone trait, so clones share them; we keep the
+    // inputs around so that a layout can rebuild its own copy when it needs to
+    // re-layout paragraphs that are shared. They are in a RefCell because
+    // visiting their glyphs needs mutable paragraphs.
     defaults: Rc<util::LayoutDefaults>,
-    pub paragraph: Paragraph,
+    runs: Rc<[util::AttributeRun]>,
+    fonts: SkiaText,
+    options: ParagraphOptions,
+    paragraphs: Rc<RefCell<Paragraphs>>,
+    /// The text hidden behind an ellipsis on the last line, if any.
+    elision: Option<Elision>,
+    /// The ranges of our text that the paragraph replaces with a single
+    /// character, in order: visible placeholders, and the hidden text.
+    replaced: Rc<[Range<usize>]>,
+}
+
+/// Text hidden behind an ellipsis.
+///
+/// Skia can only put an ellipsis at the end of a line, and hit testing it
+/// finds the end of the visible text, so we hide the text ourselves and give
+/// the paragraph an ellipsis in its place.
+#[derive(Debug, Clone)]
+struct Elision {
+    /// The hidden text.
+    range: Range<usize>,
+    /// The end of the last line before it was truncated, and the end of its
+    /// text without trailing whitespace.
+    line_end: usize,
+    line_end_excluding_whitespace: usize,
+}
+
+/// The options of a layout that apply to the whole paragraph: its direction,
+/// line height, paragraph spacing and indents, the maximum number of lines,
+/// and how to show the text that doesn't fit.
+#[derive(Debug, Clone, Copy, Default)]
+struct ParagraphOptions {
+    direction: piet::TextDirection,
+    line_height: LineHeight,
+    paragraph_spacing: f64,
+    first_line_indent: f64,
+    hanging_indent: f64,
+    max_lines: Option<usize>,
+    overflow: TextOverflow,
+}
+
+impl ParagraphOptions {
+    /// Whether the text must be split into several skia paragraphs, for the
+    /// paragraph spacing and indents that skia's paragraphs don't have.
+    fn is_split(&self) -> bool {
+        self.paragraph_spacing != 0.0 || self.first_line_indent != 0.0 || self.hanging_indent != 0.0
+    }
+}
+
+/// A layout's text, laid out by skia.
+///
+/// Skia's paragraphs have no paragraph spacing or indents, so text that has
+/// them is laid out as several skia paragraphs: for each of its paragraphs,
+/// one for the first line and one for the rest, each narrowed by its indent
+/// and placed below the one before. Offsets are in the paragraph text of the
+/// whole, as if it were a single skia paragraph.
+struct Paragraphs {
+    parts: Vec<Part>,
+}
+
+/// One of the skia paragraphs of [`Paragraphs`].
+struct Part {
+    paragraph: Paragraph,
+    /// The range of the paragraph's text in the paragraph text of the whole;
+    /// the newline that ends a paragraph is left out, as skia would add an
+    /// empty line after it.
+    start: usize,
+    end: usize,
+    /// How far the lines are indented from the start edge.
+    indent: f32,
+    /// Where the paragraph is drawn.
+    origin: Vector,
+}
+
+/// A line of [`Paragraphs`], in the paragraph text and coordinates of the
+/// whole.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    start_index: usize,
+    end_index: usize,
+    end_excluding_whitespaces: usize,
+    baseline: f64,
+    ascent: f64,
+    height: f64,
+    width: f64,
+    /// How far the line is indented from the start edge.
+    indent: f64,
 }
 
-impl Clone for ParagraphTextLayout {
-    fn clone(&self) -> Self {
-        Self {
-            text: self.text.clone(),
-            width: self.width.clone(),
-            defaults: self.defaults.clone(),
-            paragraph: build_paragraph(self.text.as_ref(), &self.defaults, self.width),
+impl Paragraphs {
+    fn height(&self) -> f32 {
+        self.parts
+            .last()
+            .map_or(0.0, |part| part.origin.y + part.paragraph.height())
+    }
+
+    fn line_number(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|part| part.paragraph.line_number())
+            .sum()
+    }
+
+    fn lines(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        for part in &self.parts {
+            lines.extend(part.paragraph.get_line_metrics().iter().map(|lm| Line {
+                start_index: part.start + lm.start_index,
+                end_index: part.start + lm.end_index,
+                end_excluding_whitespaces: part.start + lm.end_excluding_whitespaces,
+                baseline: part.origin.y as f64 + lm.baseline,
+                ascent: lm.ascent,
+                height: lm.height,
+                width: lm.width,
+                indent: part.indent as f64,
+            }));
+        }
+        lines
+    }
+
+    /// The tight boxes of the glyphs in `range`.
+    fn rects_for_range(&self, range: Range<usize>) -> Vec<skia_safe::Rect> {
+        let mut rects = Vec::new();
+        for part in &self.parts {
+            let start = range.start.max(part.start);
+            let end = range.end.min(part.end);
+            if start >= end {
+                continue;
+            }
+            let text_boxes = part.paragraph.get_rects_for_range(
+                start - part.start..end - part.start,
+                RectHeightStyle::Tight,
+                RectWidthStyle::Tight,
+            );
+            rects.extend(
+                text_boxes
+                    .iter()
+                    .map(|text_box| text_box.rect.with_offset(part.origin)),
+            );
+        }
+        rects
+    }
+
+    /// The offset of the glyph closest to `point`; a point between two parts
+    /// hits the one below it.
+    fn glyph_position_at_coordinate(&self, point: skia_safe::Point) -> usize {
+        let part = self
+            .parts
+            .iter()
+            .find(|part| point.y < part.origin.y + part.paragraph.height())
+            .or_else(|| self.parts.last());
+        match part {
+            Some(part) => {
+                let point = skia_safe::Point::new(point.x - part.origin.x, point.y - part.origin.y);
+                let position = part.paragraph.get_glyph_position_at_coordinate(point);
+                part.start + position.position as usize
+            }
+            None => 0,
+        }
+    }
+
+    fn placeholder_rects(&self) -> Vec<skia_safe::Rect> {
+        let mut rects = Vec::new();
+        for part in &self.parts {
+            let text_boxes = part.paragraph.get_rects_for_placeholders();
+            rects.extend(
+                text_boxes
+                    .iter()
+                    .map(|text_box| text_box.rect.with_offset(part.origin)),
+            );
+        }
+        rects
+    }
+
+    fn paint(&self, canvas: &mut Canvas, origin: skia_safe::Point) {
+        for part in &self.parts {
+            part.paragraph.paint(canvas, origin + part.origin);
         }
     }
 }
@@ -71,10 +300,20 @@ impl fmt::Debug for SkiaTextLayoutBuilder {
     }
 }
 
+/// The skia text layout builder.
 pub struct SkiaTextLayoutBuilder {
     text: Rc<dyn TextStorage>,
     defaults: util::LayoutDefaults,
+    attributes: util::RangeAttributes,
+    fonts: SkiaText,
     width_constraint: f64,
+    options: ParagraphOptions,
+}
+
+impl Default for SkiaText {
+    fn default() -> Self {
+        SkiaText::new()
+    }
 }
 
 impl Text for SkiaText {
@@ -85,65 +324,569 @@ impl Text for SkiaText {
         Some(FontFamily::new_unchecked(family_name))
     }
 
-    fn load_font(&mut self, _data: &[u8]) -> Result<FontFamily, Error> {
-        Err(Error::NotSupported)
+    fn load_font(&mut self, data: &[u8]) -> Result<FontFamily, Error> {
+        let typeface =
+            Typeface::from_data(Data::new_copy(data), None).ok_or(Error::FontLoadingFailed)?;
+        let family_name = typeface.family_name();
+        self.font_provider.register_typeface(typeface, None::<&str>);
+        // lookups made before this font was registered are cached, and the new
+        // font may mask a system font of the same family.
+        self.font_collection.clear_caches();
+        self.typefaces.borrow_mut().clear();
+        Ok(FontFamily::new_unchecked(family_name))
     }
 
     fn new_text_layout(&mut self, text: impl TextStorage) -> Self::TextLayoutBuilder {
         SkiaTextLayoutBuilder {
             defaults: util::LayoutDefaults::default(),
+            attributes: util::RangeAttributes::default(),
+            fonts: self.clone(),
             text: Rc::new(text),
             width_constraint: f64::INFINITY,
+            options: ParagraphOptions::default(),
         }
     }
 }
 
-fn build_typeface(defaults: &util::LayoutDefaults) -> Typeface {
-    let mut font_collection = FontCollection::new();
-    let font_mngr = FontMgr::new();
-    font_collection.set_default_font_manager(font_mngr, defaults.font.name());
-    let width = Width::NORMAL; // no options provided via piet
-    let weight: Weight = (defaults.weight.to_raw() as i32).into();
-    let slant = match defaults.style {
+fn font_style(attrs: &util::LayoutDefaults) -> FontStyle {
+    let width: Width = (attrs.width.to_raw() as i32).into();
+    let weight: Weight = (attrs.weight.to_raw() as i32).into();
+    let slant = match attrs.style {
         piet::FontStyle::Regular => Slant::Upright,
         piet::FontStyle::Italic => Slant::Italic,
     };
-    let font_style = FontStyle::new(weight, width, slant);
-    if let Some(tf) = Typeface::new(defaults.font.name(), font_style) {
+    FontStyle::new(weight, width, slant)
+}
+
+fn build_typeface(font_collection: &FontCollection, attrs: &util::LayoutDefaults) -> Typeface {
+    let font_style = font_style(attrs);
+    let family_name = attrs.font.name();
+    // the collection checks loaded fonts first, and then the system fonts
+    let mut font_collection = font_collection.clone();
+    let typeface = font_collection
+        .find_typefaces(&[family_name], font_style)
+        .into_iter()
+        .next()
+        .or_else(|| Typeface::new(family_name, font_style));
+    let typeface = if let Some(tf) = typeface {
         tf
     } else {
         let jbf = include_bytes!("../JetBrainsMono-Regular.ttf");
         Typeface::from_data(skia_safe::Data::new_copy(&jbf[..]), None).unwrap()
+    };
+    apply_variations(typeface, &attrs.font_variations)
+}
+
+/// Set the axes of a variable font; typefaces that don't support variations
+/// are returned unchanged.
+fn apply_variations(typeface: Typeface, variations: &[piet::FontVariation]) -> Typeface {
+    if variations.is_empty() {
+        return typeface;
+    }
+    let coordinates: Vec<Coordinate> = variations
+        .iter()
+        .map(|v| Coordinate {
+            axis: FourByteTag::new(u32::from_be_bytes(v.tag())),
+            value: v.value(),
+        })
+        .collect();
+    let arguments = FontArguments::new().set_variation_design_position(VariationPosition {
+        coordinates: &coordinates,
+    });
+    typeface
+        .clone_with_arguments(&arguments)
+        .unwrap_or(typeface)
+}
+
+fn build_text_style(
+    fonts: &SkiaText,
+    attrs: &util::LayoutDefaults,
+    line_height: LineHeight,
+) -> TextStyle {
+    let mut text_style = TextStyle::new();
+    if let LineHeight::Multiple(multiple) = line_height {
+        // skia's height is a multiple of the font size
+        text_style.set_height(multiple as f32);
+        text_style.set_height_override(true);
+    }
+    let mut paint = Paint::default();
+    paint.set_color(crate::convert_color(attrs.fg_color.clone()));
+    let typeface = fonts.typeface(attrs);
+    text_style.set_typeface(Some(typeface.clone()));
+    text_style.set_font_families(&[attrs.font.name()]);
+    text_style.set_font_style(font_style(attrs));
+    let shift = attrs.baseline_shift;
+    let font_size = (attrs.font_size * shift.scale()) as f32;
+    text_style.set_font_size(font_size);
+    // skia's baseline shift is downwards
+    text_style.set_baseline_shift(-shift.offset(attrs.font_size) as f32);
+    text_style.set_foreground_color(paint);
+    if let Some(color) = &attrs.background_color {
+        let mut paint = Paint::default();
+        paint.set_color(crate::convert_color(color.clone()));
+        text_style.set_background_color(paint);
+    }
+    let mut decoration = TextDecoration::NO_DECORATION;
+    if attrs.underline {
+        decoration |= TextDecoration::UNDERLINE;
+    }
+    if attrs.strikethrough {
+        decoration |= TextDecoration::LINE_THROUGH;
+    }
+    if attrs.overline {
+        decoration |= TextDecoration::OVERLINE;
+    }
+    text_style.set_decoration_type(decoration);
+    text_style.set_decoration_style(match attrs.underline_style {
+        UnderlineStyle::Single => TextDecorationStyle::Solid,
+        UnderlineStyle::Double => TextDecorationStyle::Double,
+        UnderlineStyle::Dotted => TextDecorationStyle::Dotted,
+        UnderlineStyle::Dashed => TextDecorationStyle::Dashed,
+        UnderlineStyle::Wavy => TextDecorationStyle::Wavy,
+    });
+    if let Some(color) = &attrs.decoration_color {
+        text_style.set_decoration_color(crate::convert_color(color.clone()));
+    }
+    if let Some(thickness) = attrs.decoration_thickness {
+        // skia scales the font's underline thickness
+        let (_, metrics) = Font::new(typeface, Some(font_size)).metrics();
+        let default = metrics.underline_thickness().unwrap_or(font_size / 14.0);
+        text_style.set_decoration_thickness_multiplier(thickness as f32 / default);
+    }
+    for feature in &attrs.font_features {
+        text_style.add_font_feature(feature.tag_str(), feature.value() as i32);
+    }
+    text_style.set_letter_spacing(attrs.letter_spacing as f32);
+    text_style.set_word_spacing(attrs.word_spacing as f32);
+    text_style
+}
+
+/// The text drawn in place of hidden text.
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Lay out a paragraph, hiding the text that doesn't fit in its maximum
+/// number of lines behind an ellipsis, as determined by its overflow mode.
+fn layout_paragraph(
+    text: &str,
+    defaults: &util::LayoutDefaults,
+    runs: &[util::AttributeRun],
+    fonts: &SkiaText,
+    width_constraint: f32,
+    options: ParagraphOptions,
+) -> (Paragraphs, Option<Elision>) {
+    if options.overflow == TextOverflow::Clip {
+        let paragraphs =
+            build_paragraphs(text, defaults, runs, fonts, width_constraint, options, None);
+        return (paragraphs, None);
+    }
+    // all of the text is laid out to measure what fits
+    let all_lines = ParagraphOptions {
+        max_lines: None,
+        ..options
+    };
+    let paragraphs = build_paragraphs(
+        text,
+        defaults,
+        runs,
+        fonts,
+        width_constraint,
+        all_lines,
+        None,
+    );
+    match elision(text, runs, fonts, &paragraphs, width_constraint, options) {
+        Some(elision) => {
+            let paragraphs = build_paragraphs(
+                text,
+                defaults,
+                runs,
+                fonts,
+                width_constraint,
+                options,
+                Some(&elision.range),
+            );
+            (paragraphs, Some(elision))
+        }
+        None => (paragraphs, None),
+    }
+}
+
+/// Find the text to hide so that the lines after `max_lines` are dropped,
+/// and the last line fits in `width` with an ellipsis; `paragraphs` is all
+/// of the text, laid out without an ellipsis.
+fn elision(
+    text: &str,
+    runs: &[util::AttributeRun],
+    fonts: &SkiaText,
+    paragraphs: &Paragraphs,
+    width: f32,
+    options: ParagraphOptions,
+) -> Option<Elision> {
+    let replaced = replaced_ranges(runs, None);
+    let lines = paragraphs.lines();
+    let max_lines = options.max_lines.unwrap_or(usize::MAX).max(1);
+    let is_truncated = lines.len() > max_lines;
+    let last = lines[..lines.len().min(max_lines)].last()?;
+    let start = text_offset(&replaced, last.start_index);
+    let line_end = text_offset(&replaced, last.end_index);
+    let content_end = start + text[start..line_end].trim_end().len();
+
+    let advance = |range: Range<usize>| -> f32 {
+        let range =
+            paragraph_offset(&replaced, range.start)..paragraph_offset(&replaced, range.end);
+        paragraphs
+            .rects_for_range(range)
+            .iter()
+            .map(|rect| rect.width())
+            .sum()
+    };
+    // indented lines have less room
+    let width = width - last.indent as f32;
+    if !is_truncated && advance(start..content_end) <= width {
+        return None;
+    }
+
+    // the graphemes of the text; a placeholder is kept or hidden as a whole
+    let units = |range: Range<usize>| {
+        let offset = range.start;
+        let mut units: Vec<Range<usize>> = Vec::new();
+        for (idx, grapheme) in text[range].grapheme_indices(true) {
+            let unit = offset + idx..offset + idx + grapheme.len();
+            match replaced
+                .iter()
+                .find(|r| r.start < unit.end && unit.start < r.end)
+            {
+                Some(placeholder) if units.last() == Some(placeholder) => (),
+                Some(placeholder) => units.push(placeholder.clone()),
+                None => units.push(unit),
+            }
+        }
+        units
+    };
+
+    let style_idx = content_end.max(start + 1) - 1;
+    let style_run = runs
+        .iter()
+        .find(|run| run.range.contains(&style_idx))
+        .or_else(|| runs.first())?;
+    let room = width - ellipsis_width(fonts, &style_run.attrs);
+
+    // the line keeps text from `start..head_end` and `tail_start..`
+    let mut head_end = start;
+    let mut head_width = 0.0;
+    for unit in units(start..content_end) {
+        let width = advance(unit.clone());
+        if head_width + width > room {
+            break;
+        }
+        head_width += width;
+        head_end = unit.end;
     }
+    let mut tail_start = text.len();
+    if options.overflow == TextOverflow::MiddleEllipsis {
+        // the end of the text shares the room with the start of the line,
+        // taking its graphemes alternately from each end.
+        let tail_line = text_offset(&replaced, lines.last()?.start_index).max(start);
+        let tail_end = tail_line + text[tail_line..].trim_end().len();
+        let mut head = units(start..content_end).into_iter();
+        let mut tail = units(tail_line..tail_end).into_iter().rev();
+        let mut tail_width = 0.0;
+        head_end = start;
+        head_width = 0.0;
+        tail_start = tail_end;
+        let mut head_done = false;
+        let mut tail_done = false;
+        while !(head_done && tail_done) {
+            let from_head = !head_done && (tail_done || head_width <= tail_width);
+            let unit = if from_head { head.next() } else { tail.next() };
+            let unit = match unit {
+                Some(u) if u.start >= head_end && u.end <= tail_start => u,
+                _ => {
+                    head_done |= from_head;
+                    tail_done |= !from_head;
+                    continue;
+                }
+            };
+            let width = advance(unit.clone());
+            if head_width + tail_width + width > room {
+                head_done |= from_head;
+                tail_done |= !from_head;
+            } else if from_head {
+                head_width += width;
+                head_end = unit.end;
+            } else {
+                tail_width += width;
+                tail_start = unit.start;
+            }
+        }
+    }
+    if head_end >= tail_start {
+        return None;
+    }
+    Some(Elision {
+        range: head_end..tail_start,
+        line_end,
+        line_end_excluding_whitespace: text_offset(&replaced, last.end_excluding_whitespaces),
+    })
+}
+
+/// The width of an ellipsis drawn with `attrs`.
+fn ellipsis_width(fonts: &SkiaText, attrs: &util::LayoutDefaults) -> f32 {
+    let font_size = attrs.font_size * attrs.baseline_shift.scale();
+    let font = Font::new(fonts.typeface(attrs), Some(font_size as f32));
+    font.measure_str(ELLIPSIS, None).0 + attrs.letter_spacing as f32
 }
 
 // It's convinient to have a separate method for creating paragraph, cause it doesn't have Clone
-// TODO all font related data should be moved into struct fields at some point
+//
+// The text in `elision` is left out, and an ellipsis is added in its place.
 fn build_paragraph(
     text: &str,
     defaults: &util::LayoutDefaults,
+    runs: &[util::AttributeRun],
+    fonts: &SkiaText,
     width_constraint: f32,
+    options: ParagraphOptions,
+    elision: Option<&Range<usize>>,
 ) -> Paragraph {
-    let mut paint = Paint::default();
-    let mut font_collection = FontCollection::new();
-    let font_mngr = FontMgr::new();
-    font_collection.set_default_font_manager(font_mngr, defaults.font.name());
     let mut paragraph_style = ParagraphStyle::new();
-    let mut text_style = TextStyle::new();
-    let fg_color = defaults.fg_color.clone();
-    let typeface = build_typeface(defaults);
-    text_style.set_typeface(Some(typeface));
-    text_style.set_font_size(defaults.font_size as f32);
-    paint.set_color(crate::convert_color(fg_color));
-    text_style.set_foreground_color(paint);
-    paragraph_style.set_text_style(&text_style);
-    let mut paragraph_builder = ParagraphBuilder::new(&paragraph_style, font_collection);
-    paragraph_builder.add_text(text);
+    paragraph_style.set_text_style(&build_text_style(fonts, defaults, options.line_height));
+    paragraph_style.set_text_direction(if util::is_rtl(text, options.direction) {
+        SkTextDirection::RTL
+    } else {
+        SkTextDirection::LTR
+    });
+    if let LineHeight::Absolute(height) = options.line_height {
+        // a forced strut makes every line the strut's height
+        let mut strut_style = StrutStyle::new();
+        strut_style
+            .set_strut_enabled(true)
+            .set_force_strut_height(true)
+            .set_font_size(height as f32)
+            .set_height(1.0)
+            .set_height_override(true);
+        paragraph_style.set_strut_style(strut_style);
+    }
+    if let Some(max_lines) = options.max_lines {
+        paragraph_style.set_max_lines(max_lines);
+    }
+    let mut paragraph_builder =
+        ParagraphBuilder::new(&paragraph_style, fonts.font_collection.clone());
+    for run in runs {
+        paragraph_builder.push_style(&build_text_style(fonts, &run.attrs, options.line_height));
+        // the parts of the run before and after the hidden text; placeholders
+        // are hidden as a whole, so they are in one part or neither.
+        let (before, after) = match elision {
+            Some(hidden) => (
+                run.range.start..run.range.end.min(hidden.start),
+                run.range.start.max(hidden.end)..run.range.end,
+            ),
+            None => (run.range.clone(), run.range.end..run.range.end),
+        };
+        // the ellipsis is styled like the text before it
+        let has_ellipsis = elision.map_or(false, |hidden| {
+            run.range.start < hidden.start.max(1) && hidden.start <= run.range.end
+        });
+        for (i, range) in [before, after].iter().enumerate() {
+            if i == 1 && has_ellipsis {
+                paragraph_builder.add_text(ELLIPSIS);
+            }
+            if range.is_empty() {
+                continue;
+            }
+            match run.attrs.placeholder {
+                // skia adds its own U+FFFC in place of the placeholder's text
+                Some(placeholder) => {
+                    paragraph_builder.add_placeholder(&PlaceholderStyle::new(
+                        placeholder.width as f32,
+                        placeholder.height as f32,
+                        PlaceholderAlignment::Baseline,
+                        TextBaseline::Alphabetic,
+                        placeholder.baseline as f32,
+                    ));
+                }
+                None => {
+                    paragraph_builder.add_text(&text[range.clone()]);
+                }
+            }
+        }
+        paragraph_builder.pop();
+    }
     let mut paragraph = paragraph_builder.build();
     paragraph.layout(width_constraint);
     paragraph
 }
 
+/// Lay out the text as a single skia paragraph or, if it has paragraph
+/// spacing or indents, as several; see [`Paragraphs`].
+///
+/// The text in `elision` is left out, and an ellipsis is added in its place.
+fn build_paragraphs(
+    text: &str,
+    defaults: &util::LayoutDefaults,
+    runs: &[util::AttributeRun],
+    fonts: &SkiaText,
+    width_constraint: f32,
+    options: ParagraphOptions,
+    elision: Option<&Range<usize>>,
+) -> Paragraphs {
+    let replaced = replaced_ranges(runs, elision);
+    if !options.is_split() {
+        let paragraph = build_paragraph(
+            text,
+            defaults,
+            runs,
+            fonts,
+            width_constraint,
+            options,
+            elision,
+        );
+        let part = Part {
+            paragraph,
+            start: 0,
+            end: paragraph_offset(&replaced, text.len()),
+            indent: 0.0,
+            origin: Vector::default(),
+        };
+        return Paragraphs { parts: vec![part] };
+    }
+
+    let mut parts = Vec::new();
+    let mut y = 0.0;
+    let mut lines_left = options.max_lines.unwrap_or(usize::MAX);
+    for range in paragraph_ranges(text, elision) {
+        // each paragraph has its own direction, for the side its indents are on
+        let direction = if util::is_rtl(&text[range.clone()], options.direction) {
+            piet::TextDirection::RightToLeft
+        } else {
+            piet::TextDirection::LeftToRight
+        };
+        let options = ParagraphOptions {
+            direction,
+            ..options
+        };
+        let first_line_indent = options.first_line_indent as f32;
+        let hanging_indent = options.hanging_indent as f32;
+        let mut pieces = vec![(range.clone(), first_line_indent)];
+        if hanging_indent != first_line_indent {
+            // the first line is as long as its own indent allows
+            let all_lines = ParagraphOptions {
+                max_lines: None,
+                ..options
+            };
+            let paragraph = build_part(
+                text,
+                defaults,
+                runs,
+                fonts,
+                range.clone(),
+                width_constraint - first_line_indent,
+                all_lines,
+                elision,
+            );
+            let second_line = paragraph.get_line_metrics().get(1).map(|lm| lm.start_index);
+            if let Some(second_line) = second_line {
+                let start = paragraph_offset(&replaced, range.start);
+                let split = text_offset(&replaced, start + second_line);
+                pieces = vec![
+                    (range.start..split, first_line_indent),
+                    (split..range.end, hanging_indent),
+                ];
+            }
+        }
+        for (range, indent) in pieces {
+            if lines_left == 0 {
+                break;
+            }
+            let options = ParagraphOptions {
+                max_lines: options.max_lines.map(|_| lines_left),
+                ..options
+            };
+            let paragraph = build_part(
+                text,
+                defaults,
+                runs,
+                fonts,
+                range.clone(),
+                width_constraint - indent,
+                options,
+                elision,
+            );
+            lines_left = lines_left.saturating_sub(paragraph.line_number());
+            // indents are from the start edge; right-to-left lines are already
+            // aligned to the right of their narrower width
+            let x = if direction == piet::TextDirection::RightToLeft {
+                0.0
+            } else {
+                indent
+            };
+            let height = paragraph.height();
+            parts.push(Part {
+                paragraph,
+                start: paragraph_offset(&replaced, range.start),
+                end: paragraph_offset(&replaced, range.end),
+                indent,
+                origin: Vector::new(x, y),
+            });
+            y += height;
+        }
+        if range.end < text.len() {
+            y += options.paragraph_spacing as f32;
+        }
+    }
+    Paragraphs { parts }
+}
+
+/// Lay out the text in `range` as a skia paragraph.
+#[allow(clippy::too_many_arguments)]
+fn build_part(
+    text: &str,
+    defaults: &util::LayoutDefaults,
+    runs: &[util::AttributeRun],
+    fonts: &SkiaText,
+    range: Range<usize>,
+    width_constraint: f32,
+    options: ParagraphOptions,
+    elision: Option<&Range<usize>>,
+) -> Paragraph {
+    let runs: Vec<util::AttributeRun> = runs
+        .iter()
+        .filter(|run| run.range.start < range.end && range.start < run.range.end)
+        .map(|run| util::AttributeRun {
+            range: run.range.start.max(range.start) - range.start
+                ..run.range.end.min(range.end) - range.start,
+            attrs: run.attrs.clone(),
+        })
+        .collect();
+    let elision = elision
+        .filter(|hidden| range.start <= hidden.start && hidden.end <= range.end)
+        .map(|hidden| hidden.start - range.start..hidden.end - range.start);
+    build_paragraph(
+        &text[range],
+        defaults,
+        &runs,
+        fonts,
+        width_constraint,
+        options,
+        elision.as_ref(),
+    )
+}
+
+/// The ranges of the paragraphs of `text`, without the newlines that end
+/// them; newlines in the `hidden` text don't end a paragraph.
+fn paragraph_ranges(text: &str, hidden: Option<&Range<usize>>) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    for (idx, _) in text.match_indices('\n') {
+        if hidden.map_or(false, |hidden| hidden.contains(&idx)) {
+            continue;
+        }
+        ranges.push(start..idx);
+        start = idx + 1;
+    }
+    ranges.push(start..text.len());
+    ranges
+}
+
 impl TextLayoutBuilder for SkiaTextLayoutBuilder {
     type Out = SkiaTextLayout;
 
@@ -156,37 +899,93 @@ impl TextLayoutBuilder for SkiaTextLayoutBuilder {
         self
     }
 
+    fn direction(mut self, direction: piet::TextDirection) -> Self {
+        self.options.direction = direction;
+        self
+    }
+
+    fn line_height(mut self, height: LineHeight) -> Self {
+        self.options.line_height = height;
+        self
+    }
+
+    fn paragraph_spacing(mut self, spacing: f64) -> Self {
+        self.options.paragraph_spacing = spacing;
+        self
+    }
+
+    fn first_line_indent(mut self, indent: f64) -> Self {
+        self.options.first_line_indent = indent;
+        self
+    }
+
+    fn hanging_indent(mut self, indent: f64) -> Self {
+        self.options.hanging_indent = indent;
+        self
+    }
+
+    fn max_lines(mut self, lines: usize) -> Self {
+        self.options.max_lines = Some(lines);
+        self
+    }
+
+    fn overflow(mut self, overflow: TextOverflow) -> Self {
+        self.options.overflow = overflow;
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         self.defaults.set(attribute);
         self
     }
 
     fn range_attribute(
-        self,
-        _range: impl RangeBounds<usize>,
-        _attribute: impl Into<TextAttribute>,
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
     ) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        self.attributes.add(range, attribute.into());
         self
-        // TODO
     }
 
     fn build(self) -> Result<Self::Out, Error> {
-        let layout = if self.width_constraint.is_finite() {
-            let paragraph = build_paragraph(
+        // the simple layout only supports a single style and doesn't shape
+        // or decorate text, so any range attributes, font features, spacing,
+        // baseline shift, backgrounds, decorations, placeholders, direction,
+        // line height, paragraph spacing, indents or line limit require a
+        // paragraph.
+        let needs_paragraph = self.width_constraint.is_finite()
+            || !self.attributes.is_empty()
+            || !self.defaults.font_features.is_empty()
+            || self.defaults.letter_spacing != 0.0
+            || self.defaults.word_spacing != 0.0
+            || self.defaults.baseline_shift != BaselineShift::None
+            || self.defaults.background_color.is_some()
+            || self.defaults.placeholder.is_some()
+            || self.defaults.underline
+            || self.defaults.strikethrough
+            || self.defaults.overline
+            || self.options.direction != piet::TextDirection::Auto
+            || self.options.line_height != LineHeight::Normal
+            || self.options.is_split()
+            || self.options.max_lines.is_some();
+        let layout = if needs_paragraph {
+            let runs: Rc<[util::AttributeRun]> =
+                self.attributes.runs(&self.defaults, self.text.len()).into();
+            let (paragraphs, elision) = layout_paragraph(
                 self.text.as_str(),
                 &self.defaults,
+                &runs,
+                &self.fonts,
                 self.width_constraint as f32,
+                self.options,
             );
-            let width = paragraph
-                .get_line_metrics()
+            let width = paragraphs
+                .lines()
                 .iter()
-                .map(|l| l.width)
-                .max_by(|x, y| {
-                    x.abs()
-                        .partial_cmp(&y.abs())
-                        .unwrap_or(std::cmp::Ordering::Equal)
-                })
-                .unwrap_or(0.) as f32;
+                .map(|l| l.indent + l.width.abs())
+                .fold(0.0, f64::max) as f32;
 
             // note: if you do paragraph.layout(width) again it will wrap last word
             // on each line because it's exact size as width_constraint
@@ -194,13 +993,18 @@ impl TextLayoutBuilder for SkiaTextLayoutBuilder {
                 text: self.text,
                 width,
                 defaults: Rc::new(self.defaults),
-                paragraph,
+                fonts: self.fonts,
+                options: self.options,
+                paragraphs: Rc::new(RefCell::new(paragraphs)),
+                replaced: replaced_ranges(&runs, elision.as_ref().map(|e| &e.range)).into(),
+                elision,
+                runs,
             })
         } else {
             let mut paint = Paint::default();
             let font = {
                 let size = self.defaults.font_size;
-                let typeface = build_typeface(&self.defaults);
+                let typeface = self.fonts.typeface(&self.defaults);
                 Font::new(typeface, Some(size as f32))
             };
             let fg_color = self.defaults.fg_color;
@@ -240,7 +1044,10 @@ impl TextLayout for SkiaTextLayout {
     }
 
     fn trailing_whitespace_width(&self) -> f64 {
-        unimplemented!();
+        match self {
+            SkiaTextLayout::Paragraph(paragraph) => paragraph.trailing_whitespace_width(),
+            SkiaTextLayout::Simple(simple) => simple.trailing_whitespace_width(),
+        }
     }
 
     fn image_bounds(&self) -> Rect {
@@ -291,6 +1098,27 @@ impl TextLayout for SkiaTextLayout {
             SkiaTextLayout::Simple(simple) => simple.hit_test_text_position(idx),
         }
     }
+
+    fn placeholder_rects(&self) -> Vec<(Range<usize>, Rect)> {
+        match self {
+            SkiaTextLayout::Paragraph(paragraph) => paragraph.placeholder_rects(),
+            SkiaTextLayout::Simple(_) => Vec::new(),
+        }
+    }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        match self {
+            SkiaTextLayout::Paragraph(paragraph) => paragraph.glyph_runs(),
+            SkiaTextLayout::Simple(simple) => simple.glyph_runs(),
+        }
+    }
+
+    fn glyph_outlines(&self) -> Vec<BezPath> {
+        match self {
+            SkiaTextLayout::Paragraph(paragraph) => paragraph.glyph_outlines(),
+            SkiaTextLayout::Simple(simple) => simple.glyph_outlines(),
+        }
+    }
 }
 
 impl ParagraphTextLayout {
@@ -298,22 +1126,83 @@ impl ParagraphTextLayout {
         self.defaults.fg_color.clone()
     }
 
+    /// The skia paragraph behind this layout, which may be shared with its
+    /// clones.
+    ///
+    /// This is `None` if the layout has paragraph spacing or indents, which
+    /// skia's paragraphs don't have: such a layout is laid out as several skia
+    /// paragraphs. Use [`paint`] to draw any layout.
+    ///
+    /// The paragraph is borrowed from a `RefCell`, and methods of this layout
+    /// such as [`glyph_runs`] borrow it mutably, so the returned borrow must
+    /// be dropped before calling other methods of the layout or of its clones;
+    /// otherwise they will panic.
+    ///
+    /// [`paint`]: #method.paint
+    /// [`glyph_runs`]: #method.glyph_runs
+    pub fn paragraph(&self) -> Option<Ref<'_, Paragraph>> {
+        if self.options.is_split() {
+            return None;
+        }
+        Some(Ref::map(self.paragraphs.borrow(), |paragraphs| {
+            &paragraphs.parts[0].paragraph
+        }))
+    }
+
+    /// Draw the layout with its top left corner at `origin`.
+    pub fn paint(&self, canvas: &mut Canvas, origin: skia_safe::Point) {
+        self.paragraphs.borrow().paint(canvas, origin);
+    }
+
     // this is the most efficient way for updating width, because skia's paragraph perform cashing
     // for layout function
     pub fn update_width(&mut self, new_width: f32) {
         self.width = new_width;
-        self.paragraph.layout(self.width);
+        // the text hidden behind an ellipsis depends on the width, and so do
+        // the lines of the paragraphs that have indents
+        if self.options.overflow == TextOverflow::Clip && !self.options.is_split() {
+            if let Some(paragraphs) = Rc::get_mut(&mut self.paragraphs) {
+                paragraphs.get_mut().parts[0].paragraph.layout(new_width);
+                return;
+            }
+        }
+        // the paragraph is shared with a clone, which should keep its width
+        let (paragraphs, elision) = layout_paragraph(
+            self.text.as_str(),
+            &self.defaults,
+            &self.runs,
+            &self.fonts,
+            new_width,
+            self.options,
+        );
+        self.paragraphs = Rc::new(RefCell::new(paragraphs));
+        self.replaced = replaced_ranges(&self.runs, elision.as_ref().map(|e| &e.range)).into();
+        self.elision = elision;
     }
 }
 
 impl TextLayout for ParagraphTextLayout {
     fn size(&self) -> Size {
-        let size = Size::new(self.width as f64, self.paragraph.height() as f64);
+        let size = Size::new(self.width as f64, self.paragraphs.borrow().height() as f64);
         size
     }
 
     fn trailing_whitespace_width(&self) -> f64 {
-        unimplemented!();
+        // the widest line, measured to the end of its trailing whitespace
+        let paragraphs = self.paragraphs.borrow();
+        (0..self.line_count())
+            .filter_map(|line_number| self.line_metric(line_number))
+            .map(|lm| {
+                let line_text = self.text.as_str().get(lm.range()).unwrap_or_default();
+                let end = lm.end_offset - util::trailing_nlf(line_text).unwrap_or(0);
+                let range = self.paragraph_offset(lm.start_offset)..self.paragraph_offset(end);
+                paragraphs
+                    .rects_for_range(range)
+                    .iter()
+                    .map(|rect| rect.right as f64)
+                    .fold(0.0, f64::max)
+            })
+            .fold(0.0, f64::max)
     }
 
     fn image_bounds(&self) -> Rect {
@@ -324,62 +1213,87 @@ impl TextLayout for ParagraphTextLayout {
         &self.text
     }
 
-    fn line_text(&self, _line_number: usize) -> Option<&str> {
-        unimplemented!();
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        let lm = self.line_metric(line_number)?;
+        self.text.as_str().get(lm.range())
     }
 
     fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
-        self.paragraph
-            .get_line_metrics()
-            .as_slice()
-            .get(line_number)
-            .map(|line_metric| LineMetric {
-                start_offset: line_metric.start_index,
-                end_offset: line_metric.end_index,
-                trailing_whitespace: line_metric.end_index - line_metric.end_excluding_whitespaces,
+        let lines = self.paragraphs.borrow().lines();
+        let is_last = line_number + 1 == lines.len();
+        lines.get(line_number).map(|line_metric| {
+            // the last line covers its text from before it was truncated
+            let (end_offset, end_excluding_whitespace) = match &self.elision {
+                Some(elision) if is_last => {
+                    (elision.line_end, elision.line_end_excluding_whitespace)
+                }
+                _ => (
+                    self.text_offset(line_metric.end_index),
+                    self.text_offset(line_metric.end_excluding_whitespaces),
+                ),
+            };
+            LineMetric {
+                start_offset: self.text_offset(line_metric.start_index),
+                end_offset,
+                trailing_whitespace: end_offset - end_excluding_whitespace,
                 baseline: line_metric.baseline,
                 height: line_metric.height,
                 y_offset: line_metric.baseline - line_metric.ascent,
-            })
+            }
+        })
     }
 
     fn line_count(&self) -> usize {
-        self.paragraph.line_number()
+        self.paragraphs.borrow().line_number()
     }
 
     fn hit_test_point(&self, point: Point) -> HitTestPoint {
         let skia_point = skia_safe::Point::new(point.x as f32, point.y as f32);
-        let position = self.paragraph.get_glyph_position_at_coordinate(skia_point);
-        let idx = position.position as usize;
-        let text_boxes = self.paragraph.get_rects_for_range(
-            idx..(idx + 1),
-            RectHeightStyle::Tight,
-            RectWidthStyle::Tight,
-        );
+        let paragraphs = self.paragraphs.borrow();
+        let idx = paragraphs.glyph_position_at_coordinate(skia_point);
+        let rects = paragraphs.rects_for_range(idx..(idx + 1));
         let mut contains = false;
-        for text_box in text_boxes.iter() {
-            if text_box.rect.contains(skia_point) {
+        for rect in rects.iter() {
+            if rect.contains(skia_point) {
                 contains = true
             }
         }
-        HitTestPoint::new(idx, contains)
+        drop(paragraphs);
+        let idx = self.text_offset(idx);
+
+        // past the end of a wrapped line, the caret stays on the line
+        let wrapped_line = (0..self.line_count().saturating_sub(1))
+            .filter_map(|line_number| self.line_metric(line_number))
+            .find(|lm| point.y < lm.y_offset + lm.height)
+            .filter(|lm| util::trailing_nlf(&self.text[lm.range()]).is_none());
+        match wrapped_line {
+            Some(lm) if idx >= lm.end_offset - lm.trailing_whitespace => {
+                HitTestPoint::with_affinity(lm.end_offset, contains, Affinity::Upstream)
+            }
+            _ => HitTestPoint::new(idx, contains),
+        }
     }
 
     fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
-        let idx = idx.min(self.text.len());
-        let clipped_idx = if self.text.len() == idx { idx - 1 } else { idx }; // handling case when idx = text length
-        let text_boxes = self.paragraph.get_rects_for_range(
-            clipped_idx..(clipped_idx + 1),
-            RectHeightStyle::Tight,
-            RectWidthStyle::Tight,
-        );
-        let res = if let Some(glyph_box) = text_boxes.iter().next() {
-            let point = if idx == self.text.len() {
-                Point::new(glyph_box.rect.right as f64, glyph_box.rect.top as f64)
+        let len = self.paragraph_offset(self.text.len());
+        let idx = self.paragraph_offset(idx.min(self.text.len()));
+        // handling case when idx = text length
+        let clipped_idx = if len == idx {
+            idx.saturating_sub(1)
+        } else {
+            idx
+        };
+        let rects = self
+            .paragraphs
+            .borrow()
+            .rects_for_range(clipped_idx..(clipped_idx + 1));
+        if let Some(glyph_rect) = rects.iter().next() {
+            let point = if idx == len {
+                Point::new(glyph_rect.right as f64, glyph_rect.top as f64)
             } else {
-                Point::new(glyph_box.rect.left as f64, glyph_box.rect.top as f64)
+                Point::new(glyph_rect.left as f64, glyph_rect.top as f64)
             };
-            let center = &glyph_box.rect.center();
+            let center = &glyph_rect.center();
             let mut i = 0;
             let mut line_number = 0;
             while let Some(metrics) = self.line_metric(i) {
@@ -392,16 +1306,403 @@ impl TextLayout for ParagraphTextLayout {
             }
             HitTestPosition::new(point, line_number)
         } else {
-            let info = format!("{} -- {}", self.text.as_str(), self.text.as_str().len());
-            dbg!(&info);
-            dbg!(idx);
-            //panic!();
             HitTestPosition::new(Point::new(0., 0.), 0)
+        }
+    }
+
+    fn placeholder_rects(&self) -> Vec<(Range<usize>, Rect)> {
+        // skia reports the placeholders it laid out, in text order
+        let ranges = self
+            .runs
+            .iter()
+            .filter(|run| run.attrs.placeholder.is_some() && self.replaced.contains(&run.range))
+            .map(|run| run.range.clone());
+        ranges
+            .zip(self.paragraphs.borrow().placeholder_rects())
+            .map(|(range, rect)| {
+                let rect = Rect::new(
+                    rect.left as f64,
+                    rect.top as f64,
+                    rect.right as f64,
+                    rect.bottom as f64,
+                );
+                (range, rect)
+            })
+            .collect()
+    }
+
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        let mut glyph_runs = Vec::new();
+        self.visit_glyphs(|_, run| glyph_runs.push(run));
+        glyph_runs
+    }
+
+    fn glyph_outlines(&self) -> Vec<BezPath> {
+        let mut outlines = Vec::new();
+        self.visit_glyphs(|font, run| {
+            outlines.extend(run.glyphs.iter().map(|glyph| glyph_outline(font, glyph)));
+        });
+        outlines
+    }
+}
+
+impl ParagraphTextLayout {
+    /// Calls `f` with each run of glyphs in the paragraph, and its font.
+    fn visit_glyphs(&self, mut f: impl FnMut(&Font, GlyphRun)) {
+        let mut paragraphs = self.paragraphs.borrow_mut();
+        for part in paragraphs.parts.iter_mut() {
+            let (part_origin, part_start) = (part.origin, part.start);
+            part.paragraph.visit(|_line, info| {
+                let info = match info {
+                    Some(info) if info.count() > 0 => info,
+                    _ => return,
+                };
+                let origin = info.origin();
+                let positions = info.positions();
+                let starts = info.utf8_starts();
+                let glyphs: Vec<Glyph> = info
+                    .glyphs()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, id)| {
+                        let x = positions[i].x;
+                        let next_x = positions.get(i + 1).map_or(info.advance_x(), |p| p.x);
+                        Glyph {
+                            id: *id as u32,
+                            position: Point::new(
+                                (part_origin.x + origin.x + x) as f64,
+                                (part_origin.y + origin.y + positions[i].y) as f64,
+                            ),
+                            advance: (next_x - x) as f64,
+                            cluster: self.text_offset(part_start + starts[i] as usize),
+                        }
+                    })
+                    .collect();
+                let start = self.text_offset(part_start + starts[0] as usize);
+                let end = self.text_offset(part_start + starts[info.count()] as usize);
+                let range = start.min(end)..start.max(end);
+                let (font, weight, style) = font_info(info.font());
+
+                // skia doesn't split runs where only the color changes, so we
+                // split them by the attribute run each glyph comes from.
+                let mut pieces: Vec<(usize, Vec<Glyph>)> = Vec::new();
+                for glyph in glyphs {
+                    let idx = self
+                        .runs
+                        .iter()
+                        .position(|run| run.range.contains(&glyph.cluster))
+                        .unwrap_or(0);
+                    match pieces.last_mut() {
+                        Some((last, piece)) if *last == idx => piece.push(glyph),
+                        _ => pieces.push((idx, vec![glyph])),
+                    }
+                }
+                for (idx, glyphs) in pieces {
+                    let (range, color) = match self.runs.get(idx) {
+                        Some(attr_run) => (
+                            range.start.max(attr_run.range.start)
+                                ..range.end.min(attr_run.range.end),
+                            attr_run.attrs.fg_color.clone(),
+                        ),
+                        None => (range.clone(), self.fg_color()),
+                    };
+                    let run = GlyphRun {
+                        range,
+                        font: font.clone(),
+                        font_size: info.font().size() as f64,
+                        weight,
+                        style,
+                        color,
+                        glyphs,
+                    };
+                    f(info.font(), run);
+                }
+            });
+        }
+    }
+
+    /// Maps an offset in the paragraph's text to one in ours.
+    fn text_offset(&self, offset: usize) -> usize {
+        text_offset(&self.replaced, offset)
+    }
+
+    /// Maps an offset in our text to one in the paragraph's.
+    fn paragraph_offset(&self, offset: usize) -> usize {
+        paragraph_offset(&self.replaced, offset)
+    }
+}
+
+/// The ranges of the text that a paragraph replaces with a single character,
+/// in order: skia adds a U+FFFC in place of the text of each placeholder, and
+/// we add an ellipsis in place of the `hidden` text.
+fn replaced_ranges(
+    runs: &[util::AttributeRun],
+    hidden: Option<&Range<usize>>,
+) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = runs
+        .iter()
+        .filter(|run| run.attrs.placeholder.is_some())
+        .map(|run| run.range.clone())
+        .filter(|range| hidden.map_or(true, |h| range.end <= h.start || h.end <= range.start))
+        .collect();
+    if let Some(hidden) = hidden {
+        let idx = ranges
+            .iter()
+            .position(|range| range.start >= hidden.end)
+            .unwrap_or_else(|| ranges.len());
+        ranges.insert(idx, hidden.clone());
+    }
+    ranges
+}
+
+/// Maps an offset in a paragraph's text to one in ours, given the `replaced`
+/// ranges of our text.
+fn text_offset(replaced: &[Range<usize>], offset: usize) -> usize {
+    // U+FFFC and the ellipsis are both three bytes long
+    const REPLACEMENT_LEN: isize = '\u{FFFC}'.len_utf8() as isize;
+    let mut removed = 0isize;
+    for range in replaced {
+        let start = range.start as isize - removed;
+        if offset as isize <= start {
+            break;
+        }
+        removed += range.len() as isize - REPLACEMENT_LEN;
+    }
+    (offset as isize + removed) as usize
+}
+
+/// Maps an offset in our text to one in a paragraph's, given the `replaced`
+/// ranges of our text; offsets inside a replaced range map to the start of
+/// its replacement.
+fn paragraph_offset(replaced: &[Range<usize>], offset: usize) -> usize {
+    const REPLACEMENT_LEN: isize = '\u{FFFC}'.len_utf8() as isize;
+    let mut removed = 0isize;
+    for range in replaced {
+        if offset <= range.start {
+            break;
+        }
+        if offset < range.end {
+            return (range.start as isize - removed) as usize;
+        }
+        removed += range.len() as isize - REPLACEMENT_LEN;
+    }
+    (offset as isize - removed) as usize
+}
+
+/// The outline of `glyph`, drawn with `font`, at the glyph's position.
+pub(crate) fn glyph_outline(font: &Font, glyph: &Glyph) -> BezPath {
+    let path = font
+        .get_path(glyph.id as u16)
+        .map(|path| crate::to_bez_path(&path))
+        .unwrap_or_default();
+    Affine::translate(glyph.position.to_vec2()) * path
+}
+
+/// The family, weight and style of the typeface behind `font`.
+pub(crate) fn font_info(font: &Font) -> (FontFamily, piet::FontWeight, piet::FontStyle) {
+    let typeface = font.typeface_or_default();
+    let font_style = typeface.font_style();
+    let style = match font_style.slant() {
+        Slant::Upright => piet::FontStyle::Regular,
+        Slant::Italic | Slant::Oblique => piet::FontStyle::Italic,
+    };
+    (
+        FontFamily::new_unchecked(typeface.family_name()),
+        piet::FontWeight::new(*font_style.weight() as u16),
+        style,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use piet::Placeholder;
+
+    #[test]
+    fn offsets_after_placeholder() {
+        // the placeholder covers two bytes, which skia replaces with three
+        let text = "ab xyz";
+        let placeholder = Placeholder {
+            width: 10.0,
+            height: 10.0,
+            baseline: 8.0,
         };
-        //dbg!(idx, &res);
-        res
-        //let glyph_box = text_boxes.iter().next().expect(&format!("hit_test_text_position called with idx={} out of boundary {}", idx, self.text.as_str().len()));
-        //let point = Point::new(glyph_box.rect.left as f64, glyph_box.rect.top as f64);
-        //HitTestPosition::new(point, 0)
+        let layout = SkiaText::new()
+            .new_text_layout(text)
+            .range_attribute(0..2, placeholder)
+            .build()
+            .unwrap();
+
+        let lm = layout.line_metric(0).unwrap();
+        assert_eq!(lm.range(), 0..text.len());
+        assert_eq!(layout.line_text(0), Some(text));
+
+        let pos = layout.hit_test_text_position(4);
+        let hit = layout.hit_test_point(Point::new(pos.point.x + 1.0, pos.point.y + 1.0));
+        assert_eq!(hit.idx, 4);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn caret_across_soft_break() {
+        let layout = SkiaText::new()
+            .new_text_layout("hello world")
+            .max_width(40.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+        let first = layout.line_metric(0).unwrap();
+        let second = layout.line_metric(1).unwrap();
+        assert_eq!(layout.line_text(0), Some("hello "));
+
+        // the end of the first line is the start of the second
+        let (end, affinity) = layout.line_end_offset(0, Affinity::Downstream);
+        assert_eq!((end, affinity), (first.end_offset, Affinity::Upstream));
+        let upstream = layout.cursor_rect(end, Affinity::Upstream);
+        let downstream = layout.cursor_rect(end, Affinity::Downstream);
+        assert_eq!(upstream.y0, first.y_offset);
+        assert_eq!(downstream.y0, second.y_offset);
+
+        // a point past the end of the first line stays on it
+        let hit = layout.hit_test_point(Point::new(1000.0, first.y_offset + 1.0));
+        assert_eq!(hit.idx, first.end_offset);
+        assert_eq!(hit.affinity, Affinity::Upstream);
+        let hit = layout.hit_test_point(Point::new(1000.0, second.y_offset + 1.0));
+        assert_eq!(hit.affinity, Affinity::Downstream);
+
+        let (below, _) = layout.offset_below(0, Affinity::Downstream, 0.0);
+        assert_eq!(below, second.start_offset);
+        let (above, _) = layout.offset_above(below, Affinity::Downstream, 0.0);
+        assert_eq!(above, 0);
+    }
+
+    #[test]
+    fn middle_ellipsis_hides_text() {
+        let text = "the quick brown fox jumps";
+        let layout = SkiaText::new()
+            .new_text_layout(text)
+            .max_width(80.0)
+            .max_lines(1)
+            .overflow(TextOverflow::MiddleEllipsis)
+            .build()
+            .unwrap();
+        assert_eq!(layout.text(), text);
+        assert_eq!(layout.line_count(), 1);
+
+        // the end of the text is drawn after the ellipsis
+        let lm = layout.line_metric(0).unwrap();
+        let end = layout.hit_test_point(Point::new(1000.0, lm.baseline));
+        assert_eq!(end.idx, text.len());
+
+        // hidden text is at the ellipsis, which hits the start of that text
+        let hidden = text.find("brown").unwrap();
+        let pos = layout.hit_test_text_position(hidden);
+        let hit = layout.hit_test_point(Point::new(pos.point.x + 0.5, pos.point.y + 1.0));
+        assert!(hit.idx < hidden);
+    }
+
+    #[test]
+    fn paragraph_spacing_and_indent() {
+        let mut text = SkiaText::new();
+        let input = "piet text\nmost best";
+        let plain = text
+            .new_text_layout(input)
+            .max_width(1000.0)
+            .build()
+            .unwrap();
+        let styled = text
+            .new_text_layout(input)
+            .max_width(1000.0)
+            .paragraph_spacing(10.0)
+            .first_line_indent(20.0)
+            .build()
+            .unwrap();
+        assert_eq!(styled.line_count(), 2);
+
+        // the second paragraph is moved down by the spacing
+        let second = styled.line_metric(1).unwrap();
+        let plain_second = plain.line_metric(1).unwrap();
+        assert_eq!(second.start_offset, plain_second.start_offset);
+        assert!((second.y_offset - plain_second.y_offset - 10.0).abs() < 0.001);
+        assert!((second.baseline - plain_second.baseline - 10.0).abs() < 0.001);
+        let size = styled.size();
+        assert!((size.height - plain.size().height - 10.0).abs() < 0.001);
+        assert!((size.width - plain.size().width - 20.0).abs() < 0.001);
+
+        // both paragraphs start with an indented line
+        for &idx in &[0, 10] {
+            let x = styled.hit_test_text_position(idx).point.x;
+            let plain_x = plain.hit_test_text_position(idx).point.x;
+            assert!((x - plain_x - 20.0).abs() < 0.001);
+        }
+
+        // the space between paragraphs hits the second line
+        let hit = styled.hit_test_point(Point::new(0.0, second.y_offset - 5.0));
+        assert_eq!(hit.idx, 10);
+        let rects = styled.rects_for_range(10..14);
+        assert!((rects[0].y0 - second.y_offset).abs() < 0.001);
+    }
+
+    #[test]
+    fn hanging_indent() {
+        let mut text = SkiaText::new();
+        let width = text
+            .new_text_layout("piet text")
+            .max_width(1000.0)
+            .build()
+            .unwrap()
+            .size()
+            .width;
+        let layout = text
+            .new_text_layout("piet text piet text")
+            .max_width(width + 10.0)
+            .hanging_indent(11.0)
+            .build()
+            .unwrap();
+
+        // the second line would fit without the indent
+        assert_eq!(layout.line_text(0), Some("piet text "));
+        assert_eq!(layout.line_text(1), Some("piet "));
+        assert_eq!(layout.line_text(2), Some("text"));
+        let x = layout.hit_test_text_position(10).point.x;
+        assert!((x - 11.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn outlines_of_both_layouts() {
+        let mut text = SkiaText::new();
+        let simple = text.new_text_layout("abc").build().unwrap();
+        assert!(matches!(simple, SkiaTextLayout::Simple(_)));
+        assert_eq!(simple.glyph_outlines().len(), 3);
+        assert!(!simple.outline().elements().is_empty());
+
+        let paragraph = text
+            .new_text_layout("abc")
+            .default_attribute(TextAttribute::Underline(true))
+            .build()
+            .unwrap();
+        assert!(matches!(paragraph, SkiaTextLayout::Paragraph(_)));
+        assert_eq!(paragraph.glyph_outlines().len(), 3);
+        assert!(!paragraph.outline().elements().is_empty());
+    }
+
+    #[test]
+    fn text_on_path_paragraph() {
+        // underlining the text makes it a paragraph layout
+        let layout = SkiaText::new()
+            .new_text_layout("abc")
+            .default_attribute(TextAttribute::Underline(true))
+            .build()
+            .unwrap();
+        assert!(matches!(layout, SkiaTextLayout::Paragraph(_)));
+
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((100.0, 0.0));
+        let clusters = util::text_on_path(&layout, &path, 0.0, piet::TextAlignment::Start);
+        let ranges: Vec<_> = clusters.iter().map(|c| c.range.clone()).collect();
+        assert_eq!(ranges, vec![0..1, 1..2, 2..3]);
+        let last = &clusters[2].bounds;
+        assert!(last.x1 > last.x0);
     }
 }
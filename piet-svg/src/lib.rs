@@ -1,6 +1,7 @@
 //! SVG output support for piet
 //!
-//! Text and images are unimplemented and will always return errors.
+//! Images are unimplemented and will always return errors. Text is written
+//! out as SVG text, for the viewer to lay out.
 
 #![deny(clippy::trivially_copy_pass_by_ref)]
 
@@ -9,10 +10,10 @@ mod text;
 use std::borrow::Cow;
 use std::{io, mem};
 
-use piet::kurbo::{Affine, Point, Rect, Shape, Size};
+use piet::kurbo::{Affine, BezPath, Point, Rect, Shape, Size};
 use piet::{
     Color, Error, FixedGradient, Image, ImageFormat, InterpolationMode, IntoBrush, LineCap,
-    LineJoin, StrokeStyle,
+    LineJoin, StrokeStyle, TextAlignment,
 };
 use svg::node::Node;
 
@@ -176,6 +177,19 @@ impl piet::RenderContext for RenderContext {
         self.state.clip = Some(id);
     }
 
+    fn clip_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) -> Result<()> {
+        let id = self.new_id();
+        let mut text = layout.to_svg(pos.into());
+        text.assign("transform", xf_val(&self.state.xf));
+        if let Some(clip) = self.state.clip {
+            text.assign("clip-path", format!("url(#{})", clip.to_string()));
+        }
+        let clip = svg::node::element::ClipPath::new().set("id", id).add(text);
+        self.doc.append(clip);
+        self.state.clip = Some(id);
+        Ok(())
+    }
+
     fn stroke(&mut self, shape: impl Shape, brush: &impl IntoBrush<Self>, width: f64) {
         let brush = brush.make_brush(self, || shape.bounding_box());
         add_shape(
@@ -214,8 +228,35 @@ impl piet::RenderContext for RenderContext {
         &mut self.text
     }
 
-    fn draw_text(&mut self, _layout: &Self::TextLayout, _pos: impl Into<Point>) {
-        unimplemented!()
+    fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) {
+        let mut text = layout.to_svg(pos.into());
+        text.assign("transform", xf_val(&self.state.xf));
+        if let Some(id) = self.state.clip {
+            text.assign("clip-path", format!("url(#{})", id.to_string()));
+        }
+        self.doc.append(text);
+    }
+
+    fn draw_text_on_path(
+        &mut self,
+        layout: &Self::TextLayout,
+        path: &BezPath,
+        start_offset: f64,
+        alignment: TextAlignment,
+    ) -> Result<()> {
+        let id = self.new_id();
+        let path = svg::node::element::Path::new()
+            .set("id", id)
+            .set("d", path.to_svg());
+        self.doc
+            .append(svg::node::element::Definitions::new().add(path));
+        let mut text = layout.to_svg_on_path(&id.to_string(), start_offset, alignment);
+        text.assign("transform", xf_val(&self.state.xf));
+        if let Some(id) = self.state.clip {
+            text.assign("clip-path", format!("url(#{})", id.to_string()));
+        }
+        self.doc.append(text);
+        Ok(())
     }
 
     fn save(&mut self) -> Result<()> {
@@ -489,3 +530,137 @@ impl From<Id> for svg::node::Value {
         x.to_string().into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use piet::{
+        FontFamily, RenderContext as _, Text as _, TextAttribute, TextLayoutBuilder as _,
+        UnderlineStyle,
+    };
+
+    fn to_string(rc: &RenderContext) -> String {
+        let mut out = Vec::new();
+        rc.write(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// The attributes and text of each `<tspan>` in `svg`.
+    fn tspans(svg: &str) -> Vec<(&str, &str)> {
+        svg.split("<tspan")
+            .skip(1)
+            .map(|span| {
+                let (attrs, rest) = span.split_at(span.find('>').unwrap());
+                (attrs, &rest[1..rest.find('<').unwrap()])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn text_spacing() {
+        let mut rc = RenderContext::new();
+        let layout = rc
+            .text()
+            .new_text_layout("A & B\nC")
+            .font(FontFamily::SANS_SERIF, 10.0)
+            .default_attribute(TextAttribute::LetterSpacing(2.0))
+            .range_attribute(0..5, TextAttribute::WordSpacing(4.0))
+            .build()
+            .unwrap();
+        rc.draw_text(&layout, (0.0, 0.0));
+        let svg = to_string(&rc);
+
+        // a tspan for each line, holding a tspan for each run of the line
+        let spans = tspans(&svg);
+        assert_eq!(spans.len(), 4, "{}", svg);
+        let (first_line, second_line) = (spans[0].0, spans[2].0);
+        assert!(first_line.contains("x=\"0\""), "{}", svg);
+        assert!(second_line.contains("x=\"0\""), "{}", svg);
+
+        let (attrs, text) = spans[1];
+        assert_eq!(text, "A &amp; B");
+        assert!(attrs.contains("letter-spacing=\"2\""), "{}", svg);
+        assert!(attrs.contains("word-spacing=\"4\""), "{}", svg);
+
+        let (attrs, text) = spans[3];
+        assert_eq!(text, "C");
+        assert!(attrs.contains("letter-spacing=\"2\""), "{}", svg);
+        assert!(!attrs.contains("word-spacing"), "{}", svg);
+    }
+
+    #[test]
+    fn text_decoration() {
+        let mut rc = RenderContext::new();
+        let layout = rc
+            .text()
+            .new_text_layout("plain wavy")
+            .font(FontFamily::SANS_SERIF, 10.0)
+            .range_attribute(6..10, UnderlineStyle::Wavy)
+            .range_attribute(
+                6..10,
+                TextAttribute::DecorationColor(Color::rgb8(255, 0, 0)),
+            )
+            .range_attribute(6..10, TextAttribute::DecorationThickness(2.0))
+            .build()
+            .unwrap();
+        rc.draw_text(&layout, (0.0, 0.0));
+        let svg = to_string(&rc);
+
+        let spans = tspans(&svg);
+        assert_eq!(spans.len(), 3, "{}", svg);
+
+        let (attrs, text) = spans[1];
+        assert_eq!(text, "plain ");
+        assert!(!attrs.contains("text-decoration"), "{}", svg);
+
+        let (attrs, text) = spans[2];
+        assert_eq!(text, "wavy");
+        assert!(attrs.contains("text-decoration=\"underline\""), "{}", svg);
+        assert!(attrs.contains("text-decoration-style=\"wavy\""), "{}", svg);
+        assert!(
+            attrs.contains("text-decoration-color=\"#ff0000\""),
+            "{}",
+            svg
+        );
+        assert!(attrs.contains("text-decoration-thickness=\"2\""), "{}", svg);
+    }
+
+    #[test]
+    fn text_on_path() {
+        let mut rc = RenderContext::new();
+        let layout = rc
+            .text()
+            .new_text_layout("on a path\nnot this")
+            .build()
+            .unwrap();
+        let mut path = piet::kurbo::BezPath::new();
+        path.move_to((0.0, 50.0));
+        path.quad_to((50.0, 0.0), (100.0, 50.0));
+        rc.draw_text_on_path(&layout, &path, 50.0, TextAlignment::Center)
+            .unwrap();
+        let svg = to_string(&rc);
+
+        assert!(svg.contains("<defs>"), "{}", svg);
+        assert!(svg.contains("id=\"a\""), "{}", svg);
+        assert!(svg.contains("href=\"#a\""), "{}", svg);
+        assert!(svg.contains("startOffset=\"50\""), "{}", svg);
+        assert!(svg.contains("text-anchor=\"middle\""), "{}", svg);
+        assert!(svg.contains(">on a path<"), "{}", svg);
+        assert!(!svg.contains("not this"), "{}", svg);
+    }
+
+    #[test]
+    fn clip_text_is_restored() {
+        let mut rc = RenderContext::new();
+        let layout = rc.text().new_text_layout("clip").build().unwrap();
+        rc.save().unwrap();
+        rc.clip_text(&layout, (0.0, 0.0)).unwrap();
+        rc.clear(Color::WHITE);
+        rc.restore().unwrap();
+        rc.clear(Color::BLACK);
+        let svg = to_string(&rc);
+
+        assert!(svg.contains("<clipPath"), "{}", svg);
+        assert_eq!(svg.matches("clip-path=\"url(#a)\"").count(), 1, "{}", svg);
+    }
+}
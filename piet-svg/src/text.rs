@@ -1,15 +1,31 @@
 //! Text functionality for Piet svg backend
+//!
+//! The SVG backend has no access to fonts: text is written out as `<text>`
+//! elements for the viewer to lay out, and the metrics of a layout are
+//! estimated from its font sizes. Lines are only broken at newlines.
 
-use std::ops::RangeBounds;
+use std::fmt;
+use std::ops::{Range, RangeBounds};
+use std::rc::Rc;
 
 use piet::kurbo::{Point, Rect, Size};
 use piet::{
-    Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, TextAttribute, TextStorage,
+    util, Error, FontFamily, FontStyle, FontWeight, HitTestPoint, HitTestPosition, LineMetric,
+    TextAlignment, TextAttribute, TextStorage, UnderlineStyle,
 };
+use svg::node::element;
+use svg::node::Node;
 
 type Result<T> = std::result::Result<T, Error>;
 
-/// SVG text (unimplemented)
+/// The estimated advance of a character, as a fraction of the font size.
+const ADVANCE: f64 = 0.5;
+/// The estimated ascent of a font, as a fraction of its size.
+const ASCENT: f64 = 0.8;
+/// The estimated height of a line, as a fraction of its font size.
+const LINE_HEIGHT: f64 = 1.2;
+
+/// SVG text
 #[derive(Debug, Clone)]
 pub struct Text;
 
@@ -24,21 +40,42 @@ impl piet::Text for Text {
     type TextLayout = TextLayout;
     type TextLayoutBuilder = TextLayoutBuilder;
 
-    fn font_family(&mut self, _family_name: &str) -> Option<FontFamily> {
-        Some(FontFamily::default())
+    fn font_family(&mut self, family_name: &str) -> Option<FontFamily> {
+        // the viewer resolves the name
+        Some(FontFamily::new_unchecked(family_name))
     }
 
     fn load_font(&mut self, _data: &[u8]) -> Result<FontFamily> {
         Ok(FontFamily::default())
     }
 
-    fn new_text_layout(&mut self, _text: impl TextStorage) -> TextLayoutBuilder {
-        TextLayoutBuilder
+    fn new_text_layout(&mut self, text: impl TextStorage) -> TextLayoutBuilder {
+        TextLayoutBuilder {
+            text: Rc::new(text),
+            defaults: util::LayoutDefaults::default(),
+            attributes: util::RangeAttributes::default(),
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct TextLayoutBuilder;
+/// SVG text layout builder
+///
+/// The width, alignment, direction, line height, paragraph spacing, indents
+/// and maximum number of lines of a layout are not supported, as the viewer
+/// lays out the text.
+pub struct TextLayoutBuilder {
+    text: Rc<dyn TextStorage>,
+    defaults: util::LayoutDefaults,
+    attributes: util::RangeAttributes,
+}
+
+impl fmt::Debug for TextLayoutBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextLayoutBuilder")
+            .field("text", &self.text.as_str())
+            .finish()
+    }
+}
 
 impl piet::TextLayoutBuilder for TextLayoutBuilder {
     type Out = TextLayout;
@@ -51,64 +88,338 @@ impl piet::TextLayoutBuilder for TextLayoutBuilder {
         self
     }
 
-    fn default_attribute(self, _attribute: impl Into<TextAttribute>) -> Self {
+    fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
+        self.defaults.set(attribute);
         self
     }
 
     fn range_attribute(
-        self,
-        _range: impl RangeBounds<usize>,
-        _attribute: impl Into<TextAttribute>,
+        mut self,
+        range: impl RangeBounds<usize>,
+        attribute: impl Into<TextAttribute>,
     ) -> Self {
+        let range = util::resolve_range(range, self.text.len());
+        self.attributes.add(range, attribute.into());
         self
     }
 
     fn build(self) -> Result<TextLayout> {
-        Err(Error::NotSupported)
+        let mut layout = TextLayout {
+            runs: self.attributes.runs(&self.defaults, self.text.len()).into(),
+            text: self.text,
+            line_metrics: Rc::new([]),
+            size: Size::ZERO,
+        };
+        layout.update_lines();
+        Ok(layout)
     }
 }
 
-/// SVG text layout (unimplemented)
-#[derive(Debug, Clone)]
-pub struct TextLayout;
+/// SVG text layout
+///
+/// The viewer lays out the text when it draws the SVG, so the layout can only
+/// estimate where the text will be: [`size`], the line metrics and hit
+/// testing assume every character is half as wide as its font size is tall,
+/// and will not match the text as it is drawn.
+///
+/// [`size`]: #method.size
+#[derive(Clone)]
+pub struct TextLayout {
+    text: Rc<dyn TextStorage>,
+    runs: Rc<[util::AttributeRun]>,
+    line_metrics: Rc<[LineMetric]>,
+    size: Size,
+}
+
+impl fmt::Debug for TextLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextLayout")
+            .field("text", &self.text.as_str())
+            .field("line_metrics", &self.line_metrics)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl TextLayout {
+    fn update_lines(&mut self) {
+        let text = self.text.as_str();
+        let mut line_metrics = Vec::new();
+        let mut y_offset = 0.0;
+        let mut start = 0;
+        loop {
+            let end = text[start..]
+                .find('\n')
+                .map_or_else(|| text.len(), |idx| start + idx + 1);
+            let font_size = self.font_size(start..end);
+            let trailing_whitespace = text[start..end].len() - text[start..end].trim_end().len();
+            let line = LineMetric {
+                start_offset: start,
+                end_offset: end,
+                trailing_whitespace,
+                baseline: font_size * (ASCENT + (LINE_HEIGHT - 1.0) / 2.0),
+                height: font_size * LINE_HEIGHT,
+                y_offset,
+            };
+            y_offset += line.height;
+            line_metrics.push(line);
+            // a trailing newline is followed by an empty last line
+            if end == text.len() && util::trailing_nlf(&text[start..end]).is_none() {
+                break;
+            }
+            start = end;
+        }
+        let width = line_metrics
+            .iter()
+            .map(|lm| self.advance(lm.start_offset..lm.end_offset - lm.trailing_whitespace))
+            .fold(0.0, f64::max);
+        self.size = Size::new(width, y_offset);
+        self.line_metrics = line_metrics.into();
+    }
+
+    /// The attribute runs that overlap `range`, clipped to it.
+    fn runs_in(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (Range<usize>, &util::LayoutDefaults)> {
+        self.runs.iter().filter_map(move |run| {
+            let start = run.range.start.max(range.start);
+            let end = run.range.end.min(range.end);
+            if start < end {
+                Some((start..end, &run.attrs))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The largest font size in `range`, which is the size at its start if
+    /// it is empty.
+    fn font_size(&self, range: Range<usize>) -> f64 {
+        if range.start == range.end {
+            let run = self
+                .runs
+                .iter()
+                .rev()
+                .find(|run| run.range.start <= range.start);
+            return run.map_or(util::DEFAULT_FONT_SIZE, |run| run.attrs.font_size);
+        }
+        self.runs_in(range)
+            .map(|(_, attrs)| attrs.font_size)
+            .fold(0.0, f64::max)
+    }
+
+    /// The estimated width of the text in `range`.
+    fn advance(&self, range: Range<usize>) -> f64 {
+        let text = self.text.as_str();
+        self.runs_in(range)
+            .flat_map(|(range, attrs)| text[range].chars().map(move |c| (c, attrs)))
+            .map(|(c, attrs)| {
+                let word_spacing = if c == ' ' { attrs.word_spacing } else { 0.0 };
+                attrs.font_size * ADVANCE + attrs.letter_spacing + word_spacing
+            })
+            .sum()
+    }
+
+    /// The end of a line's text, before its newline.
+    fn content_end(&self, lm: &LineMetric) -> usize {
+        lm.end_offset - util::trailing_nlf(&self.text[lm.range()]).unwrap_or(0)
+    }
+}
 
 impl piet::TextLayout for TextLayout {
     fn size(&self) -> Size {
-        unimplemented!()
+        self.size
     }
 
     fn trailing_whitespace_width(&self) -> f64 {
-        unimplemented!()
+        self.line_metrics
+            .iter()
+            .map(|lm| self.advance(lm.start_offset..self.content_end(lm)))
+            .fold(0.0, f64::max)
     }
 
     fn image_bounds(&self) -> Rect {
-        unimplemented!()
+        self.size.to_rect()
     }
 
-    #[allow(clippy::unimplemented)]
-    fn line_text(&self, _line_number: usize) -> Option<&str> {
-        unimplemented!();
+    fn text(&self) -> &str {
+        &self.text
     }
 
-    #[allow(clippy::unimplemented)]
-    fn line_metric(&self, _line_number: usize) -> Option<LineMetric> {
-        unimplemented!();
+    fn line_text(&self, line_number: usize) -> Option<&str> {
+        self.line_metrics
+            .get(line_number)
+            .map(|lm| &self.text[lm.range()])
+    }
+
+    fn line_metric(&self, line_number: usize) -> Option<LineMetric> {
+        self.line_metrics.get(line_number).cloned()
     }
 
-    #[allow(clippy::unimplemented)]
     fn line_count(&self) -> usize {
-        unimplemented!();
+        self.line_metrics.len()
+    }
+
+    fn hit_test_point(&self, point: Point) -> HitTestPoint {
+        let line_num = self
+            .line_metrics
+            .iter()
+            .position(|lm| point.y < lm.y_offset + lm.height)
+            .unwrap_or(self.line_metrics.len() - 1);
+        let lm = &self.line_metrics[line_num];
+        let content_end = self.content_end(lm);
+
+        let mut idx = lm.start_offset;
+        let mut x = 0.0;
+        for (offset, c) in self.text[lm.start_offset..content_end].char_indices() {
+            let start = lm.start_offset + offset;
+            let width = self.advance(start..start + c.len_utf8());
+            if point.x < x + width / 2.0 {
+                break;
+            }
+            x += width;
+            idx = start + c.len_utf8();
+        }
+        let is_inside = point.x >= 0.0
+            && point.x <= self.advance(lm.start_offset..content_end)
+            && point.y >= 0.0
+            && point.y <= self.size.height;
+        HitTestPoint::new(idx, is_inside)
     }
 
-    fn hit_test_point(&self, _point: Point) -> HitTestPoint {
-        unimplemented!()
+    fn hit_test_text_position(&self, idx: usize) -> HitTestPosition {
+        let idx = idx.min(self.text.len());
+        let line_num = util::line_number_for_position(&self.line_metrics, idx);
+        let lm = &self.line_metrics[line_num];
+        let x = self.advance(lm.start_offset..idx);
+        HitTestPosition::new(Point::new(x, lm.y_offset + lm.baseline), line_num)
     }
+}
 
-    fn hit_test_text_position(&self, _text_position: usize) -> HitTestPosition {
-        unimplemented!()
+impl TextLayout {
+    /// A `<text>` element with a line for each line of the layout, with the
+    /// top left corner of the layout at `pos`.
+    pub(crate) fn to_svg(&self, pos: Point) -> element::Text {
+        let mut text = element::Text::new().set("xml:space", "preserve");
+        for lm in self.line_metrics.iter() {
+            let mut line = element::TSpan::new()
+                .set("x", pos.x)
+                .set("y", pos.y + lm.y_offset + lm.baseline);
+            for span in self.spans(lm.start_offset..self.content_end(lm)) {
+                line.append(span);
+            }
+            text.append(line);
+        }
+        text
     }
 
-    fn text(&self) -> &str {
-        unimplemented!()
+    /// A `<text>` element with the first line of the layout along the path
+    /// with the id `path_id`, as drawn by `draw_text_on_path`.
+    pub(crate) fn to_svg_on_path(
+        &self,
+        path_id: &str,
+        start_offset: f64,
+        alignment: TextAlignment,
+    ) -> element::Text {
+        let anchor = match alignment {
+            TextAlignment::Center => "middle",
+            TextAlignment::End => "end",
+            TextAlignment::Start | TextAlignment::Justified => "start",
+        };
+        let mut text_path = element::TextPath::new()
+            .set("href", format!("#{}", path_id))
+            .set("startOffset", start_offset);
+        if let Some(lm) = self.line_metrics.first() {
+            for span in self.spans(lm.start_offset..lm.end_offset - lm.trailing_whitespace) {
+                text_path.append(span);
+            }
+        }
+        element::Text::new()
+            .set("xml:space", "preserve")
+            .set("text-anchor", anchor)
+            .add(text_path)
     }
+
+    /// A `<tspan>` for each attribute run in `range`.
+    pub(crate) fn spans(&self, range: Range<usize>) -> Vec<element::TSpan> {
+        self.runs_in(range)
+            .map(|(range, attrs)| {
+                let mut span = element::TSpan::new()
+                    .set("font-family", font_family(&attrs.font))
+                    .set("font-size", attrs.font_size)
+                    .set("fill", crate::fmt_color(&attrs.fg_color))
+                    .set("fill-opacity", crate::fmt_opacity(&attrs.fg_color));
+                if attrs.weight != FontWeight::REGULAR {
+                    span.assign("font-weight", attrs.weight.to_raw());
+                }
+                if attrs.style == FontStyle::Italic {
+                    span.assign("font-style", "italic");
+                }
+                if attrs.letter_spacing != 0.0 {
+                    span.assign("letter-spacing", attrs.letter_spacing);
+                }
+                if attrs.word_spacing != 0.0 {
+                    span.assign("word-spacing", attrs.word_spacing);
+                }
+                let decorations: Vec<&str> = [
+                    (attrs.underline, "underline"),
+                    (attrs.overline, "overline"),
+                    (attrs.strikethrough, "line-through"),
+                ]
+                .iter()
+                .filter(|(on, _)| *on)
+                .map(|(_, name)| *name)
+                .collect();
+                if !decorations.is_empty() {
+                    span.assign("text-decoration", decorations.join(" "));
+                    if attrs.underline_style != UnderlineStyle::Single {
+                        span.assign(
+                            "text-decoration-style",
+                            decoration_style(attrs.underline_style),
+                        );
+                    }
+                    if let Some(color) = &attrs.decoration_color {
+                        span.assign("text-decoration-color", crate::fmt_color(color));
+                    }
+                    if let Some(thickness) = attrs.decoration_thickness {
+                        span.assign("text-decoration-thickness", thickness);
+                    }
+                }
+                span.append(svg::node::Text::new(escape(&self.text[range])));
+                span
+            })
+            .collect()
+    }
+}
+
+/// A family name as a value of the `font-family` attribute; generic families
+/// are keywords, and must not be quoted.
+fn font_family(family: &FontFamily) -> String {
+    if family.is_generic() {
+        family.name().to_owned()
+    } else {
+        format!("'{}'", family.name().replace('\'', "\\'"))
+    }
+}
+
+/// An [`UnderlineStyle`] as a value of the `text-decoration-style` attribute.
+///
+/// [`UnderlineStyle`]: piet::UnderlineStyle
+fn decoration_style(style: UnderlineStyle) -> &'static str {
+    match style {
+        UnderlineStyle::Single => "solid",
+        UnderlineStyle::Double => "double",
+        UnderlineStyle::Dotted => "dotted",
+        UnderlineStyle::Dashed => "dashed",
+        UnderlineStyle::Wavy => "wavy",
+    }
+}
+
+/// Escapes text for use as the content of an element; the svg crate writes
+/// text nodes as they are.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
 }
@@ -13,8 +13,8 @@ use web_sys::CanvasRenderingContext2d;
 use piet::kurbo::{Point, Rect, Size};
 
 use piet::{
-    util, Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, Text, TextAttribute,
-    TextLayout, TextLayoutBuilder, TextStorage,
+    util, Affinity, Color, Error, FontFamily, HitTestPoint, HitTestPosition, LineMetric, Text,
+    TextAttribute, TextLayout, TextLayoutBuilder, TextStorage,
 };
 use unicode_segmentation::UnicodeSegmentation;
 
@@ -149,6 +149,40 @@ impl TextLayoutBuilder for WebTextLayoutBuilder {
         self
     }
 
+    fn direction(self, _direction: piet::TextDirection) -> Self {
+        web_sys::console::log_1(&"TextLayout direction unsupported on web".into());
+        self
+    }
+
+    fn line_height(self, _height: piet::LineHeight) -> Self {
+        web_sys::console::log_1(&"TextLayout line height unsupported on web".into());
+        self
+    }
+
+    fn paragraph_spacing(self, _spacing: f64) -> Self {
+        web_sys::console::log_1(&"TextLayout paragraph spacing unsupported on web".into());
+        self
+    }
+
+    fn first_line_indent(self, _indent: f64) -> Self {
+        web_sys::console::log_1(&"TextLayout indents unsupported on web".into());
+        self
+    }
+
+    fn hanging_indent(self, _indent: f64) -> Self {
+        web_sys::console::log_1(&"TextLayout indents unsupported on web".into());
+        self
+    }
+
+    fn max_lines(self, _lines: usize) -> Self {
+        web_sys::console::log_1(&"TextLayout max_lines unsupported on web".into());
+        self
+    }
+
+    fn overflow(self, _overflow: piet::TextOverflow) -> Self {
+        self
+    }
+
     fn default_attribute(mut self, attribute: impl Into<TextAttribute>) -> Self {
         self.defaults.set(attribute);
         self
@@ -272,6 +306,15 @@ impl TextLayout for WebTextLayout {
             htp.is_inside = false;
         }
 
+        // past the end of a wrapped line, the caret stays on the line
+        let is_last_line = self
+            .line_metrics
+            .last()
+            .map_or(true, |l| l.start_offset == lm.start_offset);
+        if htp.idx == lm.end_offset && !is_last_line && util::trailing_nlf(line).is_none() {
+            htp.affinity = Affinity::Upstream;
+        }
+
         htp
     }
 
@@ -474,7 +517,7 @@ pub(crate) fn text_width(text: &str, ctx: &CanvasRenderingContext2d) -> f64 {
 #[cfg(test)]
 pub(crate) mod test {
     use piet::kurbo::Point;
-    use piet::{Text, TextLayout, TextLayoutBuilder};
+    use piet::{Affinity, Text, TextLayout, TextLayoutBuilder};
     use wasm_bindgen_test::*;
     use web_sys::{console, window, HtmlCanvasElement};
 
@@ -1057,6 +1100,28 @@ pub(crate) mod test {
         );
     }
 
+    #[wasm_bindgen_test]
+    fn test_hit_test_end_of_wrapped_line() {
+        let (_window, context) = setup_ctx();
+        let mut text = WebText::new(context);
+
+        let font = text.font_family("sans-serif").unwrap();
+        let layout = text
+            .new_text_layout("piet text")
+            .font(font, 14.0)
+            .max_width(30.0)
+            .build()
+            .unwrap();
+        assert_eq!(layout.line_count(), 2);
+        let first = layout.line_metric(0).unwrap();
+
+        let pt = layout.hit_test_point(Point::new(100.0, 5.0));
+        assert_eq!(pt.idx, first.end_offset);
+        assert_eq!(pt.affinity, Affinity::Upstream);
+        let pt = layout.hit_test_point(Point::new(100.0, first.height + 5.0));
+        assert_eq!(pt.affinity, Affinity::Downstream);
+    }
+
     // very basic testing that multiline works
     #[wasm_bindgen_test]
     fn test_multiline_hit_test_point_basic() {
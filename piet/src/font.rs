@@ -1,7 +1,11 @@
 //! Font families, weights, etcetera
 
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::Error;
+
 /// A reference to a font family.
 ///
 /// This may be either a CSS-style "generic family name", such as "serif"
@@ -55,6 +59,75 @@ pub enum FontStyle {
     Italic,
 }
 
+/// An OpenType feature setting, such as tabular numbers or ligatures.
+///
+/// A feature is identified by a four-character tag, such as `tnum` or `liga`.
+/// Most features are turned on with a value of `1` and off with `0`; some,
+/// such as `salt` (stylistic alternates), use larger values to choose between
+/// alternatives.
+///
+/// Features can be parsed from strings similar to those used by the CSS
+/// [`font-feature-settings`] property: `"smcp"` and `"+smcp"` turn a feature
+/// on, `"-liga"` turns it off, and `"salt=2"` sets a value.
+///
+/// ```
+/// use piet::FontFeature;
+///
+/// let tabular: FontFeature = "tnum".parse().unwrap();
+/// assert_eq!(tabular, FontFeature::new(b"tnum", 1));
+/// let no_ligatures: FontFeature = "-liga".parse().unwrap();
+/// assert_eq!(no_ligatures, FontFeature::new(b"liga", 0));
+/// let alternates: FontFeature = "salt=2".parse().unwrap();
+/// assert_eq!(alternates.value(), 2);
+/// assert!("liga=on".parse::<FontFeature>().is_err());
+/// ```
+///
+/// [`font-feature-settings`]: https://developer.mozilla.org/en-US/docs/Web/CSS/font-feature-settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontFeature {
+    tag: [u8; 4],
+    value: u32,
+}
+
+/// A position on one of the axes of a variable font.
+///
+/// Axes are identified by a four-character tag; registered axes include
+/// `wght` (weight), `wdth` (width, as a percentage of normal), `opsz`
+/// (optical size), `slnt` (slant, in degrees) and `ital`, and fonts can
+/// define their own axes with uppercase tags.
+///
+/// Settings for axes a font doesn't have are ignored. A `wght` or `wdth`
+/// variation takes precedence over the [`FontWeight`] and [`FontWidth`] of
+/// the text.
+///
+/// Variations can be parsed from strings such as `"wdth=75"`:
+///
+/// ```
+/// use piet::FontVariation;
+///
+/// let condensed: FontVariation = "wdth=75".parse().unwrap();
+/// assert_eq!(condensed, FontVariation::new(b"wdth", 75.0));
+/// assert!("wdth".parse::<FontVariation>().is_err());
+/// ```
+///
+/// [`FontWeight`]: struct.FontWeight.html
+/// [`FontWidth`]: struct.FontWidth.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FontVariation {
+    tag: [u8; 4],
+    value: f32,
+}
+
+/// A font width, from ultra-condensed to ultra-expanded.
+///
+/// This is based on the [CSS `font-stretch`] property and the OpenType
+/// width classes, which run from `1` (ultra-condensed) to `9`
+/// (ultra-expanded); `5` is the normal width.
+///
+/// [CSS `font-stretch`]: https://developer.mozilla.org/en-US/docs/Web/CSS/font-stretch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontWidth(u16);
+
 impl FontFamily {
     /// A san-serif font, such as Arial or Helvetica.
     pub const SANS_SERIF: FontFamily = FontFamily(FontFamilyInner::SansSerif);
@@ -137,6 +210,154 @@ impl FontWeight {
     }
 }
 
+impl FontFeature {
+    /// Create a new feature setting from a tag and a value.
+    pub const fn new(tag: &[u8; 4], value: u32) -> FontFeature {
+        FontFeature { tag: *tag, value }
+    }
+
+    /// The feature's four-character tag.
+    pub fn tag(&self) -> [u8; 4] {
+        self.tag
+    }
+
+    /// The feature's tag, as a string.
+    ///
+    /// Tags are ASCII; a tag that isn't is replaced with `"????"`.
+    pub fn tag_str(&self) -> &str {
+        tag_str(&self.tag)
+    }
+
+    /// The feature's value; `0` turns a feature off.
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+}
+
+impl FontVariation {
+    /// Create a new variation from an axis tag and a value.
+    pub const fn new(tag: &[u8; 4], value: f32) -> FontVariation {
+        FontVariation { tag: *tag, value }
+    }
+
+    /// The four-character tag of the axis.
+    pub fn tag(&self) -> [u8; 4] {
+        self.tag
+    }
+
+    /// The axis tag, as a string.
+    ///
+    /// Tags are ASCII; a tag that isn't is replaced with `"????"`.
+    pub fn tag_str(&self) -> &str {
+        tag_str(&self.tag)
+    }
+
+    /// The position on the axis, in the axis's own units.
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+}
+
+impl FontWidth {
+    /// The ultra-condensed width, width class `1`.
+    pub const ULTRA_CONDENSED: FontWidth = FontWidth(1);
+    /// The extra-condensed width, width class `2`.
+    pub const EXTRA_CONDENSED: FontWidth = FontWidth(2);
+    /// The condensed width, width class `3`.
+    pub const CONDENSED: FontWidth = FontWidth(3);
+    /// The semi-condensed width, width class `4`.
+    pub const SEMI_CONDENSED: FontWidth = FontWidth(4);
+    /// The normal width, width class `5`.
+    pub const NORMAL: FontWidth = FontWidth(5);
+    /// The semi-expanded width, width class `6`.
+    pub const SEMI_EXPANDED: FontWidth = FontWidth(6);
+    /// The expanded width, width class `7`.
+    pub const EXPANDED: FontWidth = FontWidth(7);
+    /// The extra-expanded width, width class `8`.
+    pub const EXTRA_EXPANDED: FontWidth = FontWidth(8);
+    /// The ultra-expanded width, width class `9`.
+    pub const ULTRA_EXPANDED: FontWidth = FontWidth(9);
+
+    /// Create a new `FontWidth` from an OpenType width class.
+    ///
+    /// Values will be clamped to the range 1..=9.
+    pub fn new(raw: u16) -> FontWidth {
+        FontWidth(raw.min(9).max(1))
+    }
+
+    /// Return the raw width class as a u16.
+    pub const fn to_raw(self) -> u16 {
+        self.0
+    }
+
+    /// The width as a percentage of the normal width, as used by CSS and by
+    /// the `wdth` axis of variable fonts.
+    pub fn percentage(self) -> f32 {
+        const PERCENTAGES: [f32; 9] = [50.0, 62.5, 75.0, 87.5, 100.0, 112.5, 125.0, 150.0, 200.0];
+        PERCENTAGES[self.0 as usize - 1]
+    }
+}
+
+impl FromStr for FontFeature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FontFeature, Error> {
+        let s = s.trim();
+        let (s, default_value) = if let Some(s) = s.strip_prefix('-') {
+            (s, 0)
+        } else {
+            (s.strip_prefix('+').unwrap_or(s), 1)
+        };
+        let mut parts = s.splitn(2, '=');
+        let tag = parse_tag(parts.next().unwrap_or_default())?;
+        let value = match parts.next() {
+            Some(value) => value.trim().parse().map_err(|_| Error::InvalidInput)?,
+            None => default_value,
+        };
+        Ok(FontFeature::new(&tag, value))
+    }
+}
+
+impl FromStr for FontVariation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FontVariation, Error> {
+        let mut parts = s.splitn(2, '=');
+        let tag = parse_tag(parts.next().unwrap_or_default())?;
+        let value = parts
+            .next()
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or(Error::InvalidInput)?;
+        Ok(FontVariation::new(&tag, value))
+    }
+}
+
+impl fmt::Display for FontFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.tag_str(), self.value)
+    }
+}
+
+impl fmt::Display for FontVariation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.tag_str(), self.value)
+    }
+}
+
+fn parse_tag(tag: &str) -> Result<[u8; 4], Error> {
+    let tag = tag.trim();
+    if tag.len() != 4 || !tag.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(Error::InvalidInput);
+    }
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(tag.as_bytes());
+    Ok(bytes)
+}
+
+fn tag_str(tag: &[u8; 4]) -> &str {
+    std::str::from_utf8(tag).unwrap_or("????")
+}
+
 impl Default for FontFamily {
     fn default() -> Self {
         FontFamily::SYSTEM_UI
@@ -149,6 +370,12 @@ impl Default for FontWeight {
     }
 }
 
+impl Default for FontWidth {
+    fn default() -> Self {
+        FontWidth::NORMAL
+    }
+}
+
 impl Default for FontStyle {
     fn default() -> Self {
         FontStyle::Regular
@@ -1,12 +1,13 @@
 //! The main render context trait.
 
 use std::borrow::Cow;
+use std::ops::RangeBounds;
 
-use kurbo::{Affine, Point, Rect, Shape};
+use kurbo::{Affine, BezPath, Point, Rect, Shape};
 
 use crate::{
-    Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient, Image, LinearGradient,
-    RadialGradient, StrokeStyle, Text, TextLayout,
+    util, Color, Error, FixedGradient, FixedLinearGradient, FixedRadialGradient, Image,
+    LinearGradient, RadialGradient, StrokeStyle, Text, TextAlignment, TextLayout,
 };
 
 /// A requested interpolation mode for drawing images.
@@ -117,6 +118,21 @@ where
     /// are clipped by the shape.
     fn clip(&mut self, shape: impl Shape);
 
+    /// Clip to the glyphs of a text layout, drawn at `pos`.
+    ///
+    /// This behaves exactly like [`clip`](#method.clip) with the layout's
+    /// [`outline`] as the shape. On backends that can't outline text and
+    /// have no other way to clip to it, it returns [`Error::NotSupported`]
+    /// and leaves the clip unchanged.
+    ///
+    /// [`outline`]: trait.TextLayout.html#method.outline
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    fn clip_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>) -> Result<(), Error> {
+        let outline = text_outline(layout, pos.into())?;
+        self.clip(outline);
+        Ok(())
+    }
+
     fn text(&mut self) -> &mut Self::Text;
 
     /// Draw a text layout.
@@ -125,6 +141,146 @@ where
     /// the text. Note: this is true even if the text is right-to-left.
     fn draw_text(&mut self, layout: &Self::TextLayout, pos: impl Into<Point>);
 
+    /// Draw only the part of a text layout that covers `range`, optionally
+    /// in `color` instead of the layout's text colors.
+    ///
+    /// The range may span several lines. Drawing is clipped to the rects
+    /// returned by [`TextLayout::rects_for_range`], which are as tall as each
+    /// line's [`LineMetric`]; glyphs that straddle the edge of the range are
+    /// cut at the caret position.
+    ///
+    /// With a `color`, the text is drawn by [`draw_text_with_brush`], so
+    /// whether decorations are drawn with it depends on the backend; the
+    /// default implementation of that method doesn't draw them.
+    ///
+    /// [`TextLayout::rects_for_range`]: trait.TextLayout.html#method.rects_for_range
+    /// [`LineMetric`]: struct.LineMetric.html
+    /// [`draw_text_with_brush`]: #method.draw_text_with_brush
+    fn draw_text_range(
+        &mut self,
+        layout: &Self::TextLayout,
+        range: impl RangeBounds<usize>,
+        pos: impl Into<Point>,
+        color: Option<Color>,
+    ) -> Result<(), Error> {
+        let pos = pos.into();
+        let rects = layout.rects_for_range(range);
+        if rects.is_empty() {
+            return Ok(());
+        }
+        let mut clip = BezPath::new();
+        for rect in rects {
+            for el in (rect + pos.to_vec2()).path_elements(0.1) {
+                clip.push(el);
+            }
+        }
+        self.with_save(|rc| {
+            rc.clip(clip);
+            match color {
+                Some(color) => rc.draw_text_with_brush(layout, pos, &color),
+                None => {
+                    rc.draw_text(layout, pos);
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    /// Draw a text layout, filling its glyphs with `brush` instead of the
+    /// layout's text colors.
+    ///
+    /// The default implementation fills the layout's [`outline`], so it
+    /// doesn't draw decorations or backgrounds. It returns
+    /// [`Error::NotSupported`] if the backend can't outline text.
+    ///
+    /// [`outline`]: trait.TextLayout.html#method.outline
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    fn draw_text_with_brush(
+        &mut self,
+        layout: &Self::TextLayout,
+        pos: impl Into<Point>,
+        brush: &impl IntoBrush<Self>,
+    ) -> Result<(), Error> {
+        let outline = text_outline(layout, pos.into())?;
+        if !outline.elements().is_empty() {
+            self.fill(outline, brush);
+        }
+        Ok(())
+    }
+
+    /// Draw the outlines of the glyphs of a text layout, stroked with `brush`.
+    ///
+    /// This can be combined with [`draw_text`] or [`draw_text_with_brush`] to
+    /// draw outlined text. It returns [`Error::NotSupported`] if the backend
+    /// can't outline text.
+    ///
+    /// [`draw_text`]: #tymethod.draw_text
+    /// [`draw_text_with_brush`]: #method.draw_text_with_brush
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    fn stroke_text(
+        &mut self,
+        layout: &Self::TextLayout,
+        pos: impl Into<Point>,
+        brush: &impl IntoBrush<Self>,
+        width: f64,
+        style: &StrokeStyle,
+    ) -> Result<(), Error> {
+        let outline = text_outline(layout, pos.into())?;
+        if !outline.elements().is_empty() {
+            self.stroke_styled(outline, brush, width, style);
+        }
+        Ok(())
+    }
+
+    /// Draw the first line of a text layout along a path.
+    ///
+    /// The text's baseline follows `path`, with each grapheme cluster rotated
+    /// to the path's tangent. The text starts `start_offset` along the path,
+    /// or is centered on or ends at that point, depending on `alignment`;
+    /// clusters that fall off the end of the path are not drawn.
+    ///
+    /// The default implementation fills the [`glyph_outlines`] of the line,
+    /// each moved onto the path by its cluster's transform from
+    /// [`util::text_on_path`], in the colors of the [`glyph_runs`]; it
+    /// doesn't draw decorations or backgrounds. It returns
+    /// [`Error::NotSupported`] if the backend can't outline text.
+    ///
+    /// [`glyph_outlines`]: trait.TextLayout.html#method.glyph_outlines
+    /// [`glyph_runs`]: trait.TextLayout.html#method.glyph_runs
+    /// [`util::text_on_path`]: util/fn.text_on_path.html
+    /// [`Error::NotSupported`]: enum.Error.html#variant.NotSupported
+    fn draw_text_on_path(
+        &mut self,
+        layout: &Self::TextLayout,
+        path: &BezPath,
+        start_offset: f64,
+        alignment: TextAlignment,
+    ) -> Result<(), Error> {
+        let clusters = util::text_on_path(layout, path, start_offset, alignment);
+        if clusters.is_empty() {
+            return Ok(());
+        }
+        let outlines = layout.glyph_outlines();
+        if outlines.is_empty() {
+            return Err(Error::NotSupported);
+        }
+        let mut outlines = outlines.into_iter();
+        for run in layout.glyph_runs() {
+            let mut shape = BezPath::new();
+            for (glyph, outline) in run.glyphs.iter().zip(&mut outlines) {
+                if let Some(cluster) = util::path_cluster_at(&clusters, glyph.cluster) {
+                    for el in (cluster.transform * outline).elements() {
+                        shape.push(*el);
+                    }
+                }
+            }
+            if !shape.elements().is_empty() {
+                self.fill(shape, &run.color);
+            }
+        }
+        Ok(())
+    }
+
     /// Save the context state.
     ///
     /// Pushes the current context state onto a stack, to be popped by
@@ -208,6 +364,18 @@ where
     fn current_transform(&self) -> Affine;
 }
 
+/// The outline of a layout's glyphs, drawn at `pos`.
+///
+/// Text with no visible characters has an empty outline; otherwise an empty
+/// outline means that the backend can't outline text.
+fn text_outline(layout: &impl TextLayout, pos: Point) -> Result<BezPath, Error> {
+    let outline = layout.outline();
+    if outline.elements().is_empty() && !layout.text().trim().is_empty() {
+        return Err(Error::NotSupported);
+    }
+    Ok(Affine::translate(pos.to_vec2()) * outline)
+}
+
 /// A trait for various types that can be used as brushes. These include
 /// backend-independent types such `Color` and `LinearGradient`, as well
 /// as the types used to represent these on a specific backend.
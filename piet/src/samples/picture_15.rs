@@ -0,0 +1,54 @@
+//! OpenType font features: tabular numbers, ligatures, and small caps.
+
+use crate::kurbo::{Point, Size};
+use crate::{
+    Color, Error, FontFamily, FontFeature, RenderContext, Text, TextLayout, TextLayoutBuilder,
+};
+
+pub const SIZE: Size = Size::new(400., 300.);
+
+static NUMBERS: &str = "1,111.11\n8,888.88\n  101.01";
+static LIGATURES: &str = "official affine fjord";
+static SMALL_CAPS: &str = "Small Caps";
+
+pub fn draw<R: RenderContext>(rc: &mut R) -> Result<(), Error> {
+    rc.clear(Color::WHITE);
+    let text = rc.text();
+
+    // proportional numbers on the left, tabular numbers on the right
+    let proportional = text
+        .new_text_layout(NUMBERS)
+        .font(FontFamily::SERIF, 18.0)
+        .default_attribute(FontFeature::new(b"pnum", 1))
+        .build()?;
+    let tabular = text
+        .new_text_layout(NUMBERS)
+        .font(FontFamily::SERIF, 18.0)
+        .default_attribute(FontFeature::new(b"tnum", 1))
+        .build()?;
+
+    // ligatures are turned off for the second half of the line
+    let ligatures = text
+        .new_text_layout(LIGATURES)
+        .font(FontFamily::SERIF, 18.0)
+        .range_attribute(9.., FontFeature::new(b"liga", 0))
+        .build()?;
+
+    let small_caps = text
+        .new_text_layout(SMALL_CAPS)
+        .font(FontFamily::SERIF, 18.0)
+        .default_attribute(vec![
+            FontFeature::new(b"smcp", 1),
+            FontFeature::new(b"c2sc", 1),
+        ])
+        .build()?;
+
+    rc.draw_text(&proportional, Point::new(20.0, 20.0));
+    rc.draw_text(&tabular, Point::new(220.0, 20.0));
+    let y = 40.0 + proportional.size().height;
+    rc.draw_text(&ligatures, Point::new(20.0, y));
+    let y = y + 20.0 + ligatures.size().height;
+    rc.draw_text(&small_caps, Point::new(20.0, y));
+
+    Ok(())
+}
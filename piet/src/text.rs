@@ -2,8 +2,12 @@
 
 use std::ops::{Range, RangeBounds};
 
-use crate::kurbo::{Point, Rect, Size};
-use crate::{Color, Error, FontFamily, FontStyle, FontWeight};
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+use crate::kurbo::{BezPath, Point, Rect, Size};
+use crate::{
+    util, Color, Error, FontFamily, FontFeature, FontStyle, FontVariation, FontWeight, FontWidth,
+};
 
 /// The Piet text API.
 ///
@@ -142,6 +146,7 @@ impl std::ops::Deref for dyn TextStorage {
 }
 
 /// Attributes that can be applied to text.
+#[derive(Debug, Clone)]
 pub enum TextAttribute {
     /// The font family.
     FontFamily(FontFamily),
@@ -151,6 +156,13 @@ pub enum TextAttribute {
     Weight(FontWeight),
     /// The foreground color of the text.
     TextColor(crate::Color),
+    /// A color drawn behind the text.
+    ///
+    /// The highlight fills the same area as [`rects_for_range`] would for
+    /// the range, so it follows the text's alignment and line breaks.
+    ///
+    /// [`rects_for_range`]: trait.TextLayout.html#method.rects_for_range
+    BackgroundColor(crate::Color),
     /// The [`FontStyle`]; either regular or italic.
     ///
     /// [`FontStyle`]: enum.FontStyle.html
@@ -159,6 +171,68 @@ pub enum TextAttribute {
     Underline(bool),
     /// Strikethrough.
     Strikethrough(bool),
+    /// A line above the text.
+    Overline(bool),
+    /// The [`UnderlineStyle`]; setting a style also turns the underline on.
+    ///
+    /// [`UnderlineStyle`]: enum.UnderlineStyle.html
+    UnderlineStyle(UnderlineStyle),
+    /// The color of underlines, strikethroughs and overlines; by default they
+    /// are the color of the text.
+    DecorationColor(crate::Color),
+    /// The thickness of underlines, strikethroughs and overlines, in display
+    /// points; by default this comes from the font.
+    DecorationThickness(f64),
+    /// OpenType [`FontFeature`] settings.
+    ///
+    /// Features set for a range are combined with the default features; a
+    /// setting for a tag replaces the default setting for the same tag. Like
+    /// other range attributes they don't stack, so overlapping ranges of
+    /// features replace each other rather than being combined.
+    ///
+    /// [`FontFeature`]: struct.FontFeature.html
+    FontFeatures(Vec<FontFeature>),
+    /// The [`FontWidth`], from condensed to expanded.
+    ///
+    /// [`FontWidth`]: struct.FontWidth.html
+    Width(FontWidth),
+    /// Positions on the axes of a variable font, as [`FontVariation`]s.
+    ///
+    /// These are combined with the default variations in the same way as
+    /// [`FontFeatures`].
+    ///
+    /// [`FontVariation`]: struct.FontVariation.html
+    /// [`FontFeatures`]: #variant.FontFeatures
+    FontVariations(Vec<FontVariation>),
+    /// Extra space added after each character, in points.
+    ///
+    /// Negative values bring characters closer together. Backends that shape
+    /// text disable optional ligatures when this is not zero.
+    LetterSpacing(f64),
+    /// Extra space added to each space character, in points.
+    ///
+    /// This is in addition to any letter spacing.
+    WordSpacing(f64),
+    /// Replace the text in the range with an empty [`Placeholder`] box, for
+    /// drawing an image or widget inline.
+    ///
+    /// The range is usually a single object replacement character, U+FFFC.
+    /// The box is measured and wrapped as a single unit, its text is not
+    /// drawn, and its position is reported by [`placeholder_rects`]. Other
+    /// range attributes that start or end inside the range don't split it.
+    ///
+    /// [`Placeholder`]: struct.Placeholder.html
+    /// [`placeholder_rects`]: trait.TextLayout.html#method.placeholder_rects
+    Placeholder(Placeholder),
+    /// A [`BaselineShift`], for superscripts and subscripts.
+    ///
+    /// The shift is reflected in the layout's [`LineMetric`]s and hit testing.
+    /// On the Direct2D backend only the font size is scaled: the text stays
+    /// on the baseline, and lines are not made taller for it.
+    ///
+    /// [`BaselineShift`]: enum.BaselineShift.html
+    /// [`LineMetric`]: struct.LineMetric.html
+    BaselineShift(BaselineShift),
 }
 
 /// A trait for laying out text.
@@ -179,6 +253,92 @@ pub trait TextLayoutBuilder: Sized {
     /// [`TextAlignment`]: enum.TextAlignment.html
     fn alignment(self, alignment: TextAlignment) -> Self;
 
+    /// Set the base [`TextDirection`] of the paragraphs in this layout.
+    ///
+    /// The base direction determines the order of runs of mixed-direction
+    /// text, as well as which edge is the start for [`TextAlignment`].
+    ///
+    /// [`TextDirection`]: enum.TextDirection.html
+    /// [`TextAlignment`]: enum.TextAlignment.html
+    fn direction(self, direction: TextDirection) -> Self {
+        let _ = direction;
+        self
+    }
+
+    /// Set the [`LineHeight`] of the lines in this layout.
+    ///
+    /// The difference from a line's normal height is split evenly above and
+    /// below its text, and is reflected in each line's [`LineMetric`].
+    ///
+    /// [`LineHeight`]: enum.LineHeight.html
+    /// [`LineMetric`]: struct.LineMetric.html
+    fn line_height(self, height: LineHeight) -> Self {
+        let _ = height;
+        self
+    }
+
+    /// Set the extra space between paragraphs, in display points.
+    ///
+    /// Paragraphs are separated by hard line breaks. The space is added
+    /// between the lines' [`LineMetric`]s, so the `y_offset` of the first line
+    /// of a paragraph is greater than the bottom of the line before it.
+    ///
+    /// [`LineMetric`]: struct.LineMetric.html
+    fn paragraph_spacing(self, spacing: f64) -> Self {
+        let _ = spacing;
+        self
+    }
+
+    /// Set how far the first line of each paragraph is indented from the start
+    /// edge, in display points.
+    ///
+    /// Indented lines have less room before they are wrapped.
+    fn first_line_indent(self, indent: f64) -> Self {
+        let _ = indent;
+        self
+    }
+
+    /// Set how far every line but the first line of each paragraph is
+    /// indented from the start edge, in display points.
+    ///
+    /// Indented lines have less room before they are wrapped.
+    fn hanging_indent(self, indent: f64) -> Self {
+        let _ = indent;
+        self
+    }
+
+    /// Set the maximum number of lines in this layout.
+    ///
+    /// Text that doesn't fit is hidden as determined by the [`TextOverflow`]
+    /// mode; [`TextLayout::text`] still returns all of the text, and hit
+    /// testing maps hidden text to the ellipsis, if there is one.
+    ///
+    /// The [`LineMetric`] of the last line covers only the text of that line,
+    /// as it was before truncation; the text after a
+    /// [`TextOverflow::MiddleEllipsis`] is drawn on the line, but is outside
+    /// of its range.
+    ///
+    /// [`TextOverflow`]: enum.TextOverflow.html
+    /// [`TextLayout::text`]: trait.TextLayout.html#tymethod.text
+    /// [`LineMetric`]: struct.LineMetric.html
+    /// [`TextOverflow::MiddleEllipsis`]: enum.TextOverflow.html#variant.MiddleEllipsis
+    fn max_lines(self, lines: usize) -> Self {
+        let _ = lines;
+        self
+    }
+
+    /// Set how text that doesn't fit in [`max_lines`] is shown.
+    ///
+    /// An ellipsis is also added if the last line is wider than the
+    /// [`max_width`].
+    ///
+    /// [`max_lines`]: #method.max_lines
+    /// [`max_width`]: #tymethod.max_width
+    fn overflow(self, overflow: TextOverflow) -> Self {
+        let _ = overflow;
+        self
+    }
+
     /// A convenience method for setting the default font family and size.
     ///
     /// # Examples
@@ -292,6 +452,99 @@ pub enum TextAlignment {
     Justified,
 }
 
+/// The base direction of the paragraphs in a [`TextLayout`].
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextDirection {
+    /// The direction of each paragraph is determined by its first strongly
+    /// directional character; paragraphs without one are left-to-right.
+    Auto,
+    /// Every paragraph is left-to-right.
+    LeftToRight,
+    /// Every paragraph is right-to-left.
+    RightToLeft,
+}
+
+/// The height of the lines in a [`TextLayout`].
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineHeight {
+    /// Each line is as tall as the ascent, descent and line gap of its fonts.
+    Normal,
+    /// Each line is this many display points tall.
+    Absolute(f64),
+    /// Each line is this multiple of the largest font size on the line, like
+    /// a unitless `line-height` in CSS.
+    Multiple(f64),
+}
+
+/// How a [`TextLayout`] shows text that doesn't fit in its maximum number of
+/// lines.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextOverflow {
+    /// Lines after the last line are not shown.
+    Clip,
+    /// The end of the last line is replaced with an ellipsis.
+    Ellipsis,
+    /// The last line shows its start and the end of the text, separated by
+    /// an ellipsis.
+    MiddleEllipsis,
+}
+
+/// The appearance of an underline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnderlineStyle {
+    /// One solid line.
+    Single,
+    /// Two thin lines.
+    Double,
+    /// A row of dots.
+    Dotted,
+    /// A row of short dashes.
+    Dashed,
+    /// A wavy line, as used to mark spelling errors.
+    Wavy,
+}
+
+/// An empty box reserved inline in a [`TextLayout`], for an image or widget
+/// that the caller draws.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placeholder {
+    /// The width of the box; text after it is moved along by this much.
+    pub width: f64,
+    /// The height of the box. The line is made tall enough to fit it.
+    pub height: f64,
+    /// The distance from the top of the box down to the baseline of the
+    /// text around it.
+    pub baseline: f64,
+}
+
+/// A vertical shift of text from the baseline, along with a change in its
+/// font size.
+///
+/// Superscript and subscript offsets are relative to the font size of the
+/// text before scaling. Lines are made tall enough to fit shifted text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BaselineShift {
+    /// The text sits on the baseline, at its normal size.
+    None,
+    /// The text is raised by a third of its font size, and its font size is
+    /// multiplied by the given factor.
+    Superscript(f64),
+    /// The text is lowered by a fifth of its font size, and its font size is
+    /// multiplied by the given factor.
+    Subscript(f64),
+    /// The text is raised by `offset` display points, or lowered if `offset`
+    /// is negative, and its font size is multiplied by `scale`.
+    Offset { offset: f64, scale: f64 },
+}
+
 /// A drawable text object.
 ///
 /// ## Line Breaks
@@ -415,8 +668,9 @@ pub trait TextLayout: Clone {
     ///
     /// `range` will be clamped to the length of the text if necessary.
     ///
-    /// Note: this implementation is not currently BiDi aware; it will be updated
-    /// when BiDi support is added.
+    /// Note: this default implementation is not BiDi aware, and returns a
+    /// single rect for each line; backends that support mixed-direction text
+    /// override it.
     fn rects_for_range(&self, range: impl RangeBounds<usize>) -> Vec<Rect> {
         let text_len = self.text().len();
         let mut range = crate::util::resolve_range(range, text_len);
@@ -454,6 +708,354 @@ pub trait TextLayout: Clone {
 
         result
     }
+
+    /// Returns the position of each [`Placeholder`] in the layout, along with
+    /// the range of text it replaces, in text order.
+    ///
+    /// Placeholders that are not shown, because they are past the layout's
+    /// maximum number of lines, are not included.
+    ///
+    /// The default implementation returns no rects; backends that support
+    /// placeholders override it.
+    ///
+    /// [`Placeholder`]: struct.Placeholder.html
+    fn placeholder_rects(&self) -> Vec<(Range<usize>, Rect)> {
+        Vec::new()
+    }
+
+    /// Returns the shaped glyphs of the layout, for drawing text by some means
+    /// other than [`RenderContext::draw_text`].
+    ///
+    /// There is a [`GlyphRun`] for each piece of a line that is drawn with a
+    /// single font; runs are in line order, and in visual order within a line.
+    /// Placeholders have no glyphs, and are not included.
+    ///
+    /// The default implementation returns no runs; backends that can expose
+    /// their glyphs override it.
+    ///
+    /// [`RenderContext::draw_text`]: trait.RenderContext.html#tymethod.draw_text
+    /// [`GlyphRun`]: struct.GlyphRun.html
+    fn glyph_runs(&self) -> Vec<GlyphRun> {
+        Vec::new()
+    }
+
+    /// Returns the outline of each glyph in the layout, positioned as
+    /// [`RenderContext::draw_text`] would draw them at the origin.
+    ///
+    /// There is one path for each glyph returned by [`glyph_runs`], in the
+    /// same order; glyphs with no outline, such as spaces, have an empty path.
+    /// Decorations, backgrounds and placeholders are not included.
+    ///
+    /// The default implementation returns no outlines; backends that can
+    /// expose their glyphs override it.
+    ///
+    /// [`RenderContext::draw_text`]: trait.RenderContext.html#tymethod.draw_text
+    /// [`glyph_runs`]: #method.glyph_runs
+    fn glyph_outlines(&self) -> Vec<BezPath> {
+        Vec::new()
+    }
+
+    /// Returns the outlines of all of the layout's glyphs, merged into a
+    /// single path.
+    ///
+    /// This is the path to fill to draw the text as a shape, or to clip to;
+    /// see [`glyph_outlines`] for the details.
+    ///
+    /// [`glyph_outlines`]: #method.glyph_outlines
+    fn outline(&self) -> BezPath {
+        let mut outline = BezPath::new();
+        for glyph in self.glyph_outlines() {
+            for el in glyph.elements() {
+                outline.push(*el);
+            }
+        }
+        outline
+    }
+
+    /// Returns the rect of a caret at text position `idx`, as a zero-width
+    /// rect as tall as the caret's line.
+    ///
+    /// At the end of a line that was broken to fit the layout's width, the
+    /// same position is both the end of one line and the start of the next;
+    /// `affinity` chooses between them. In mixed-direction text, it also
+    /// chooses which of the two runs meeting at a position the caret is drawn
+    /// against.
+    ///
+    /// The default implementation finds the end of a line that was broken to
+    /// fit from the glyphs of its last grapheme cluster, as reported by
+    /// [`glyph_runs`]; if the backend reports no glyphs, an upstream caret is
+    /// placed before that cluster instead. It ignores affinity in
+    /// mixed-direction text.
+    ///
+    /// [`glyph_runs`]: #method.glyph_runs
+    fn cursor_rect(&self, idx: usize, affinity: Affinity) -> Rect {
+        let idx = idx.min(self.text().len());
+        let pos = self.hit_test_text_position(idx);
+        let line_num = caret_line(self, idx, affinity);
+        let lm = self.line_metric(line_num).unwrap_or_default();
+        let x = if line_num == pos.line {
+            pos.point.x
+        } else {
+            soft_break_x(self, &lm)
+        };
+        Rect::new(x, lm.y_offset, x, lm.y_offset + lm.height)
+    }
+
+    /// Returns the text position after the grapheme cluster that follows
+    /// `idx`, or the end of the text.
+    fn next_grapheme_offset(&self, idx: usize) -> usize {
+        let text = self.text();
+        let idx = idx.min(text.len());
+        GraphemeCursor::new(idx, text.len(), true)
+            .next_boundary(text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| text.len())
+    }
+
+    /// Returns the text position before the grapheme cluster that precedes
+    /// `idx`, or `0`.
+    fn prev_grapheme_offset(&self, idx: usize) -> usize {
+        let text = self.text();
+        let idx = idx.min(text.len());
+        GraphemeCursor::new(idx, text.len(), true)
+            .prev_boundary(text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(0)
+    }
+
+    /// Returns the text position at the end of the word after `idx`, skipping
+    /// any whitespace first; if `idx` is inside a word, this is the end of that
+    /// word.
+    ///
+    /// Words are found with the Unicode word boundary rules.
+    fn next_word_offset(&self, idx: usize) -> usize {
+        let text = self.text();
+        let idx = char_boundary_before(text, idx);
+        text[idx..]
+            .split_word_bound_indices()
+            .find(|(_, word)| !word.trim_start().is_empty())
+            .map(|(start, word)| idx + start + word.len())
+            .unwrap_or_else(|| text.len())
+    }
+
+    /// Returns the text position at the start of the word before `idx`,
+    /// skipping any whitespace first; if `idx` is inside a word, this is the
+    /// start of that word.
+    fn prev_word_offset(&self, idx: usize) -> usize {
+        let text = self.text();
+        let idx = char_boundary_before(text, idx);
+        text[..idx]
+            .split_word_bound_indices()
+            .rev()
+            .find(|(_, word)| !word.trim_start().is_empty())
+            .map(|(start, _)| start)
+            .unwrap_or(0)
+    }
+
+    /// Returns the text position at the start of the line with the caret at
+    /// `idx`.
+    fn line_start_offset(&self, idx: usize, affinity: Affinity) -> usize {
+        let line_num = caret_line(self, idx, affinity);
+        self.line_metric(line_num).unwrap_or_default().start_offset
+    }
+
+    /// Returns the caret position at the end of the line with the caret at
+    /// `idx`.
+    ///
+    /// This is before the line's newline, if it has one; at the end of a line
+    /// that was broken to fit the layout's width, the caret is upstream so
+    /// that it stays on the line.
+    fn line_end_offset(&self, idx: usize, affinity: Affinity) -> (usize, Affinity) {
+        let line_num = caret_line(self, idx, affinity);
+        let lm = self.line_metric(line_num).unwrap_or_default();
+        line_end(self, line_num, &lm)
+    }
+
+    /// Returns the caret position on the line above the caret at `idx`,
+    /// nearest to the horizontal position `x`.
+    ///
+    /// When moving the caret through several lines, `x` should be the
+    /// position the caret had before the first move, so that it doesn't
+    /// drift towards the start of the lines. Moving up from the first line
+    /// goes to the start of the text.
+    fn offset_above(&self, idx: usize, affinity: Affinity, x: f64) -> (usize, Affinity) {
+        match caret_line(self, idx, affinity) {
+            0 => (0, Affinity::Downstream),
+            line_num => caret_on_line(self, line_num - 1, x),
+        }
+    }
+
+    /// Returns the caret position on the line below the caret at `idx`,
+    /// nearest to the horizontal position `x`.
+    ///
+    /// See [`offset_above`] for how `x` should be chosen. Moving down from the
+    /// last line goes to the end of the text.
+    ///
+    /// [`offset_above`]: #method.offset_above
+    fn offset_below(&self, idx: usize, affinity: Affinity, x: f64) -> (usize, Affinity) {
+        let line_num = caret_line(self, idx, affinity) + 1;
+        if line_num >= self.line_count() {
+            (self.text().len(), Affinity::Downstream)
+        } else {
+            caret_on_line(self, line_num, x)
+        }
+    }
+}
+
+/// The closest char boundary in `text` at or before `idx`.
+fn char_boundary_before(text: &str, idx: usize) -> usize {
+    let mut idx = idx.min(text.len());
+    while !text.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+/// The line that a caret at `idx` is drawn on.
+fn caret_line<L: TextLayout + ?Sized>(layout: &L, idx: usize, affinity: Affinity) -> usize {
+    let idx = idx.min(layout.text().len());
+    let line_num = layout.hit_test_text_position(idx).line;
+    if affinity == Affinity::Upstream && line_num > 0 && is_soft_break(layout, line_num - 1) {
+        let prev = layout.line_metric(line_num - 1).unwrap_or_default();
+        if idx == prev.end_offset {
+            return line_num - 1;
+        }
+    }
+    line_num
+}
+
+/// The horizontal position of the end of a line that was broken to fit the
+/// layout's width.
+fn soft_break_x<L: TextLayout + ?Sized>(layout: &L, lm: &LineMetric) -> f64 {
+    // `hit_test_text_position` only finds leading edges, and the position
+    // after the last cluster is the start of the next line, so we measure
+    // the last cluster's glyphs instead.
+    let last = layout.prev_grapheme_offset(lm.end_offset);
+    let leading = layout.hit_test_text_position(last).point.x;
+    cluster_trailing_x(layout, last..lm.end_offset, leading).unwrap_or(leading)
+}
+
+/// The horizontal position of the trailing edge of the grapheme cluster
+/// `cluster`, whose leading edge is at `leading`, measured from its glyphs.
+///
+/// Returns `None` if the backend reports no glyphs for the cluster.
+pub(crate) fn cluster_trailing_x<L: TextLayout + ?Sized>(
+    layout: &L,
+    cluster: Range<usize>,
+    leading: f64,
+) -> Option<f64> {
+    let mut extent: Option<(f64, f64)> = None;
+    for run in layout.glyph_runs() {
+        if run.range.end <= cluster.start || run.range.start >= cluster.end {
+            continue;
+        }
+        for glyph in &run.glyphs {
+            if !cluster.contains(&glyph.cluster) {
+                continue;
+            }
+            let (x0, x1) = (glyph.position.x, glyph.position.x + glyph.advance);
+            extent = Some(match extent {
+                Some((min, max)) => (min.min(x0), max.max(x1)),
+                None => (x0, x1),
+            });
+        }
+    }
+    // the trailing edge is whichever side is away from the leading edge
+    extent.map(|(x0, x1)| {
+        if (x0 - leading).abs() < (x1 - leading).abs() {
+            x1
+        } else {
+            x0
+        }
+    })
+}
+
+/// The text of a line, sliced from the layout's text so that it doesn't
+/// depend on the backend's `line_text`.
+fn text_of_line<'a, L: TextLayout + ?Sized>(layout: &'a L, lm: &LineMetric) -> &'a str {
+    layout.text().get(lm.range()).unwrap_or_default()
+}
+
+/// Whether `line_num` ends because it was broken to fit the layout's width,
+/// rather than at a newline or the end of the text.
+fn is_soft_break<L: TextLayout + ?Sized>(layout: &L, line_num: usize) -> bool {
+    line_num + 1 < layout.line_count()
+        && layout.line_metric(line_num).map_or(false, |lm| {
+            util::trailing_nlf(text_of_line(layout, &lm)).is_none()
+        })
+}
+
+/// The caret position at the end of a line.
+fn line_end<L: TextLayout + ?Sized>(
+    layout: &L,
+    line_num: usize,
+    lm: &LineMetric,
+) -> (usize, Affinity) {
+    if is_soft_break(layout, line_num) {
+        return (lm.end_offset, Affinity::Upstream);
+    }
+    let newline = util::trailing_nlf(text_of_line(layout, lm)).unwrap_or(0);
+    (lm.end_offset - newline, Affinity::Downstream)
+}
+
+/// The caret position on `line_num` nearest to the horizontal position `x`.
+fn caret_on_line<L: TextLayout + ?Sized>(layout: &L, line_num: usize, x: f64) -> (usize, Affinity) {
+    let lm = layout.line_metric(line_num).unwrap_or_default();
+    let hit = layout.hit_test_point(Point::new(x, lm.y_offset + lm.baseline));
+    let (end, end_affinity) = line_end(layout, line_num, &lm);
+    if hit.idx >= end {
+        (end, end_affinity)
+    } else {
+        (hit.idx, hit.affinity)
+    }
+}
+
+/// A sequence of glyphs from a [`TextLayout`], drawn with a single font and
+/// color.
+///
+/// [`TextLayout`]: trait.TextLayout.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphRun {
+    /// The range of the layout's text that the glyphs were shaped from.
+    pub range: Range<usize>,
+    /// The family of the font the glyphs come from.
+    ///
+    /// This may differ from the requested family, if the backend fell back to
+    /// another font for characters the requested font doesn't have.
+    pub font: FontFamily,
+    /// The font size, after any [`BaselineShift`] scaling.
+    ///
+    /// [`BaselineShift`]: enum.BaselineShift.html
+    pub font_size: f64,
+    /// The weight of the font the glyphs come from.
+    pub weight: FontWeight,
+    /// The style of the font the glyphs come from.
+    pub style: FontStyle,
+    /// The color the glyphs are drawn in.
+    pub color: Color,
+    /// The glyphs, in visual order.
+    pub glyphs: Vec<Glyph>,
+}
+
+/// A single positioned glyph in a [`GlyphRun`].
+///
+/// [`GlyphRun`]: struct.GlyphRun.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glyph {
+    /// The glyph's id in its font.
+    pub id: u32,
+    /// The glyph's origin on the baseline, relative to the layout's origin.
+    pub position: Point,
+    /// How far the pen moves after drawing the glyph.
+    pub advance: f64,
+    /// The start of the cluster the glyph belongs to, as a utf-8 offset in the
+    /// layout's text.
+    ///
+    /// Several glyphs may share a cluster, and a cluster may cover several
+    /// characters, as with ligatures.
+    pub cluster: usize,
 }
 
 /// Metadata about each line in a text layout.
@@ -513,6 +1115,27 @@ impl LineMetric {
     }
 }
 
+/// Which side of a text position a caret is attached to.
+///
+/// Where a line was broken to fit the layout's width, the position at the
+/// break is both the end of one line and the start of the next. An upstream
+/// caret is drawn at the end of the first line, and a downstream caret at the
+/// start of the second. In mixed-direction text, a position between runs of
+/// different directions has a similar ambiguity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    /// The caret is attached to the text before the position.
+    Upstream,
+    /// The caret is attached to the text after the position.
+    Downstream,
+}
+
+impl Default for Affinity {
+    fn default() -> Self {
+        Affinity::Downstream
+    }
+}
+
 /// Result of hit testing a point in a [`TextLayout`].
 ///
 /// This type is returned by [`TextLayout::hit_test_point`].
@@ -531,6 +1154,14 @@ pub struct HitTestPoint {
     /// end of that line, and a click below the last line will resolve to a
     /// position in that line.
     pub is_inside: bool,
+    /// Which side of `idx` the point was on.
+    ///
+    /// This is [`Affinity::Upstream`] when the point is past the end of a
+    /// line that was broken to fit the layout's width, so that a caret placed
+    /// there stays at the end of that line.
+    ///
+    /// [`Affinity::Upstream`]: enum.Affinity.html#variant.Upstream
+    pub affinity: Affinity,
 }
 
 /// Result of hit testing a text position in a [`TextLayout`].
@@ -563,7 +1194,17 @@ impl HitTestPoint {
     /// Only for use by backends
     #[doc(hidden)]
     pub fn new(idx: usize, is_inside: bool) -> HitTestPoint {
-        HitTestPoint { idx, is_inside }
+        HitTestPoint::with_affinity(idx, is_inside, Affinity::Downstream)
+    }
+
+    /// Only for use by backends
+    #[doc(hidden)]
+    pub fn with_affinity(idx: usize, is_inside: bool, affinity: Affinity) -> HitTestPoint {
+        HitTestPoint {
+            idx,
+            is_inside,
+            affinity,
+        }
     }
 }
 
@@ -593,12 +1234,136 @@ impl From<FontStyle> for TextAttribute {
     }
 }
 
+impl From<FontFeature> for TextAttribute {
+    fn from(src: FontFeature) -> TextAttribute {
+        TextAttribute::FontFeatures(vec![src])
+    }
+}
+
+impl From<Vec<FontFeature>> for TextAttribute {
+    fn from(src: Vec<FontFeature>) -> TextAttribute {
+        TextAttribute::FontFeatures(src)
+    }
+}
+
+impl From<FontWidth> for TextAttribute {
+    fn from(src: FontWidth) -> TextAttribute {
+        TextAttribute::Width(src)
+    }
+}
+
+impl From<FontVariation> for TextAttribute {
+    fn from(src: FontVariation) -> TextAttribute {
+        TextAttribute::FontVariations(vec![src])
+    }
+}
+
+impl From<Vec<FontVariation>> for TextAttribute {
+    fn from(src: Vec<FontVariation>) -> TextAttribute {
+        TextAttribute::FontVariations(src)
+    }
+}
+
+impl From<UnderlineStyle> for TextAttribute {
+    fn from(src: UnderlineStyle) -> TextAttribute {
+        TextAttribute::UnderlineStyle(src)
+    }
+}
+
+impl From<Placeholder> for TextAttribute {
+    fn from(src: Placeholder) -> TextAttribute {
+        TextAttribute::Placeholder(src)
+    }
+}
+
+impl Placeholder {
+    /// A box of the given size, sitting on the baseline.
+    pub fn new(width: f64, height: f64) -> Self {
+        Placeholder {
+            width,
+            height,
+            baseline: height,
+        }
+    }
+
+    /// Set the distance from the top of the box down to the baseline; a
+    /// value less than the height makes the box hang below the baseline.
+    pub fn with_baseline(mut self, baseline: f64) -> Self {
+        self.baseline = baseline;
+        self
+    }
+}
+
+impl From<BaselineShift> for TextAttribute {
+    fn from(src: BaselineShift) -> TextAttribute {
+        TextAttribute::BaselineShift(src)
+    }
+}
+
+impl BaselineShift {
+    /// Superscript text at 65% of its normal size.
+    pub const SUPERSCRIPT: BaselineShift = BaselineShift::Superscript(0.65);
+    /// Subscript text at 65% of its normal size.
+    pub const SUBSCRIPT: BaselineShift = BaselineShift::Subscript(0.65);
+
+    /// How far the baseline is raised, in display points, for text with the
+    /// unscaled font size `font_size`.
+    pub fn offset(self, font_size: f64) -> f64 {
+        match self {
+            BaselineShift::None => 0.0,
+            BaselineShift::Superscript(_) => font_size / 3.0,
+            BaselineShift::Subscript(_) => -font_size / 5.0,
+            BaselineShift::Offset { offset, .. } => offset,
+        }
+    }
+
+    /// The factor the font size is multiplied by.
+    pub fn scale(self) -> f64 {
+        match self {
+            BaselineShift::None => 1.0,
+            BaselineShift::Superscript(scale)
+            | BaselineShift::Subscript(scale)
+            | BaselineShift::Offset { scale, .. } => scale,
+        }
+    }
+}
+
+impl Default for BaselineShift {
+    fn default() -> Self {
+        BaselineShift::None
+    }
+}
+
+impl Default for UnderlineStyle {
+    fn default() -> Self {
+        UnderlineStyle::Single
+    }
+}
+
 impl Default for TextAlignment {
     fn default() -> Self {
         TextAlignment::Start
     }
 }
 
+impl Default for TextDirection {
+    fn default() -> Self {
+        TextDirection::Auto
+    }
+}
+
+impl Default for TextOverflow {
+    fn default() -> Self {
+        TextOverflow::Clip
+    }
+}
+
+impl Default for LineHeight {
+    fn default() -> Self {
+        LineHeight::Normal
+    }
+}
+
 impl TextStorage for std::sync::Arc<str> {
     fn as_str(&self) -> &str {
         self
@@ -1,11 +1,20 @@
 //! Code useful for multiple backends
 
+use std::collections::BTreeSet;
 use std::ops::{Bound, Range, RangeBounds};
 
-use crate::kurbo::{Rect, Size};
-use crate::{Color, FontFamily, FontStyle, FontWeight, LineMetric, TextAttribute};
+use crate::kurbo::{
+    Affine, BezPath, ParamCurve, ParamCurveArclen, ParamCurveDeriv, PathSeg, Point, Rect, Size,
+    Vec2,
+};
+use crate::{
+    BaselineShift, Color, FontFamily, FontFeature, FontStyle, FontVariation, FontWeight, FontWidth,
+    LineMetric, Placeholder, TextAlignment, TextAttribute, TextDirection, TextLayout,
+    UnderlineStyle,
+};
 
 use unic_bidi::bidi_class::{BidiClass, BidiClassCategory};
+use unicode_segmentation::UnicodeSegmentation;
 
 /// The default point sie for text in piet.
 pub const DEFAULT_FONT_SIZE: f64 = 12.0;
@@ -149,15 +158,28 @@ fn compute_erf7(x: f64) -> f64 {
 }
 
 /// A type backends can use to represent the default values for a `TextLayout`
+#[derive(Clone)]
 #[non_exhaustive]
 pub struct LayoutDefaults {
     pub font: FontFamily,
     pub font_size: f64,
     pub weight: FontWeight,
     pub fg_color: Color,
+    pub background_color: Option<Color>,
     pub style: FontStyle,
     pub underline: bool,
     pub strikethrough: bool,
+    pub overline: bool,
+    pub underline_style: UnderlineStyle,
+    pub decoration_color: Option<Color>,
+    pub decoration_thickness: Option<f64>,
+    pub font_features: Vec<FontFeature>,
+    pub width: FontWidth,
+    pub font_variations: Vec<FontVariation>,
+    pub letter_spacing: f64,
+    pub word_spacing: f64,
+    pub baseline_shift: BaselineShift,
+    pub placeholder: Option<Placeholder>,
 }
 
 impl LayoutDefaults {
@@ -170,7 +192,34 @@ impl LayoutDefaults {
             TextAttribute::Style(style) => self.style = style,
             TextAttribute::Underline(flag) => self.underline = flag,
             TextAttribute::TextColor(color) => self.fg_color = color,
+            TextAttribute::BackgroundColor(color) => self.background_color = Some(color),
             TextAttribute::Strikethrough(flag) => self.strikethrough = flag,
+            TextAttribute::Overline(flag) => self.overline = flag,
+            TextAttribute::UnderlineStyle(style) => {
+                self.underline = true;
+                self.underline_style = style;
+            }
+            TextAttribute::DecorationColor(color) => self.decoration_color = Some(color),
+            TextAttribute::DecorationThickness(thickness) => {
+                self.decoration_thickness = Some(thickness)
+            }
+            TextAttribute::FontFeatures(features) => {
+                for feature in features {
+                    self.font_features.retain(|f| f.tag() != feature.tag());
+                    self.font_features.push(feature);
+                }
+            }
+            TextAttribute::Width(width) => self.width = width,
+            TextAttribute::FontVariations(variations) => {
+                for variation in variations {
+                    self.font_variations.retain(|v| v.tag() != variation.tag());
+                    self.font_variations.push(variation);
+                }
+            }
+            TextAttribute::LetterSpacing(spacing) => self.letter_spacing = spacing,
+            TextAttribute::WordSpacing(spacing) => self.word_spacing = spacing,
+            TextAttribute::BaselineShift(shift) => self.baseline_shift = shift,
+            TextAttribute::Placeholder(placeholder) => self.placeholder = Some(placeholder),
         }
     }
 }
@@ -182,13 +231,143 @@ impl Default for LayoutDefaults {
             font_size: DEFAULT_FONT_SIZE,
             weight: FontWeight::default(),
             fg_color: DEFAULT_TEXT_COLOR,
+            background_color: None,
             style: FontStyle::default(),
             underline: false,
             strikethrough: false,
+            overline: false,
+            underline_style: UnderlineStyle::default(),
+            decoration_color: None,
+            decoration_thickness: None,
+            font_features: Vec::new(),
+            width: FontWidth::default(),
+            font_variations: Vec::new(),
+            letter_spacing: 0.0,
+            word_spacing: 0.0,
+            baseline_shift: BaselineShift::None,
+            placeholder: None,
         }
     }
 }
 
+/// A range of text along with the attributes that apply to all of it.
+#[derive(Clone)]
+pub struct AttributeRun {
+    /// The utf-8 range of the run in the layout's text.
+    pub range: Range<usize>,
+    /// The fully resolved attributes for this run.
+    pub attrs: LayoutDefaults,
+}
+
+/// A type backends can use to collect range attributes while building a
+/// `TextLayout`, and to later resolve them into [`AttributeRun`]s.
+///
+/// This implements the semantics described on [`TextLayoutBuilder::range_attribute`]:
+/// attributes do not stack, so adding an attribute ends any earlier span
+/// of the same kind at the start of the new range.
+///
+/// [`AttributeRun`]: struct.AttributeRun.html
+/// [`TextLayoutBuilder::range_attribute`]: ../trait.TextLayoutBuilder.html#tymethod.range_attribute
+#[derive(Clone, Default)]
+pub struct RangeAttributes {
+    spans: Vec<(Range<usize>, TextAttribute)>,
+}
+
+impl RangeAttributes {
+    /// Add an attribute for the given range.
+    pub fn add(&mut self, range: Range<usize>, attr: TextAttribute) {
+        let kind = std::mem::discriminant(&attr);
+        for (prev_range, prev_attr) in self.spans.iter_mut() {
+            if std::mem::discriminant(prev_attr) == kind && prev_range.end > range.start {
+                prev_range.end = range.start.max(prev_range.start);
+            }
+        }
+        self.spans.push((range, attr));
+    }
+
+    /// Returns `true` if no range attributes have been added.
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Split the text into runs, each with a uniform set of attributes.
+    ///
+    /// Runs are returned in text order, and together cover `0..len`. If `len`
+    /// is `0`, a single empty run with the default attributes is returned.
+    ///
+    /// Each placeholder is a single run, with the attributes that cover all
+    /// of it; other attributes that start or end inside it are ignored there.
+    pub fn runs(&self, defaults: &LayoutDefaults, len: usize) -> Vec<AttributeRun> {
+        let mut boundaries = vec![0, len];
+        for (range, _) in &self.spans {
+            boundaries.push(range.start.min(len));
+            boundaries.push(range.end.min(len));
+        }
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        // a placeholder is a single box, so it isn't split; without a default
+        // placeholder, only the edges of placeholder ranges remain.
+        // Placeholder ranges never overlap, as adding one truncates the others.
+        let mut placeholders: Vec<Range<usize>> = self
+            .spans
+            .iter()
+            .filter(|(_, attr)| matches!(attr, TextAttribute::Placeholder(_)))
+            .map(|(range, _)| range.start.min(len)..range.end.min(len))
+            .filter(|range| range.start < range.end)
+            .collect();
+        placeholders.sort_by_key(|range| range.start);
+        boundaries.retain(|&idx| {
+            let next = placeholders.partition_point(|r| r.start < idx);
+            let prev = next.checked_sub(1).map(|i| &placeholders[i]);
+            if prev.map_or(false, |r| idx < r.end) {
+                return false;
+            }
+            defaults.placeholder.is_none()
+                || idx == 0
+                || idx == len
+                || prev.map_or(false, |r| r.end == idx)
+                || placeholders.get(next).map_or(false, |r| r.start == idx)
+        });
+
+        if len == 0 {
+            return vec![AttributeRun {
+                range: 0..0,
+                attrs: defaults.clone(),
+            }];
+        }
+
+        // sweep over the runs, keeping track of the spans that have started
+        // and not yet ended; they are applied in the order they were added.
+        let mut starts: Vec<usize> = (0..self.spans.len())
+            .filter(|&i| self.spans[i].0.start < self.spans[i].0.end)
+            .collect();
+        starts.sort_by_key(|&i| self.spans[i].0.start);
+        let mut starts = starts.into_iter().peekable();
+        let mut active = BTreeSet::new();
+
+        boundaries
+            .windows(2)
+            .map(|pair| {
+                let range = pair[0]..pair[1];
+                while let Some(i) = starts.next_if(|&i| self.spans[i].0.start <= range.start) {
+                    active.insert(i);
+                }
+                active.retain(|&i| self.spans[i].0.end > range.start);
+                let mut attrs = defaults.clone();
+                for &i in &active {
+                    let (span, attr) = &self.spans[i];
+                    // spans that end inside a placeholder don't cover it
+                    if span.end >= range.end {
+                        attrs.set(attr.clone());
+                    }
+                }
+                AttributeRun { range, attrs }
+            })
+            .collect()
+    }
+}
+
 /// If `x` is a single (non-alpha) channel of a premultiplied color and `a` is the alpha channel,
 /// returns the corresponding channel of the unpremultiplied version of the color.
 pub fn unpremul(x: u8, a: u8) -> u8 {
@@ -215,6 +394,186 @@ pub fn first_strong_rtl(text: &str) -> bool {
         .unwrap_or(false)
 }
 
+/// Returns `true` if `text` should be laid out right-to-left, given its base
+/// direction.
+///
+/// For [`TextDirection::Auto`], this is determined by [`first_strong_rtl`].
+///
+/// [`TextDirection::Auto`]: ../enum.TextDirection.html#variant.Auto
+/// [`first_strong_rtl`]: fn.first_strong_rtl.html
+pub fn is_rtl(text: &str, direction: TextDirection) -> bool {
+    match direction {
+        TextDirection::Auto => first_strong_rtl(text),
+        TextDirection::LeftToRight => false,
+        TextDirection::RightToLeft => true,
+    }
+}
+
+/// The accuracy used when measuring distances along a path.
+const ARCLEN_ACCURACY: f64 = 1e-3;
+
+/// A grapheme cluster of a text layout, positioned on a path by
+/// [`text_on_path`].
+///
+/// [`text_on_path`]: fn.text_on_path.html
+#[derive(Debug, Clone)]
+pub struct PathCluster {
+    /// The range of the cluster in the layout's text.
+    pub range: Range<usize>,
+    /// The bounds of the cluster in the layout's coordinate space, as tall as
+    /// its line.
+    pub bounds: Rect,
+    /// The transform that moves the cluster from its position in the layout
+    /// onto the path, rotated to the path's tangent.
+    pub transform: Affine,
+}
+
+/// Positions the grapheme clusters of the first line of `layout` along
+/// `path`.
+///
+/// The text's baseline follows the path, starting `start_offset` along it;
+/// `alignment` says whether the start, center or end of the text is placed at
+/// that offset, with [`TextAlignment::Justified`] treated as `Start`. Each
+/// cluster is rotated around its horizontal center, and clusters whose center
+/// falls off either end of the path are skipped. The clusters are returned
+/// in text order.
+///
+/// Every glyph of a [`GlyphRun`] belongs to the cluster that contains its
+/// `cluster` offset, and is placed on the path by that cluster's transform.
+///
+/// [`TextAlignment::Justified`]: ../enum.TextAlignment.html#variant.Justified
+/// [`GlyphRun`]: ../struct.GlyphRun.html
+pub fn text_on_path(
+    layout: &impl TextLayout,
+    path: &BezPath,
+    start_offset: f64,
+    alignment: TextAlignment,
+) -> Vec<PathCluster> {
+    let line = match layout.line_metric(0) {
+        Some(line) => line,
+        None => return Vec::new(),
+    };
+    let line_text = &layout.text()[line.start_offset..line.end_offset];
+    let line_text = line_text.trim_end();
+    if line_text.is_empty() {
+        return Vec::new();
+    }
+
+    // each cluster ends where the next one starts; the end of the last one
+    // may also be the start of the next line, so we measure its glyphs, or
+    // use the position after it if that is still on this line.
+    let mut edges: Vec<(usize, f64)> = line_text
+        .grapheme_indices(true)
+        .map(|(idx, _)| line.start_offset + idx)
+        .map(|idx| (idx, layout.hit_test_text_position(idx).point.x))
+        .collect();
+    let end = line.start_offset + line_text.len();
+    let (last, leading) = edges[edges.len() - 1];
+    let trailing =
+        crate::text::cluster_trailing_x(layout, last..end, leading).unwrap_or_else(|| {
+            let pos = layout.hit_test_text_position(end);
+            if pos.line == 0 {
+                pos.point.x
+            } else {
+                leading
+            }
+        });
+    edges.push((end, trailing));
+
+    let (y0, y1) = (line.y_offset, line.y_offset + line.height);
+    let clusters: Vec<(Range<usize>, Rect)> = edges
+        .windows(2)
+        .map(|pair| {
+            let ((start, x0), (end, x1)) = (pair[0], pair[1]);
+            (start..end, Rect::new(x0.min(x1), y0, x0.max(x1), y1))
+        })
+        .collect();
+
+    let x0 = clusters
+        .iter()
+        .map(|(_, b)| b.x0)
+        .fold(f64::INFINITY, f64::min);
+    let x1 = clusters
+        .iter()
+        .map(|(_, b)| b.x1)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let shift = match alignment {
+        TextAlignment::Center => start_offset - (x0 + x1) / 2.0,
+        TextAlignment::End => start_offset - x1,
+        TextAlignment::Start | TextAlignment::Justified => start_offset - x0,
+    };
+
+    let segments: Vec<_> = path
+        .segments()
+        .map(|seg| (seg, seg.arclen(ARCLEN_ACCURACY)))
+        .collect();
+    let baseline = line.y_offset + line.baseline;
+    clusters
+        .into_iter()
+        .filter_map(|(range, bounds)| {
+            let center = (bounds.x0 + bounds.x1) / 2.0;
+            let (point, tangent) = point_at_length(&segments, center + shift)?;
+            let normal = Vec2::new(-tangent.y, tangent.x);
+            let origin = point - tangent * center - normal * baseline;
+            let transform =
+                Affine::new([tangent.x, tangent.y, normal.x, normal.y, origin.x, origin.y]);
+            Some(PathCluster {
+                range,
+                bounds,
+                transform,
+            })
+        })
+        .collect()
+}
+
+/// The cluster among `clusters`, which are in text order, that contains the
+/// text position `idx`.
+pub(crate) fn path_cluster_at(clusters: &[PathCluster], idx: usize) -> Option<&PathCluster> {
+    clusters
+        .binary_search_by(|cluster| {
+            if cluster.range.end <= idx {
+                std::cmp::Ordering::Less
+            } else if cluster.range.start > idx {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .ok()
+        .map(|i| &clusters[i])
+}
+
+/// Returns the point `distance` along a path, and the unit tangent there.
+fn point_at_length(segments: &[(PathSeg, f64)], distance: f64) -> Option<(Point, Vec2)> {
+    if distance < 0.0 {
+        return None;
+    }
+    let mut remaining = distance;
+    for (seg, len) in segments {
+        if remaining > *len {
+            remaining -= len;
+            continue;
+        }
+        let t = seg.inv_arclen(remaining, ARCLEN_ACCURACY);
+        let deriv = match seg {
+            PathSeg::Line(line) => line.deriv().eval(t),
+            PathSeg::Quad(quad) => quad.deriv().eval(t),
+            PathSeg::Cubic(cubic) => cubic.deriv().eval(t),
+        }
+        .to_vec2();
+        // at a cusp the derivative vanishes, so we fall back to the chord
+        let tangent = if deriv.hypot() > 0.0 {
+            deriv
+        } else {
+            seg.end() - seg.start()
+        };
+        if tangent.hypot() > 0.0 {
+            return Some((seg.eval(t), tangent / tangent.hypot()));
+        }
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -237,4 +596,117 @@ mod tests {
 
         assert_eq!(count_until_utf16("", 0), None);
     }
+
+    #[test]
+    fn range_attributes_do_not_stack() {
+        let mut attrs = RangeAttributes::default();
+        attrs.add(0..100, TextAttribute::Weight(FontWeight::BOLD));
+        attrs.add(20..50, TextAttribute::Weight(FontWeight::THIN));
+        attrs.add(30..40, TextAttribute::Underline(true));
+
+        let runs = attrs.runs(&LayoutDefaults::default(), 120);
+        let ranges: Vec<_> = runs.iter().map(|run| run.range.clone()).collect();
+        assert_eq!(
+            ranges,
+            vec![0..20, 20..30, 30..40, 40..50, 50..100, 100..120]
+        );
+
+        assert_eq!(runs[0].attrs.weight, FontWeight::BOLD);
+        assert_eq!(runs[1].attrs.weight, FontWeight::THIN);
+        assert!(runs[2].attrs.underline);
+        assert!(!runs[3].attrs.underline);
+        assert_eq!(runs[4].attrs.weight, FontWeight::REGULAR);
+        assert_eq!(runs[5].attrs.weight, FontWeight::REGULAR);
+    }
+
+    #[test]
+    fn range_attributes_out_of_order() {
+        let mut attrs = RangeAttributes::default();
+        attrs.add(6..10, TextAttribute::Underline(true));
+        attrs.add(0..8, TextAttribute::Weight(FontWeight::BOLD));
+
+        let runs = attrs.runs(&LayoutDefaults::default(), 10);
+        let ranges: Vec<_> = runs.iter().map(|run| run.range.clone()).collect();
+        assert_eq!(ranges, vec![0..6, 6..8, 8..10]);
+        assert!(!runs[0].attrs.underline);
+        assert!(runs[1].attrs.underline);
+        assert_eq!(runs[1].attrs.weight, FontWeight::BOLD);
+        assert_eq!(runs[2].attrs.weight, FontWeight::REGULAR);
+    }
+
+    #[test]
+    fn font_features_combine() {
+        let mut defaults = LayoutDefaults::default();
+        defaults.set(vec![
+            FontFeature::new(b"tnum", 1),
+            FontFeature::new(b"liga", 1),
+        ]);
+        let mut attrs = RangeAttributes::default();
+        attrs.add(5..10, FontFeature::new(b"liga", 0).into());
+
+        let runs = attrs.runs(&defaults, 10);
+        assert_eq!(runs[0].attrs.font_features, defaults.font_features);
+        assert_eq!(
+            runs[1].attrs.font_features,
+            vec![FontFeature::new(b"tnum", 1), FontFeature::new(b"liga", 0)]
+        );
+    }
+
+    #[test]
+    fn range_attributes_empty_text() {
+        let runs = RangeAttributes::default().runs(&LayoutDefaults::default(), 0);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].range, 0..0);
+    }
+
+    #[test]
+    fn placeholders_are_not_split() {
+        let placeholder = Placeholder {
+            width: 10.0,
+            height: 10.0,
+            baseline: 8.0,
+        };
+        let mut attrs = RangeAttributes::default();
+        attrs.add(2..8, placeholder.into());
+        attrs.add(0..5, TextAttribute::TextColor(Color::WHITE));
+        attrs.add(4..6, TextAttribute::Underline(true));
+
+        let runs = attrs.runs(&LayoutDefaults::default(), 10);
+        let ranges: Vec<_> = runs.iter().map(|run| run.range.clone()).collect();
+        assert_eq!(ranges, vec![0..2, 2..8, 8..10]);
+        assert_eq!(runs[1].attrs.placeholder, Some(placeholder));
+        assert!(!runs[1].attrs.underline);
+
+        // with a default placeholder, the whole text is one box
+        let mut defaults = LayoutDefaults::default();
+        defaults.set(placeholder);
+        let mut attrs = RangeAttributes::default();
+        attrs.add(0..5, TextAttribute::TextColor(Color::WHITE));
+        let runs = attrs.runs(&defaults, 10);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].range, 0..10);
+    }
+
+    #[test]
+    fn points_along_path() {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((10.0, 0.0));
+        path.line_to((10.0, 20.0));
+        let segments: Vec<_> = path
+            .segments()
+            .map(|seg| (seg, seg.arclen(ARCLEN_ACCURACY)))
+            .collect();
+
+        let (point, tangent) = point_at_length(&segments, 4.0).unwrap();
+        assert!((point - Point::new(4.0, 0.0)).hypot() < 1e-6);
+        assert!((tangent - Vec2::new(1.0, 0.0)).hypot() < 1e-6);
+
+        let (point, tangent) = point_at_length(&segments, 15.0).unwrap();
+        assert!((point - Point::new(10.0, 5.0)).hypot() < 1e-6);
+        assert!((tangent - Vec2::new(0.0, 1.0)).hypot() < 1e-6);
+
+        assert!(point_at_length(&segments, -1.0).is_none());
+        assert!(point_at_length(&segments, 31.0).is_none());
+    }
 }